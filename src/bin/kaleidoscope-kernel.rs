@@ -0,0 +1,123 @@
+//! The kernel needs the `jupyter` feature; without it this binary only says so.
+
+#![cfg_attr(not(feature = "jupyter"), allow(dead_code, unused_imports))]
+
+extern crate docopt;
+extern crate rustc_serialize;
+extern crate kaleidoscope;
+
+use docopt::Docopt;
+use kaleidoscope::driver;
+use kaleidoscope::guard::Limits;
+#[cfg(feature = "jupyter")]
+use kaleidoscope::kernel;
+use kaleidoscope::passes::PassOptions;
+use kaleidoscope::prelude;
+
+use rustc_serialize::json::Json;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+const USAGE: &'static str = "
+Jupyter kernel for Kaleidoscope.
+
+Usage:
+  kaleidoscope-kernel [-O LEVEL] [--stack-size=<mb>] [--timeout=<ms>] [--no-prelude] <connection-file>
+  kaleidoscope-kernel install [--prefix=<dir>]
+
+Options:
+  -O LEVEL           Optimization level from 0 to 3 [default: 1].
+  --stack-size=<mb>  Stack size in megabytes for running cells [default: 8].
+  --timeout=<ms>     Interrupt a cell that runs longer than this many milliseconds, 0 for
+                     no limit [default: 0].
+  --no-prelude       Start without the standard prelude.
+  --prefix=<dir>     Jupyter data directory to install the kernel spec into instead of
+                     ~/.local/share/jupyter.
+";
+
+#[allow(non_snake_case)]
+#[derive(Debug, RustcDecodable)]
+struct Args {
+  cmd_install: bool,
+  arg_connection_file: String,
+  flag_O: u32,
+  flag_stack_size: usize,
+  flag_timeout: u32,
+  flag_no_prelude: bool,
+  flag_prefix: Option<String>
+}
+
+#[cfg(not(feature = "jupyter"))]
+fn main() {
+  println!("Error: built without the jupyter feature, rebuild with --features jupyter");
+  std::process::exit(1);
+}
+
+#[cfg(feature = "jupyter")]
+fn main() {
+  let args: Args = Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|e| e.exit());
+
+  if args.cmd_install {
+    match install(args.flag_prefix.as_ref()) {
+      Ok(path) => println!("Installed kernel spec in {}", path.display()),
+      Err(message) => {
+        println!("Error: {}", message);
+        std::process::exit(1);
+      }
+    }
+    return
+  }
+
+  let options = driver::Options {
+    passes: PassOptions::with_opt_level(args.flag_O).unwrap_or_else(|message| {
+      println!("Error: {}", message);
+      std::process::exit(1);
+    }),
+    limits: Limits {
+      stack_size: args.flag_stack_size * 1024 * 1024,
+      timeout: args.flag_timeout
+    },
+    prelude: if args.flag_no_prelude { None } else { Some(prelude::STANDARD.to_string()) },
+    quiet: true,
+    // Jupyter interrupts cells
+    interactive: true,
+    ..driver::Options::default()
+  };
+
+  let result = kernel::ConnectionInfo::read(&args.arg_connection_file)
+    .and_then(|connection| kernel::run(&connection, &options));
+  if let Err(message) = result {
+    println!("Error: {}", message);
+    std::process::exit(1);
+  }
+}
+
+// Writes kernel.json, which tells Jupyter how to start this kernel.
+fn install(prefix: Option<&String>) -> Result<PathBuf, String> {
+  let prefix = match prefix {
+    Some(prefix) => PathBuf::from(prefix),
+    None => try!(env::home_dir().ok_or("no home directory, give --prefix".to_string()))
+      .join(".local/share/jupyter")
+  };
+  let directory = prefix.join("kernels").join("kaleidoscope");
+  try!(fs::create_dir_all(&directory).map_err(|e| format!("{}: {}", directory.display(), e)));
+
+  let executable = try!(env::current_exe().map_err(|e| format!("{}", e)));
+  let mut spec = BTreeMap::new();
+  spec.insert("argv".to_string(), Json::Array(vec![
+    Json::String(executable.to_string_lossy().into_owned()),
+    Json::String("{connection_file}".to_string())
+  ]));
+  spec.insert("display_name".to_string(), Json::String("Kaleidoscope".to_string()));
+  spec.insert("language".to_string(), Json::String("kaleidoscope".to_string()));
+
+  let path = directory.join("kernel.json");
+  try!(File::create(&path).and_then(|mut f| writeln!(f, "{}", Json::Object(spec).pretty()))
+       .map_err(|e| format!("{}: {}", path.display(), e)));
+  Ok(directory)
+}
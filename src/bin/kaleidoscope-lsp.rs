@@ -0,0 +1,17 @@
+extern crate kaleidoscope;
+
+use std::io;
+use std::io::Write;
+
+use kaleidoscope::lsp;
+
+// Speaks the Language Server Protocol on stdin and stdout; errors go to stderr, which editors
+// show in their logs.
+fn main() {
+  let stdin = io::stdin();
+  let stdout = io::stdout();
+  if let Err(message) = lsp::serve(&mut stdin.lock(), &mut stdout.lock()) {
+    let _ = writeln!(io::stderr(), "Error: {}", message);
+    std::process::exit(1);
+  }
+}
@@ -1,11 +1,27 @@
+use std::collections::HashMap;
 use std::iter;
+use std::mem;
+use std::ptr;
 use parser;
 use context::Context;
+use module;
 use module::ModuleProvider;
+use timing::Timer;
+use guard;
+use builtins;
 
 use llvm_sys::LLVMRealPredicate::{LLVMRealOLT, LLVMRealONE};
 use llvm_sys::analysis::LLVMVerifierFailureAction::LLVMAbortProcessAction;
-use llvm_sys::core::LLVMDeleteFunction;
+use std::ffi::CString;
+
+use llvm_sys::LLVMLinkage::LLVMAvailableExternallyLinkage;
+use llvm_sys::core::{LLVMCountBasicBlocks, LLVMDeleteBasicBlock, LLVMDeleteFunction, LLVMGetFirstBasicBlock, LLVMSetLinkage};
+use llvm_sys::core::LLVMReplaceAllUsesWith;
+use llvm_sys::core::{LLVMAddGlobal, LLVMBuildCall, LLVMBuildLoad, LLVMGetNamedGlobal, LLVMTypeOf};
+use llvm_sys::core::LLVMSetTailCall;
+use llvm_sys::core::{LLVMAddFunction, LLVMBuildICmp, LLVMBuildUnreachable, LLVMConstInt, LLVMFunctionType};
+use llvm_sys::core::{LLVMGetNamedFunction, LLVMInt64TypeInContext, LLVMSetVolatile, LLVMVoidTypeInContext};
+use llvm_sys::LLVMIntPredicate::LLVMIntNE;
 use llvm_sys::prelude::LLVMValueRef;
 
 use iron_llvm::{LLVMRef, LLVMRefCtor};
@@ -19,6 +35,10 @@ use iron_llvm::core::instruction::{PHINode, PHINodeRef};
 pub type Runnable = bool;
 pub type IRBuildingResult = Result<(LLVMValueRef, Runnable), String>;
 
+// Functions whose bodies have at most this many AST nodes are copied into later modules
+// when cross-module inlining is enabled.
+const MAX_IMPORTED_SIZE: usize = 32;
+
 fn error(message : &str) -> IRBuildingResult {
   Err(message.to_string())
 }
@@ -30,8 +50,8 @@ pub trait IRBuilder {
 impl IRBuilder for parser::ParsingResult {
   fn codegen(&self, context: &mut Context, module_provider: &mut ModuleProvider) -> IRBuildingResult {
     match self {
-      &Ok((ref ast, _)) => ast.codegen(context, module_provider),
-      &Err(ref message) => Err(message.clone())
+      &(ref ast, _, ref errors) if errors.is_empty() => ast.codegen(context, module_provider),
+      &(_, _, ref errors) => Err(errors[0].clone())
     }
   }
 }
@@ -57,40 +77,65 @@ impl IRBuilder for parser::ASTNode {
 
 impl IRBuilder for parser::Prototype {
   fn codegen(&self, context: &mut Context, module_provider: &mut ModuleProvider) -> IRBuildingResult {
-    let function = match module_provider.get_function(&self.name) {
-      Some((prev_def, redef)) => {
-        if prev_def.count_params() as usize != self.args.len() {
-          return error("redefinition of function with different number of args")
-        }
+    prototype_codegen(self, context, module_provider, false)
+  }
+}
 
-        if redef {
-          return error("redefinition of function")
-        }
+// Gives the function of the prototype in the current module, declaring it if needed. With
+// `allow_redefinition`, a function that is already defined can be defined again: see
+// `Function::codegen` for how the body is replaced.
+fn prototype_codegen(prototype: &parser::Prototype, context: &mut Context, module_provider: &mut ModuleProvider,
+                     allow_redefinition: bool) -> IRBuildingResult {
+  let function = match module_provider.get_function(&prototype.name) {
+    Some((prev_def, redef)) => {
+      if prev_def.count_params() as usize != prototype.args.len() {
+        return error("redefinition of function with different number of args")
+      }
 
-        prev_def
-      },
-      None => {
-        let mut param_types = iter::repeat(context.ty.to_ref()).take(self.args.len()).collect::<Vec<_>>();
-        let fty = FunctionTypeRef::get(&context.ty, param_types.as_mut_slice(), false);
-        FunctionRef::new(&mut module_provider.get_module(), &self.name, &fty)
+      if redef && !allow_redefinition {
+        return error("redefinition of function")
       }
-    };
 
-    for (param, arg) in function.params_iter().zip(&self.args) {
-      param.set_name(arg);
-    }
+      prev_def
+    },
+    None => declare_function(&prototype.name, prototype.args.len(), context, module_provider)
+  };
 
-    Ok((function.to_ref(), false))
+  for (param, arg) in function.params_iter().zip(&prototype.args) {
+    param.set_name(arg);
   }
+
+  Ok((function.to_ref(), false))
+}
+
+fn declare_function(name: &str, args: usize, context: &mut Context, module_provider: &mut ModuleProvider) -> FunctionRef {
+  let mut param_types = iter::repeat(context.ty.to_ref()).take(args).collect::<Vec<_>>();
+  let fty = FunctionTypeRef::get(&context.ty, param_types.as_mut_slice(), false);
+  FunctionRef::new(&mut module_provider.get_module(), name, &fty)
 }
 
 impl IRBuilder for parser::Function {
   fn codegen(&self, context: &mut Context, module_provider: &mut ModuleProvider) -> IRBuildingResult {
+    let timer = Timer::start();
     // Since there are no global variables, it's ok to remove all variables which are defined before.
     context.named_values.clear();
 
-    let (function, _) = try!(self.prototype.codegen(context, module_provider));
-    let mut function = unsafe { FunctionRef::from_ref(function) };
+    let allow_redefinition = context.hot_reload;
+    let declared = module_provider.get_function(&self.prototype.name).is_some();
+    let (declaration, _) = try!(prototype_codegen(&self.prototype, context, module_provider, allow_redefinition));
+
+    // The body is generated into a function of its own, which takes the place of the declaration,
+    // or of an earlier definition, only once it is complete. A failed definition leaves
+    // everything as it was.
+    let mut function = declare_function("", self.prototype.args.len(), context, module_provider);
+    for (param, arg) in function.params_iter().zip(&self.prototype.args) {
+      param.set_name(arg);
+    }
+
+    if let Some(ref mut debug_info) = context.debug_info {
+      debug_info.start_function(&self.prototype, function.to_ref(), module_provider.get_module().to_ref(),
+                                context.builder.to_ref());
+    }
 
     let mut bb = function.append_basic_block_in_context(&mut context.context, "entry");
     context.builder.position_at_end(&mut bb);
@@ -99,135 +144,194 @@ impl IRBuilder for parser::Function {
       context.named_values.insert(arg.clone(), param.to_ref());
     }
 
-    let body = match self.expression.codegen(context, module_provider) {
+    // recursion in tail position becomes a loop, which has to be interruptible too
+    build_interrupt_check(context, module_provider);
+
+    context.tail_position = true;
+    context.function_name = self.prototype.name.clone();
+    let body = self.expression.codegen(context, module_provider);
+    context.function_name.clear();
+    let body = match body {
       Ok((value, _)) => value,
       Err(message) => {
-        unsafe { LLVMDeleteFunction(function.to_ref()) };
+        finish_debug_info(context);
+        unsafe {
+          LLVMDeleteFunction(function.to_ref());
+          if !declared {
+            LLVMDeleteFunction(declaration);
+          }
+        }
         return Err(message);
       }
     };
 
     context.builder.build_ret(&body);
+    finish_debug_info(context);
+
+    // calls of the declaration, recursive ones included, now go to the new body
+    unsafe {
+      LLVMReplaceAllUsesWith(declaration, function.to_ref());
+      LLVMDeleteFunction(declaration);
+    }
+    function.set_name(&self.prototype.name);
+    if context.hot_reload && self.prototype.name.as_str() != "" {
+      context.reloadable_functions.insert(self.prototype.name.clone());
+    }
 
     function.verify(LLVMAbortProcessAction);
+    context.stats.codegen_ms += timer.elapsed_ms();
+
+    let timer = Timer::start();
+    context.stats.instructions_before += module::count_instructions(function.to_ref());
     module_provider.get_pass_manager().run(&mut function);
+    context.stats.instructions_after += module::count_instructions(function.to_ref());
+    context.stats.optimize_ms += timer.elapsed_ms();
 
     context.named_values.clear();
+
+    if context.inline_across_modules && !context.hot_reload && self.prototype.name.as_str() != ""
+      && expression_size(&self.expression) <= MAX_IMPORTED_SIZE {
+      context.definitions.insert(self.prototype.name.clone(), self.clone());
+    }
+
     Ok((function.to_ref(), self.prototype.name.as_str() == ""))
   }
 }
 
 impl IRBuilder for parser::Expression {
   fn codegen(&self, context: &mut Context, module_provider: &mut ModuleProvider) -> IRBuildingResult {
-    match self {
-      &parser::LiteralExpr(ref value) => {
-        Ok((RealConstRef::get(&context.ty, *value).to_ref(), false))
-      },
-
-      &parser::VariableExpr(ref name) => {
-        match context.named_values.get(name) {
-          Some(value) => {
-            Ok((*value, false))
-          },
-          None => error("unknown variable name")
-        }
-      },
-
-      &parser::BinaryExpr(ref name, ref lhs, ref rhs) => {
-        let (lhs_value, _) = try!(lhs.codegen(context, module_provider));
-        let (rhs_value, _) = try!(rhs.codegen(context, module_provider));
-
-        match name.as_str() {
-          "+" => Ok((context.builder.build_fadd(lhs_value, rhs_value, "addtmp"), false)),
-          "-" => Ok((context.builder.build_fsub(lhs_value, rhs_value, "subtmp"), false)),
-          "*" => Ok((context.builder.build_fmul(lhs_value, rhs_value, "multmp"), false)),
-          "<" => {
-            let cmp = context.builder.build_fcmp(LLVMRealOLT, lhs_value, rhs_value, "cmptmp");
-
-            // convert boolean to double 0.0 or 1.0
-            Ok((context.builder.build_ui_to_fp(cmp, context.ty.to_ref(), "booltmp"), false))
-          },
-          op => {
-            let name = "binary".to_string() + op;
-            let (function, _) = match module_provider.get_function(&name) {
-              Some(function) => function,
-              None => return error("binary operator not found")
-            };
-
-            let mut args_value = vec![lhs_value, rhs_value];
-
-            Ok((context.builder.build_call(function.to_ref(),
-                                          args_value.as_mut_slice(),
-                                          "binop"),
-                                        false))
-          }
-          // _ => error("invalid binary operator")
-        }
-      },
+    // the instructions of the expression itself get its location, those of subexpressions theirs
+    let location = context.debug_info.as_ref().map(|debug_info| debug_info.enter(self, context.builder.to_ref()));
+    let result = expression_codegen(self, context, module_provider);
+    if let (Some(debug_info), Some(location)) = (context.debug_info.as_ref(), location) {
+      debug_info.leave(context.builder.to_ref(), location);
+    }
+    result
+  }
+}
 
-      &parser::UnaryExpr(ref name, ref operand) => {
-        return unary_codegen(self, context, module_provider);
+fn expression_codegen(expr: &parser::Expression, context: &mut Context, module_provider: &mut ModuleProvider) -> IRBuildingResult {
+  // subexpressions are not in tail position unless stated otherwise
+  let tail = mem::replace(&mut context.tail_position, false);
+
+  match expr {
+    &parser::LiteralExpr(ref value) => {
+      Ok((RealConstRef::get(&context.ty, *value).to_ref(), false))
+    },
+
+    &parser::VariableExpr(ref name) => {
+      match context.named_values.get(name) {
+        Some(value) => {
+          Ok((*value, false))
+        },
+        None => error("unknown variable name")
       }
+    },
+
+    &parser::BinaryExpr(ref name, ref lhs, ref rhs) => {
+      let (lhs_value, _) = try!(lhs.codegen(context, module_provider));
+      let (rhs_value, _) = try!(rhs.codegen(context, module_provider));
+
+      match name.as_str() {
+        "+" => Ok((context.builder.build_fadd(lhs_value, rhs_value, "addtmp"), false)),
+        "-" => Ok((context.builder.build_fsub(lhs_value, rhs_value, "subtmp"), false)),
+        "*" => Ok((context.builder.build_fmul(lhs_value, rhs_value, "multmp"), false)),
+        "<" => {
+          let cmp = context.builder.build_fcmp(LLVMRealOLT, lhs_value, rhs_value, "cmptmp");
+
+          // convert boolean to double 0.0 or 1.0
+          Ok((context.builder.build_ui_to_fp(cmp, context.ty.to_ref(), "booltmp"), false))
+        },
+        op => {
+          let name = "binary".to_string() + op;
+          let function = match try!(get_callee(&name, context, module_provider)) {
+            Some(function) => function,
+            None => return error("binary operator not found")
+          };
+
+          let mut args_value = vec![lhs_value, rhs_value];
+
+          Ok((build_call(&name, &function, args_value.as_mut_slice(), "binop", tail, context, module_provider),
+              false))
+        }
+        // _ => error("invalid binary operator")
+      }
+    },
 
-      &parser::ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
-        let (cond_value, _) = try!(cond_expr.codegen(context, module_provider));
-        let zero = RealConstRef::get(&context.ty, 0.0);
-        let ifcond = context.builder.build_fcmp(LLVMRealONE, cond_value, zero.to_ref(), "ifcond");
-
-        let block = context.builder.get_insert_block();
-        let mut function = block.get_parent();
-        let mut then_block = function.append_basic_block_in_context(&mut context.context, "then");
-        let mut else_block = function.append_basic_block_in_context(&mut context.context, "else");
-        let mut merge_block = function.append_basic_block_in_context(&mut context.context, "ifcont");
-        context.builder.build_cond_br(ifcond, &then_block, &else_block);
-
-        context.builder.position_at_end(&mut then_block);
-        let (then_value, _) = try!(then_expr.codegen(context, module_provider));
-        context.builder.build_br(&merge_block);
-        let then_end_block = context.builder.get_insert_block();
-
-        context.builder.position_at_end(&mut else_block);
-        let (else_value, _) = try!(else_expr.codegen(context, module_provider));
-        context.builder.build_br(&merge_block);
-        let else_end_block = context.builder.get_insert_block();
-
-        context.builder.position_at_end(&mut merge_block);
-        // TODO: fix builder methods, so they generate the right instruction
-        let mut phi = unsafe {
-          PHINodeRef::from_ref(context.builder.build_phi(context.ty.to_ref(), "ifphi"))
-        };
-        phi.add_incoming(vec![then_value].as_mut_slice(), vec![then_end_block].as_mut_slice());
-        phi.add_incoming(vec![else_value].as_mut_slice(), vec![else_end_block].as_mut_slice());
-
-        Ok((phi.to_ref(), false))
-      },
-
-      &parser::LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
-        return loop_codegen(self, context, module_provider);
-      },
-
-      &parser::CallExpr(ref name, ref args) => {
-        let (function, _) = match module_provider.get_function(name) {
+    &parser::UnaryExpr(ref name, ref operand) => {
+      return unary_codegen(expr, tail, context, module_provider);
+    }
+
+    &parser::ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+      let (cond_value, _) = try!(cond_expr.codegen(context, module_provider));
+      let zero = RealConstRef::get(&context.ty, 0.0);
+      let ifcond = context.builder.build_fcmp(LLVMRealONE, cond_value, zero.to_ref(), "ifcond");
+
+      let block = context.builder.get_insert_block();
+      let mut function = block.get_parent();
+      let mut then_block = function.append_basic_block_in_context(&mut context.context, "then");
+      let mut else_block = function.append_basic_block_in_context(&mut context.context, "else");
+      let mut merge_block = function.append_basic_block_in_context(&mut context.context, "ifcont");
+      context.builder.build_cond_br(ifcond, &then_block, &else_block);
+
+      context.builder.position_at_end(&mut then_block);
+      context.tail_position = tail;
+      let (then_value, _) = try!(then_expr.codegen(context, module_provider));
+      context.builder.build_br(&merge_block);
+      let then_end_block = context.builder.get_insert_block();
+
+      context.builder.position_at_end(&mut else_block);
+      context.tail_position = tail;
+      let (else_value, _) = try!(else_expr.codegen(context, module_provider));
+      context.builder.build_br(&merge_block);
+      let else_end_block = context.builder.get_insert_block();
+
+      context.builder.position_at_end(&mut merge_block);
+      // TODO: fix builder methods, so they generate the right instruction
+      let mut phi = unsafe {
+        PHINodeRef::from_ref(context.builder.build_phi(context.ty.to_ref(), "ifphi"))
+      };
+      phi.add_incoming(vec![then_value].as_mut_slice(), vec![then_end_block].as_mut_slice());
+      phi.add_incoming(vec![else_value].as_mut_slice(), vec![else_end_block].as_mut_slice());
+
+      Ok((phi.to_ref(), false))
+    },
+
+    &parser::LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+      return loop_codegen(expr, context, module_provider);
+    },
+
+    &parser::CallExpr(ref name, ref args) => {
+      // functions defined or declared by the user take precedence over builtins
+      let function = match try!(get_callee(name, context, module_provider)) {
+        Some(function) => function,
+        None => match builtins::declare(name, context, module_provider) {
           Some(function) => function,
           None => return error("unknown functino referenced")
-        };
-
-        if function.count_params() as usize != args.len() {
-          return error("incorrect number of arguments passed")
         }
+      };
 
-        let mut args_value = Vec::new();
-        for arg in args.iter() {
-          let (arg_value, _) = try!(arg.codegen(context, module_provider));
-          args_value.push(arg_value);
-        }
+      if function.count_params() as usize != args.len() {
+        return error("incorrect number of arguments passed")
+      }
 
-        Ok((context.builder.build_call(function.to_ref(), args_value.as_mut_slice(), "calltmp"), false))
+      let mut args_value = Vec::new();
+      for arg in args.iter() {
+        let (arg_value, _) = try!(arg.codegen(context, module_provider));
+        args_value.push(arg_value);
       }
+
+      Ok((build_call(name, &function, args_value.as_mut_slice(), "calltmp", tail, context, module_provider), false))
     }
   }
 }
 
+fn finish_debug_info(context: &mut Context) {
+  if let Some(ref mut debug_info) = context.debug_info {
+    debug_info.finish_function(context.builder.to_ref());
+  }
+}
+
 fn loop_codegen(expr: &parser::Expression, context: &mut Context, module_provider: &mut ModuleProvider) -> IRBuildingResult {
   if let &parser::LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} = expr {
     let (start_value, _) = try!(start_expr.codegen(context, module_provider));
@@ -260,6 +364,7 @@ fn loop_codegen(expr: &parser::Expression, context: &mut Context, module_provide
 
     let (step_value, _) = try!(step_expr.codegen(context, module_provider));
     let next_value = context.builder.build_fadd(variable.to_ref(), step_value, "nextvar");
+    build_interrupt_check(context, module_provider);
     let loop_end_block = context.builder.get_insert_block();
     variable.add_incoming(vec![next_value].as_mut_slice(), vec![loop_end_block].as_mut_slice());
 
@@ -278,19 +383,173 @@ fn loop_codegen(expr: &parser::Expression, context: &mut Context, module_provide
   }
 }
 
-fn unary_codegen(expr: &parser::Expression, context: &mut Context, module_provider: &mut ModuleProvider) -> IRBuildingResult {
+fn unary_codegen(expr: &parser::Expression, tail: bool, context: &mut Context, module_provider: &mut ModuleProvider) -> IRBuildingResult {
   if let &parser::UnaryExpr(ref name, ref operand) = expr {
     let (operand, _) = try!(operand.codegen(context, module_provider));
     let name = "unary".to_string() + name;
-    let (function, _) = match module_provider.get_function(name.as_str()) {
+    let function = match try!(get_callee(name.as_str(), context, module_provider)) {
       Some(f) => f,
       None => return error("unary operator not found")
     };
 
     let mut args = vec![operand];
 
-    Ok((context.builder.build_call(function.to_ref(), args.as_mut_slice(), "unop"), true))
+    Ok((build_call(&name, &function, args.as_mut_slice(), "unop", tail, context, module_provider), true))
   } else {
     error("Expected unary expression")
   }
 }
+
+// Looks up the function to call. With cross-module inlining enabled, a small function that was
+// compiled into an earlier module gets its body generated again in the current module with
+// `available_externally` linkage. The inliner can then see it, while the body itself is never
+// emitted and calls that are not inlined still go to the code compiled before.
+fn get_callee(name: &str, context: &mut Context, module_provider: &mut ModuleProvider) -> Result<Option<FunctionRef>, String> {
+  let mut function = match module_provider.get_function(name) {
+    Some((function, _)) => function,
+    None => return Ok(None)
+  };
+
+  // with hot reload, calls go through the slots and an imported body would never be inlined
+  if !context.inline_across_modules || context.hot_reload || function.count_basic_blocks() > 0 {
+    return Ok(Some(function))
+  }
+  let definition = match context.definitions.get(name) {
+    Some(definition) => definition.clone(),
+    None => return Ok(Some(function))
+  };
+
+  let mut insert_block = context.builder.get_insert_block();
+  // the body is not where the call is
+  let location = context.debug_info.as_ref().map(|debug_info| debug_info.suspend(context.builder.to_ref()));
+  let named_values = mem::replace(&mut context.named_values, HashMap::new());
+  let tail_position = mem::replace(&mut context.tail_position, true);
+
+  // the entry block is appended first, so that recursive calls find the body and stop importing
+  let mut bb = function.append_basic_block_in_context(&mut context.context, "entry");
+  context.builder.position_at_end(&mut bb);
+  for (param, arg) in function.params_iter().zip(&definition.prototype.args) {
+    context.named_values.insert(arg.clone(), param.to_ref());
+  }
+  let body = definition.expression.codegen(context, module_provider);
+  if let Ok((value, _)) = body {
+    context.builder.build_ret(&value);
+    unsafe { LLVMSetLinkage(function.to_ref(), LLVMAvailableExternallyLinkage) };
+    module_provider.get_pass_manager().run(&mut function);
+  } else {
+    // drop whatever was generated before the error, leaving a declaration again
+    unsafe {
+      while LLVMCountBasicBlocks(function.to_ref()) > 0 {
+        LLVMDeleteBasicBlock(LLVMGetFirstBasicBlock(function.to_ref()));
+      }
+    }
+  }
+
+  context.named_values = named_values;
+  context.tail_position = tail_position;
+  context.builder.position_at_end(&mut insert_block);
+  if let (Some(debug_info), Some(location)) = (context.debug_info.as_ref(), location) {
+    debug_info.leave(context.builder.to_ref(), location);
+  }
+  try!(body);
+
+  Ok(Some(function))
+}
+
+fn expression_size(expr: &parser::Expression) -> usize {
+  match expr {
+    &parser::LiteralExpr(_) | &parser::VariableExpr(_) => 1,
+    &parser::BinaryExpr(_, ref lhs, ref rhs) => 1 + expression_size(lhs) + expression_size(rhs),
+    &parser::UnaryExpr(_, ref operand) => 1 + expression_size(operand),
+    &parser::ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+      1 + expression_size(cond_expr) + expression_size(then_expr) + expression_size(else_expr)
+    },
+    &parser::LoopExpr{ref start_expr, ref end_expr, ref step_expr, ref body_expr, ..} => {
+      1 + expression_size(start_expr) + expression_size(end_expr) + expression_size(step_expr) + expression_size(body_expr)
+    },
+    &parser::CallExpr(_, ref args) => 1 + args.iter().map(expression_size).fold(0, |a, b| a + b)
+  }
+}
+
+// Branches to a call of the interrupt handler if the interrupt flag is set and continues in a
+// new block otherwise.
+fn build_interrupt_check(context: &mut Context, module_provider: &mut ModuleProvider) {
+  if !context.interrupt_checks {
+    return
+  }
+
+  let flag_name = CString::new(guard::INTERRUPT_FLAG_SYMBOL).unwrap();
+  let handler_name = CString::new(guard::INTERRUPTED_SYMBOL).unwrap();
+  let value_name = CString::new("interrupt").unwrap();
+  let cond_name = CString::new("interrupted").unwrap();
+  let empty_name = CString::new("").unwrap();
+
+  let block = context.builder.get_insert_block();
+  let mut function = block.get_parent();
+  let mut interrupt_block = function.append_basic_block_in_context(&mut context.context, "interrupt");
+  let mut continue_block = function.append_basic_block_in_context(&mut context.context, "continue");
+
+  unsafe {
+    let module = module_provider.get_module().to_ref();
+    let flag_type = LLVMInt64TypeInContext(context.context.to_ref());
+    let mut flag = LLVMGetNamedGlobal(module, flag_name.as_ptr());
+    if flag.is_null() {
+      flag = LLVMAddGlobal(module, flag_type, flag_name.as_ptr());
+    }
+    let mut handler = LLVMGetNamedFunction(module, handler_name.as_ptr());
+    if handler.is_null() {
+      let handler_type = LLVMFunctionType(LLVMVoidTypeInContext(context.context.to_ref()), ptr::null_mut(), 0, 0);
+      handler = LLVMAddFunction(module, handler_name.as_ptr(), handler_type);
+    }
+
+    let value = LLVMBuildLoad(context.builder.to_ref(), flag, value_name.as_ptr());
+    LLVMSetVolatile(value, 1);
+    let is_set = LLVMBuildICmp(context.builder.to_ref(), LLVMIntNE, value, LLVMConstInt(flag_type, 0, 0),
+                               cond_name.as_ptr());
+    context.builder.build_cond_br(is_set, &interrupt_block, &continue_block);
+
+    context.builder.position_at_end(&mut interrupt_block);
+    LLVMBuildCall(context.builder.to_ref(), handler, ptr::null_mut(), 0, empty_name.as_ptr());
+    LLVMBuildUnreachable(context.builder.to_ref());
+  }
+
+  context.builder.position_at_end(&mut continue_block);
+}
+
+/// Suffix of the globals that hold the current address of a function when hot reload is on.
+pub const SLOT_SUFFIX: &'static str = ".slot";
+
+// With hot reload, calls to functions defined in the session go through a global holding the
+// address of their latest definition, so that already compiled callers pick up redefinitions.
+// Externs are called directly, and so are recursive calls, which a redefinition replaces along
+// with the rest of the function and which `tailcallelim` can only turn into loops then. Calls in `tail` position are marked as tail calls, which lets
+// the `tailcallelim` pass turn self recursion into loops.
+fn build_call(name: &str, function: &FunctionRef, args: &mut [LLVMValueRef], value_name: &str, tail: bool,
+              context: &mut Context, module_provider: &mut ModuleProvider) -> LLVMValueRef {
+  let call = build_call_instruction(name, function, args, value_name, context, module_provider);
+  if tail {
+    unsafe { LLVMSetTailCall(call, 1) };
+  }
+  call
+}
+
+fn build_call_instruction(name: &str, function: &FunctionRef, args: &mut [LLVMValueRef], value_name: &str,
+                          context: &mut Context, module_provider: &mut ModuleProvider) -> LLVMValueRef {
+  if !context.hot_reload || !context.reloadable_functions.contains(name) || name == context.function_name {
+    return context.builder.build_call(function.to_ref(), args, value_name)
+  }
+
+  let slot_name = CString::new(format!("{}{}", name, SLOT_SUFFIX)).unwrap();
+  let callee_name = CString::new("callee").unwrap();
+  let value_name = CString::new(value_name).unwrap();
+  unsafe {
+    let module = module_provider.get_module().to_ref();
+    let mut slot = LLVMGetNamedGlobal(module, slot_name.as_ptr());
+    if slot.is_null() {
+      // an external declaration, resolved by the JIT to the slot it maintains
+      slot = LLVMAddGlobal(module, LLVMTypeOf(function.to_ref()), slot_name.as_ptr());
+    }
+    let callee = LLVMBuildLoad(context.builder.to_ref(), slot, callee_name.as_ptr());
+    LLVMBuildCall(context.builder.to_ref(), callee, args.as_mut_ptr(), args.len() as u32, value_name.as_ptr())
+  }
+}
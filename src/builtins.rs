@@ -0,0 +1,86 @@
+//! Math functions that can be called without an `extern` declaration. Most of them are lowered
+//! to LLVM intrinsics; the JIT resolves the library calls the intrinsics may turn into, and `tan`
+//! which has no intrinsic, to the implementations below rather than to whatever the process
+//! happens to export.
+
+use std::iter;
+
+use context::Context;
+use module::ModuleProvider;
+
+use iron_llvm::LLVMRef;
+use iron_llvm::core::value::{FunctionCtor, FunctionRef};
+use iron_llvm::core::types::{FunctionTypeCtor, FunctionTypeRef};
+use iron_llvm::support::add_symbol;
+
+pub struct Builtin {
+  pub name: &'static str,
+  pub args: &'static [&'static str],
+  pub description: &'static str,
+  // function called for it: an intrinsic or a symbol registered by `register_symbols`
+  symbol: &'static str
+}
+
+pub static BUILTINS: &'static [Builtin] = &[
+  Builtin { name: "sin", args: &["x"], description: "sine of x (radians)", symbol: "llvm.sin.f64" },
+  Builtin { name: "cos", args: &["x"], description: "cosine of x (radians)", symbol: "llvm.cos.f64" },
+  Builtin { name: "tan", args: &["x"], description: "tangent of x (radians)", symbol: "tan" },
+  Builtin { name: "exp", args: &["x"], description: "e raised to x", symbol: "llvm.exp.f64" },
+  Builtin { name: "log", args: &["x"], description: "natural logarithm of x", symbol: "llvm.log.f64" },
+  Builtin { name: "pow", args: &["x", "y"], description: "x raised to y", symbol: "llvm.pow.f64" },
+  Builtin { name: "sqrt", args: &["x"], description: "square root of x", symbol: "llvm.sqrt.f64" },
+  Builtin { name: "floor", args: &["x"], description: "largest integer not greater than x", symbol: "llvm.floor.f64" },
+  Builtin { name: "abs", args: &["x"], description: "absolute value of x", symbol: "llvm.fabs.f64" },
+  Builtin { name: "min", args: &["x", "y"], description: "smaller of x and y", symbol: "llvm.minnum.f64" },
+  Builtin { name: "max", args: &["x", "y"], description: "larger of x and y", symbol: "llvm.maxnum.f64" }
+];
+
+pub fn find(name: &str) -> Option<&'static Builtin> {
+  BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// One line per builtin, as shown in the REPL.
+pub fn listing() -> String {
+  let mut result = String::new();
+  for builtin in BUILTINS.iter() {
+    let signature = format!("{}({})", builtin.name, builtin.args.join(", "));
+    result.push_str(&format!("{:<12}{}\n", signature, builtin.description));
+  }
+  result
+}
+
+/// Declares the function implementing builtin `name` in the current module. Returns `None` if
+/// there is no such builtin.
+pub fn declare(name: &str, context: &mut Context, module_provider: &mut ModuleProvider) -> Option<FunctionRef> {
+  let builtin = match find(name) {
+    Some(builtin) => builtin,
+    None => return None
+  };
+
+  if let Some(function) = module_provider.get_module().get_function_by_name(builtin.symbol) {
+    return Some(function)
+  }
+  let mut param_types = iter::repeat(context.ty.to_ref()).take(builtin.args.len()).collect::<Vec<_>>();
+  let fty = FunctionTypeRef::get(&context.ty, param_types.as_mut_slice(), false);
+  Some(FunctionRef::new(&mut module_provider.get_module(), builtin.symbol, &fty))
+}
+
+extern fn sin(x: f64) -> f64 { x.sin() }
+extern fn cos(x: f64) -> f64 { x.cos() }
+extern fn tan(x: f64) -> f64 { x.tan() }
+extern fn exp(x: f64) -> f64 { x.exp() }
+extern fn log(x: f64) -> f64 { x.ln() }
+extern fn pow(x: f64, y: f64) -> f64 { x.powf(y) }
+extern fn floor(x: f64) -> f64 { x.floor() }
+
+pub fn register_symbols() {
+  unsafe {
+    add_symbol("sin", sin as *const ());
+    add_symbol("cos", cos as *const ());
+    add_symbol("tan", tan as *const ());
+    add_symbol("exp", exp as *const ());
+    add_symbol("log", log as *const ());
+    add_symbol("pow", pow as *const ());
+    add_symbol("floor", floor as *const ());
+  }
+}
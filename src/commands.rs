@@ -0,0 +1,158 @@
+//! Colon commands of the REPL, e.g. `:ast 1 + 2` or `:load file.ks`.
+
+use std::fs::File;
+use std::io::Read;
+
+use builtins;
+use driver::Session;
+use lexer::tokenize;
+use parser::*;
+
+pub use self::Command::*;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Command {
+  Help,
+  Quit,
+  Tokens(String),
+  AST(String),
+  IR(String),
+  Load(String),
+  Save(String),
+  Restore(String),
+  Reset,
+  Funcs,
+  Ops,
+  Builtins,
+  Time(String)
+}
+
+const HELP: &'static str = "\
+:help            Show this help.
+:quit            Leave the REPL, like quit, q or exit.
+:tokens <src>    Show the tokens of src.
+:ast <src>       Show the AST of src.
+:ir <name>       Show the IR of function name.
+:load <file>     Evaluate the definitions and expressions of a file.
+:save <file>     Write the definitions and externs entered so far to a file.
+:restore <file>  Compile the definitions of a saved session.
+:reset           Forget everything defined in this session.
+:funcs           List the functions defined and declared so far.
+:ops             List the binary operators and their precedences.
+:builtins        List the math functions available without extern.
+:time <expr>     Evaluate expr and show how long each phase took.
+";
+
+/// Names of the commands, for completion.
+pub const NAMES: &'static [&'static str] = &[
+  ":help", ":quit", ":tokens", ":ast", ":ir", ":load", ":save", ":restore", ":reset", ":funcs", ":ops", ":builtins", ":time"
+];
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+  let line = line.trim();
+  let (name, argument) = match line.find(char::is_whitespace) {
+    Some(i) => (&line[..i], line[i..].trim()),
+    None => (line, "")
+  };
+
+  let command = match name {
+    ":help" | ":h" => Help,
+    ":quit" | ":q" => Quit,
+    ":reset" => Reset,
+    ":funcs" => Funcs,
+    ":ops" => Ops,
+    ":builtins" => Builtins,
+    ":tokens" => Tokens(try!(required(name, argument))),
+    ":ast" => AST(try!(required(name, argument))),
+    ":ir" => IR(try!(required(name, argument))),
+    ":load" => Load(try!(required(name, argument))),
+    ":save" => Save(try!(required(name, argument))),
+    ":restore" => Restore(try!(required(name, argument))),
+    ":time" => Time(try!(required(name, argument))),
+    _ => return Err(format!("unknown command {}, see :help", name))
+  };
+  Ok(command)
+}
+
+fn required(name: &str, argument: &str) -> Result<String, String> {
+  if argument.is_empty() {
+    Err(format!("{} needs an argument, see :help", name))
+  } else {
+    Ok(argument.to_string())
+  }
+}
+
+pub fn execute(command: Command, session: &mut Session) -> Result<(), String> {
+  match command {
+    Help => print!("{}", HELP),
+
+    // handled by the REPL loop
+    Quit => (),
+
+    Tokens(source) => println!("{:?}", tokenize(&source)),
+
+    AST(source) => {
+      // operators defined in the source are not kept
+      let mut settings = session.parser_settings().clone();
+      let (ast, rest, errors) = parse(tokenize(&source).as_slice(), &[], &mut settings);
+      for message in errors.iter() {
+        println!("Error: {}", message);
+      }
+      if !rest.is_empty() {
+        println!("Error: unexpected end of input");
+      }
+      for node in ast.iter() {
+        println!("{:?}", node);
+      }
+    },
+
+    IR(name) => match session.function_ir(&name) {
+      Some(ir) => print!("{}", ir),
+      None => return Err(format!("unknown function {}", name))
+    },
+
+    Load(path) => {
+      let mut source = String::new();
+      try!(File::open(&path).and_then(|mut f| f.read_to_string(&mut source))
+           .map_err(|e| format!("{}: {}", path, e)));
+      session.eval_source(&source);
+    },
+
+    Save(path) => {
+      let count = try!(session.save(&path));
+      println!("Saved {} definitions to {}", count, path);
+    },
+
+    Restore(path) => {
+      let count = try!(session.restore(&path));
+      println!("Restored {} definitions from {}", count, path);
+    },
+
+    Reset => {
+      session.reset();
+      println!("Session reset");
+    },
+
+    Funcs => for node in session.definitions().iter() {
+      match node {
+        &ExternNode(ref prototype) => println!("extern {}", prototype),
+        &FunctionNode(ref function) => println!("def {}", function.prototype)
+      }
+    },
+
+    Ops => for (operator, precedence) in session.parser_settings().operators().into_iter() {
+      println!("{:>4}  {}", precedence, operator);
+    },
+
+    Builtins => print!("{}", builtins::listing()),
+
+    Time(source) => {
+      session.eval_source(&source);
+      // with --stats, evaluating has already printed them
+      if !session.options().stats {
+        print!("{}", session.last_stats().report());
+      }
+    }
+  }
+  Ok(())
+}
@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use llvm_sys::prelude::LLVMValueRef;
 
+use debug_info::DebugInfo;
+use parser;
+use timing::Stats;
+
 use iron_llvm::core;
 use iron_llvm::core::types::{RealTypeCtor, RealTypeRef};
 use iron_llvm::{LLVMRef, LLVMRefCtor};
@@ -10,6 +14,24 @@ pub struct Context {
   pub builder: core::Builder,
   pub named_values: HashMap<String, LLVMValueRef>,
   pub ty: RealTypeRef,
+  // Copy bodies of small functions into later modules so that they can be inlined there.
+  pub inline_across_modules: bool,
+  // ASTs of the functions that can be copied, by name
+  pub definitions: HashMap<String, parser::Function>,
+  // Allow redefining functions and call them through slots holding their latest address.
+  pub hot_reload: bool,
+  // functions defined while hot reload was on, which are called through slots
+  pub reloadable_functions: HashSet<String>,
+  // whether the expression being generated is the last thing its function evaluates
+  pub tail_position: bool,
+  // name of the function being generated, whose calls to itself are never made through a slot
+  pub function_name: String,
+  // poll the interrupt flag at function entries and loop back-edges
+  pub interrupt_checks: bool,
+  // source locations of the nodes being compiled, None without -g
+  pub debug_info: Option<DebugInfo>,
+  // time spent and instructions generated since the last evaluation
+  pub stats: Stats,
 }
 
 impl Context {
@@ -18,7 +40,16 @@ impl Context {
       context: core::Context::get_global(),
       builder: core::Builder::new(),
       named_values: HashMap::new(),
-      ty: RealTypeRef::get_double()
+      ty: RealTypeRef::get_double(),
+      inline_across_modules: false,
+      definitions: HashMap::new(),
+      hot_reload: false,
+      reloadable_functions: HashSet::new(),
+      tail_position: false,
+      function_name: String::new(),
+      interrupt_checks: false,
+      debug_info: None,
+      stats: Stats::default()
     }
   }
 }
@@ -0,0 +1,291 @@
+//! Debug information for the generated code, enabled with `-g`: a compile unit and a subprogram
+//! for every function and the source location of every expression, so that debuggers and
+//! profilers can tell where JIT-compiled code came from. MCJIT registers the objects it emits
+//! with the GDB JIT interface by itself.
+//!
+//! The C API of LLVM 3.6 has no DIBuilder, so the metadata is built by hand in the format its
+//! DIBuilder writes: every descriptor starts with a string of fields separated by NUL
+//! characters, the DWARF tag first. A location is a node of line, column, scope and the
+//! location it was inlined at, which the passes keep on the instructions they transform.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
+use std::iter;
+use std::ptr;
+
+use lexer::*;
+use parser::*;
+use semantics::Locator;
+
+use libc::c_char;
+use llvm_sys::core::{LLVMAddNamedMetadataOperand, LLVMConstInt, LLVMGetCurrentDebugLocation};
+use llvm_sys::core::{LLVMGetNamedMetadataNumOperands, LLVMInt32TypeInContext, LLVMMDNodeInContext};
+use llvm_sys::core::{LLVMMDStringInContext, LLVMSetCurrentDebugLocation};
+use llvm_sys::prelude::{LLVMBuilderRef, LLVMContextRef, LLVMModuleRef, LLVMValueRef};
+
+const DW_TAG_COMPILE_UNIT: u32 = 0x11;
+const DW_TAG_SUBROUTINE_TYPE: u32 = 0x15;
+const DW_TAG_BASE_TYPE: u32 = 0x24;
+const DW_TAG_FILE_TYPE: u32 = 0x29;
+const DW_TAG_SUBPROGRAM: u32 = 0x2e;
+// there is no DWARF language for Kaleidoscope, debuggers know how to show C
+const DW_LANG_C: u32 = 0x2;
+const DW_ATE_FLOAT: u32 = 0x4;
+const FLAG_PROTOTYPED: u32 = 1 << 8;
+// full debug info, not only line tables
+const FULL_DEBUG: u32 = 1;
+
+// version of the metadata format of LLVM 3.6 and of the DWARF emitted from it
+const DEBUG_METADATA_VERSION: u64 = 2;
+const DWARF_VERSION: u64 = 4;
+// behaviour of a module flag when modules are linked: warn if they disagree
+const MODULE_FLAG_WARNING: u64 = 2;
+
+const PRODUCER: &'static str = "kaleidoscope";
+// name of the functions of top-level expressions, which have none in the IR
+const ANONYMOUS_NAME: &'static str = "__anon_expr";
+
+/// Where the nodes being compiled came from, and the metadata of the function being generated.
+pub struct DebugInfo {
+  context: LLVMContextRef,
+  file: String,
+  directory: String,
+  optimized: bool,
+  // lines of the sources evaluated before the current one
+  line_offset: usize,
+  // locations of the prototypes and expressions of the nodes being compiled, by address
+  locations: HashMap<usize, Location>,
+  // subprogram of the function being generated, None if its source is unknown
+  scope: Option<LLVMValueRef>
+}
+
+impl DebugInfo {
+  /// `file` is the name of the source shown by debuggers, relative to the current directory.
+  /// `optimized` tells them that variables and lines may have been optimized away.
+  pub fn new(file: &str, context: LLVMContextRef, optimized: bool) -> DebugInfo {
+    DebugInfo {
+      context: context,
+      file: file.to_string(),
+      directory: env::current_dir().map(|dir| dir.display().to_string()).unwrap_or(String::new()),
+      optimized: optimized,
+      line_offset: 0,
+      locations: HashMap::new(),
+      scope: None
+    }
+  }
+
+  /// Records where the functions of `nodes` and their expressions are, given the ranges of
+  /// `tokens` the nodes were parsed from and the spans of the tokens. The nodes must not move
+  /// until they are compiled, they are known by their addresses.
+  pub fn locate(&mut self, nodes: &[(&ASTNode, (usize, usize))], tokens: &[Token], spans: &[Span]) {
+    for &(node, range) in nodes.iter() {
+      if let &FunctionNode(ref function) = node {
+        let mut locator = Locator::new(tokens, spans, range);
+        let prototype = &function.prototype;
+        // the name and the parameters come first, they must not be taken for variables
+        let span = match prototype.ftype {
+          _ if prototype.name.is_empty() => spans.get(range.0).cloned(),
+          Normal => locator.find(&Ident(prototype.name.clone())),
+          UnaryOp(ref op) | BinaryOp(ref op, _) => locator.find(&Operator(op.clone()))
+        };
+        for arg in prototype.args.iter() {
+          locator.find(&Ident(arg.clone()));
+        }
+        self.record(prototype, span);
+        self.expression(&function.expression, &mut locator);
+      }
+    }
+  }
+
+  /// Forgets the locations of the nodes compiled from `source`, whose lines are counted so
+  /// that the lines of the next source follow them. None for nodes without a source.
+  pub fn finish_source(&mut self, source: Option<&str>) {
+    self.locations.clear();
+    if let Some(source) = source {
+      self.line_offset += source.matches('\n').count();
+    }
+  }
+
+  /// Describes `function`, defined by `prototype` in `module`, and starts giving instructions
+  /// the location of the prototype. Functions of unknown source get no debug info.
+  pub fn start_function(&mut self, prototype: &Prototype, function: LLVMValueRef, module: LLVMModuleRef,
+                        builder: LLVMBuilderRef) {
+    let location = match self.locations.get(&address(prototype)) {
+      Some(location) => *location,
+      None => {
+        self.scope = None;
+        return
+      }
+    };
+
+    unsafe {
+      let flags_name = CString::new("llvm.module.flags").unwrap();
+      if LLVMGetNamedMetadataNumOperands(module, flags_name.as_ptr()) == 0 {
+        let module_flags = [("Debug Info Version", DEBUG_METADATA_VERSION), ("Dwarf Version", DWARF_VERSION)];
+        for &(name, value) in module_flags.iter() {
+          let flag = self.node(vec![self.int(MODULE_FLAG_WARNING), self.string(name), self.int(value)]);
+          LLVMAddNamedMetadataOperand(module, flags_name.as_ptr(), flag);
+        }
+      }
+
+      let encoding = DW_ATE_FLOAT.to_string();
+      let flags = FLAG_PROTOTYPED.to_string();
+      let language = DW_LANG_C.to_string();
+      let kind = FULL_DEBUG.to_string();
+
+      let file_pair = self.node(vec![self.string(&self.file), self.string(&self.directory)]);
+      let file = self.node(vec![self.header(DW_TAG_FILE_TYPE, &[]), file_pair]);
+      let double = self.node(vec![
+        self.header(DW_TAG_BASE_TYPE, &["double", "0", "64", "64", "0", "0", encoding.as_str()]),
+        ptr::null_mut(), ptr::null_mut()]);
+      // the return type, then the parameter types
+      let types = self.node(iter::repeat(double).take(prototype.args.len() + 1).collect());
+      let function_type = self.node(vec![
+        self.header(DW_TAG_SUBROUTINE_TYPE, &["", "0", "0", "0", "0", "0", "0"]),
+        ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), types, ptr::null_mut(), ptr::null_mut(), ptr::null_mut()]);
+
+      let name = if prototype.name.is_empty() { ANONYMOUS_NAME } else { prototype.name.as_str() };
+      let line = location.line.to_string();
+      let optimized = if self.optimized { "1" } else { "0" };
+      let subprogram = self.node(vec![
+        self.header(DW_TAG_SUBPROGRAM, &[name, name, "", line.as_str(), "0", "1", "0", "0",
+                                         flags.as_str(), optimized, line.as_str()]),
+        file_pair, file, function_type, ptr::null_mut(), function, ptr::null_mut(), ptr::null_mut(),
+        self.node(vec![])]);
+
+      // a unit of its own for every function, since modules are compiled one definition at a time
+      let unit = self.node(vec![
+        self.header(DW_TAG_COMPILE_UNIT, &[language.as_str(), PRODUCER, optimized, "", "0", "",
+                                           kind.as_str()]),
+        file_pair, self.node(vec![]), self.node(vec![]), self.node(vec![subprogram]), self.node(vec![]),
+        self.node(vec![])]);
+      let units_name = CString::new("llvm.dbg.cu").unwrap();
+      LLVMAddNamedMetadataOperand(module, units_name.as_ptr(), unit);
+
+      self.scope = Some(subprogram);
+      LLVMSetCurrentDebugLocation(builder, self.location(location, subprogram));
+    }
+  }
+
+  /// Stops giving instructions locations, after the function is generated or has failed.
+  pub fn finish_function(&mut self, builder: LLVMBuilderRef) {
+    self.scope = None;
+    unsafe { LLVMSetCurrentDebugLocation(builder, ptr::null_mut()) };
+  }
+
+  /// Gives the instructions built next the location of `expr`, if it is known, and returns the
+  /// location to `leave` with once they are built.
+  pub fn enter(&self, expr: &Expression, builder: LLVMBuilderRef) -> LLVMValueRef {
+    unsafe {
+      let previous = LLVMGetCurrentDebugLocation(builder);
+      if let (Some(scope), Some(location)) = (self.scope, self.locations.get(&address(expr))) {
+        LLVMSetCurrentDebugLocation(builder, self.location(*location, scope));
+      }
+      previous
+    }
+  }
+
+  /// Builds the next instructions without a location, e.g. for the body of another function,
+  /// until `leave` is called with the returned location.
+  pub fn suspend(&self, builder: LLVMBuilderRef) -> LLVMValueRef {
+    unsafe {
+      let previous = LLVMGetCurrentDebugLocation(builder);
+      LLVMSetCurrentDebugLocation(builder, ptr::null_mut());
+      previous
+    }
+  }
+
+  pub fn leave(&self, builder: LLVMBuilderRef, previous: LLVMValueRef) {
+    unsafe { LLVMSetCurrentDebugLocation(builder, previous) };
+  }
+
+  fn record<T>(&mut self, item: &T, span: Option<Span>) {
+    if let Some(span) = span {
+      let location = Location { line: span.start.line + self.line_offset, column: span.start.column };
+      self.locations.insert(address(item), location);
+    }
+  }
+
+  // Walks `expr` in the order of the source, like the semantic checks do.
+  fn expression(&mut self, expr: &Expression, locator: &mut Locator) {
+    match expr {
+      // literals have no instructions of their own, and the default step of a loop no token
+      &LiteralExpr(_) => (),
+
+      &VariableExpr(ref name) => {
+        let span = locator.find(&Ident(name.clone()));
+        self.record(expr, span);
+      },
+
+      &BinaryExpr(ref op, ref lhs, ref rhs) => {
+        self.expression(lhs, locator);
+        let span = locator.find(&Operator(op.clone()));
+        self.record(expr, span);
+        self.expression(rhs, locator);
+      },
+
+      &UnaryExpr(ref op, ref operand) => {
+        let span = locator.find(&Operator(op.clone()));
+        self.record(expr, span);
+        self.expression(operand, locator);
+      },
+
+      &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+        let span = locator.find(&If);
+        self.record(expr, span);
+        self.expression(cond_expr, locator);
+        self.expression(then_expr, locator);
+        self.expression(else_expr, locator);
+      },
+
+      &LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+        let span = locator.find(&For);
+        self.record(expr, span);
+        locator.find(&Ident(var_name.clone()));
+        self.expression(start_expr, locator);
+        self.expression(end_expr, locator);
+        self.expression(step_expr, locator);
+        self.expression(body_expr, locator);
+      },
+
+      &CallExpr(ref name, ref args) => {
+        let span = locator.find(&Ident(name.clone()));
+        self.record(expr, span);
+        for arg in args.iter() {
+          self.expression(arg, locator);
+        }
+      }
+    }
+  }
+
+  unsafe fn location(&self, location: Location, scope: LLVMValueRef) -> LLVMValueRef {
+    self.node(vec![self.int(location.line as u64), self.int(location.column as u64), scope, ptr::null_mut()])
+  }
+
+  // The header of a descriptor: its tag in hexadecimal, then its fields.
+  unsafe fn header(&self, tag: u32, fields: &[&str]) -> LLVMValueRef {
+    let mut header = format!("0x{:x}", tag);
+    for field in fields.iter() {
+      header.push('\0');
+      header.push_str(field);
+    }
+    self.string(&header)
+  }
+
+  unsafe fn string(&self, value: &str) -> LLVMValueRef {
+    LLVMMDStringInContext(self.context, value.as_ptr() as *const c_char, value.len() as u32)
+  }
+
+  unsafe fn node(&self, mut operands: Vec<LLVMValueRef>) -> LLVMValueRef {
+    LLVMMDNodeInContext(self.context, operands.as_mut_ptr(), operands.len() as u32)
+  }
+
+  unsafe fn int(&self, value: u64) -> LLVMValueRef {
+    LLVMConstInt(LLVMInt32TypeInContext(self.context), value, 0)
+  }
+}
+
+fn address<T>(item: &T) -> usize {
+  item as *const T as usize
+}
@@ -0,0 +1,80 @@
+//! Orders definitions so that every function comes after the functions and operators it uses,
+//! which is the order they have to be compiled in when a session is replayed.
+
+use std::collections::HashMap;
+
+use parser::*;
+
+pub fn node_name(node: &ASTNode) -> &str {
+  match node {
+    &ExternNode(ref prototype) => &prototype.name,
+    &FunctionNode(ref function) => &function.prototype.name
+  }
+}
+
+/// Sorts `nodes` by their dependencies, keeping the original order where there are none.
+/// Functions calling each other keep the order they are visited in.
+pub fn dependency_order(nodes: &[ASTNode]) -> Vec<ASTNode> {
+  let index = nodes.iter().enumerate()
+    .map(|(i, node)| (node_name(node).to_string(), i))
+    .collect::<HashMap<_, _>>();
+  let mut visited = vec![false; nodes.len()];
+  let mut order = Vec::new();
+  for i in 0..nodes.len() {
+    visit(i, nodes, &index, &mut visited, &mut order);
+  }
+  order
+}
+
+fn visit(i: usize, nodes: &[ASTNode], index: &HashMap<String, usize>, visited: &mut Vec<bool>,
+         order: &mut Vec<ASTNode>) {
+  if visited[i] {
+    return
+  }
+  visited[i] = true;
+
+  if let &FunctionNode(ref function) = &nodes[i] {
+    let mut callees = Vec::new();
+    collect_callees(&function.expression, &mut callees);
+    for callee in callees.iter() {
+      // anything else is builtin, from the prelude or undefined
+      if let Some(&j) = index.get(callee) {
+        visit(j, nodes, index, visited, order);
+      }
+    }
+  }
+  order.push(nodes[i].clone());
+}
+
+// Names of the functions `expr` calls, operators included.
+fn collect_callees(expr: &Expression, callees: &mut Vec<String>) {
+  match expr {
+    &LiteralExpr(_) | &VariableExpr(_) => (),
+    &BinaryExpr(ref op, ref lhs, ref rhs) => {
+      callees.push("binary".to_string() + op);
+      collect_callees(lhs, callees);
+      collect_callees(rhs, callees);
+    },
+    &UnaryExpr(ref op, ref operand) => {
+      callees.push("unary".to_string() + op);
+      collect_callees(operand, callees);
+    },
+    &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+      collect_callees(cond_expr, callees);
+      collect_callees(then_expr, callees);
+      collect_callees(else_expr, callees);
+    },
+    &LoopExpr{ref start_expr, ref end_expr, ref step_expr, ref body_expr, ..} => {
+      collect_callees(start_expr, callees);
+      collect_callees(end_expr, callees);
+      collect_callees(step_expr, callees);
+      collect_callees(body_expr, callees);
+    },
+    &CallExpr(ref name, ref args) => {
+      callees.push(name.clone());
+      for arg in args.iter() {
+        collect_callees(arg, callees);
+      }
+    }
+  }
+}
@@ -1,17 +1,40 @@
 pub use self::Stage::{Exec, AST, Tokens, IR};
+pub use self::Evaluation::{Compiled, Returned, RuntimeError, CodegenError, Rejected};
+use std::fs;
+use std::fs::File;
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::fs::MetadataExt;
 
 use parser::*;
 use lexer::*;
+use module;
 use module::{ModuleProvider, SimpleModuleProvider};
+use dependencies;
+use printer::pretty;
 use jitter;
 use jitter::JITter;
 use context::Context;
 use builder;
 use builder::IRBuilder;
+use debug_info::DebugInfo;
+use serialization;
+use serialization::Format;
+use passes::PassOptions;
+use tail_calls;
+use semantics;
+use semantics::{SemanticError, SymbolTable};
+use lint;
+use lint::Warning;
+use commands;
+use builtins;
+use editor::Editor;
+use guard::Limits;
+use timing::{Stats, Timer};
 
 use llvm_sys::core::LLVMDumpValue;
+use llvm_sys::prelude::LLVMValueRef;
 
 use iron_llvm::LLVMRef;
 use iron_llvm::core::value::Value;
@@ -25,87 +48,718 @@ pub enum Stage {
   Tokens
 }
 
-pub fn main_loop(stage: Stage) {
-  let stdin = io::stdin();
-  let mut stdout = io::stdout();
-  let mut input = String::new();
-  let mut parser_settings = default_parser_settings();
-  let mut ir_container: Box<JITter> = match stage {
+/// What became of a node evaluated in a session.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Evaluation {
+  // the function compiled for a definition, an extern, or a top-level expression that is not run
+  Compiled(LLVMValueRef),
+  Returned(f64),
+  RuntimeError(String),
+  CodegenError(String),
+  // errors found by semantic analysis, the node was not compiled
+  Rejected(Vec<SemanticError>)
+}
+
+/// Settings of a driver session given on the command line.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Options {
+  pub passes: PassOptions,
+  pub inline_across_modules: bool,
+  pub hot_reload: bool,
+  pub report_tail_calls: bool,
+  pub limits: Limits,
+  // source compiled before the first input, None to start empty
+  pub prelude: Option<String>,
+  // write compiled bitcode next to saved sessions and use it to restore them
+  pub cache_bitcode: bool,
+  // saved session to restore before the first prompt
+  pub resume: Option<String>,
+  // print how long the phases of every input took
+  pub stats: bool,
+  // no prompts and no IR dumps in the Exec stage, for reading programs from pipes
+  pub quiet: bool,
+  // whether someone can interrupt running code with Ctrl-C, e.g. at a terminal
+  pub interactive: bool,
+  // attach source locations to the generated code
+  pub debug_info: bool
+}
+
+/// Name of the source of the REPL in debug info. Lines count from its first input.
+pub const SOURCE_NAME: &'static str = "<stdin>";
+
+impl Options {
+  /// Whether generated code checks for interrupts, which costs a load and a branch at every
+  /// function entry and loop iteration: only with a timeout or when it can be interrupted.
+  pub fn interruptible(&self) -> bool {
+    self.limits.timeout != 0 || self.interactive
+  }
+}
+
+fn new_ir_container(stage: &Stage, options: &Options) -> Box<JITter> {
+  match *stage {
     Exec => {
       target::initilalize_native_target();
       target::initilalize_native_asm_printer();
       jitter::init();
-      Box::new(jitter::MCJITter::new("main"))
+      Box::new(jitter::MCJITter::new("main", &options.passes, options.limits))
     },
-    _ => Box::new(SimpleModuleProvider::new("main"))
-  };
-  let mut builder_context = Context::new();
+    _ => Box::new(SimpleModuleProvider::new("main", &options.passes))
+  }
+}
+
+/// State of a REPL session: the operators and functions defined so far and the code compiled
+/// for them.
+pub struct Session {
+  pub stage: Stage,
+  options: Options,
+  parser_settings: ParserSettings,
+  ir_container: Box<JITter>,
+  context: Context,
+  // definitions and externs entered after the prelude, only the latest one for each name
+  definitions: Vec<ASTNode>,
+  // functions compiled so far, the prelude included
+  symbols: SymbolTable,
+  // operator precedences from before the input being evaluated was parsed
+  operators: ParserSettings,
+  // phases of the input being evaluated and of the last evaluated one
+  stats: Stats,
+  last_stats: Stats,
+  errors: usize
+}
+
+impl Session {
+  pub fn new(stage: Stage, options: &Options) -> Session {
+    let mut session = Session {
+      stage: stage.clone(),
+      options: options.clone(),
+      parser_settings: default_parser_settings(),
+      ir_container: new_ir_container(&stage, options),
+      context: Context::new(),
+      definitions: Vec::new(),
+      symbols: SymbolTable::new(),
+      operators: default_parser_settings(),
+      stats: Stats::default(),
+      last_stats: Stats::default(),
+      errors: 0
+    };
+    session.context.inline_across_modules = options.inline_across_modules;
+    session.context.hot_reload = options.hot_reload && stage == Exec;
+    session.context.interrupt_checks = stage == Exec && options.interruptible();
+    if options.debug_info && (stage == Exec || stage == IR) {
+      let context = session.context.context.to_ref();
+      session.context.debug_info = Some(DebugInfo::new(SOURCE_NAME, context, !options.passes.is_empty()));
+    }
+
+    if stage != Tokens {
+      if let Some(ref prelude) = options.prelude {
+        // in the AST stage only the operator precedences are needed
+        let module_provider = if stage == AST { None } else { Some(session.ir_container.get_module_provider()) };
+        let nodes = load_prelude(prelude, &mut session.parser_settings, &mut session.context, module_provider);
+        for node in nodes.iter() {
+          session.symbols.define_node(node);
+        }
+        if stage != AST {
+          session.ir_container.finish_definition();
+        }
+      }
+    }
+    session.operators = session.parser_settings.clone();
+    session
+  }
+
+  /// Starts over with the options the session was created with.
+  pub fn reset(&mut self) {
+    let options = self.options.clone();
+    *self = Session::new(self.stage.clone(), &options);
+  }
+
+  pub fn options(&self) -> &Options {
+    &self.options
+  }
+
+  pub fn parser_settings(&self) -> &ParserSettings {
+    &self.parser_settings
+  }
+
+  pub fn definitions(&self) -> &[ASTNode] {
+    self.definitions.as_slice()
+  }
+
+  pub fn tokenize(&mut self, input: &str) -> Vec<Token> {
+    let timer = Timer::start();
+    let tokens = tokenize(input);
+    self.stats.tokenize_ms += timer.elapsed_ms();
+    tokens
+  }
+
+  /// Parses tokens of an input, which defines the operators declared in it.
+  pub fn parse(&mut self, tokens: &[Token]) -> ParsingResult {
+    let timer = Timer::start();
+    let result = parse(tokens, &[], &mut self.parser_settings);
+    self.stats.parse_ms += timer.elapsed_ms();
+    result
+  }
+
+  /// Like `parse`, with the token positions of nodes and errors.
+  pub fn parse_with_positions(&mut self, tokens: &[Token]) -> PositionedParsingResult {
+    let timer = Timer::start();
+    let result = parse_with_positions(tokens, &mut self.parser_settings);
+    self.stats.parse_ms += timer.elapsed_ms();
+    result
+  }
+
+  pub fn report_error(&mut self, message: &str) {
+    println!("Error: {}", message);
+    self.errors += 1;
+  }
+
+  /// Number of errors reported so far.
+  pub fn error_count(&self) -> usize {
+    self.errors
+  }
+
+  /// Statistics of the last evaluated input.
+  pub fn last_stats(&self) -> &Stats {
+    &self.last_stats
+  }
+
+  /// Compiles `ast` and, in the Exec stage, runs its top-level expressions, printing what the
+  /// REPL shows for every node. `source` is the text `ast` was parsed from, if there is one, to
+  /// show where semantic errors are.
+  pub fn eval(&mut self, ast: &[ASTNode], source: Option<&str>) {
+    if self.stage == AST {
+      println!("{:?}", ast);
+      return
+    }
+
+    if self.options.report_tail_calls {
+      report_tail_calls(ast);
+    }
+    for warning in self.lint(ast, source).iter() {
+      println!("Warning: {}", warning);
+    }
+
+    for evaluation in self.evaluate(ast, source).into_iter() {
+      match evaluation {
+        Compiled(value) => if self.stage == IR || !self.options.quiet {
+          // value.dump()
+          unsafe {
+              LLVMDumpValue(value)
+          }
+        },
+        Returned(value) => println!("=> {}", value),
+        RuntimeError(message) => {
+          println!("Runtime error: {}", message);
+          self.errors += 1;
+        },
+        CodegenError(message) => {
+          println!("Error occured: {}", message);
+          self.errors += 1;
+        },
+        Rejected(errors) => for error in errors.iter() {
+          self.report_error(&format!("{}", error));
+        }
+      }
+    }
+
+    if self.options.stats {
+      print!("{}", self.last_stats.report());
+    }
+  }
+
+  /// Compiles `ast` and, in the Exec stage, runs its top-level expressions. Gives what became
+  /// of every node, in order, without printing anything. Nodes with semantic errors are not
+  /// compiled; the errors of all nodes are found before any of them is.
+  pub fn evaluate(&mut self, ast: &[ASTNode], source: Option<&str>) -> Vec<Evaluation> {
+    // the values of earlier evaluations are not used anymore
+    self.ir_container.remove_unused_modules();
+    let errors = self.check(ast, source);
+    if self.context.debug_info.is_some() {
+      if let Some((nodes, tokens, spans)) = self.locate(ast, source) {
+        let nodes = ast.iter().zip(nodes.iter()).map(|(node, &(_, range))| (node, range)).collect::<Vec<_>>();
+        self.context.debug_info.as_mut().unwrap().locate(nodes.as_slice(), tokens.as_slice(), spans.as_slice());
+      }
+    }
+    let mut evaluations = Vec::new();
+    for (i, node) in ast.iter().enumerate() {
+      let node_errors = errors.iter().filter(|error| error.node == i).cloned().collect::<Vec<_>>();
+      if !node_errors.is_empty() {
+        evaluations.push(Rejected(node_errors));
+        continue
+      }
+
+      let evaluation = match node.codegen(&mut self.context, self.ir_container.get_module_provider()) {
+        Ok((value, runnable)) => {
+          self.record(node);
+          if runnable && self.stage == Exec {
+            match self.ir_container.run_function(value) {
+              Ok(value) => Returned(value),
+              Err(message) => RuntimeError(message)
+            }
+          } else {
+            self.ir_container.finish_definition();
+            Compiled(value.to_ref())
+          }
+        },
+        Err(message) => CodegenError(message)
+      };
+      evaluations.push(evaluation);
+    }
+    if let Some(ref mut debug_info) = self.context.debug_info {
+      debug_info.finish_source(source);
+    }
+
+    self.stats.merge(&self.context.stats);
+    self.context.stats = Stats::default();
+    self.stats.merge(&self.ir_container.take_stats());
+    self.last_stats = mem::replace(&mut self.stats, Stats::default());
+    self.operators = self.parser_settings.clone();
+    evaluations
+  }
+
+  /// Parses and evaluates a complete source text, e.g. a file.
+  pub fn eval_source(&mut self, source: &str) {
+    let tokens = self.tokenize(source);
+    let (ast, rest, mut errors) = self.parse(tokens.as_slice());
+    if !rest.is_empty() {
+      errors.push("unexpected end of input".to_string());
+    }
+    for message in errors.iter() {
+      self.report_error(message);
+    }
+    self.eval(ast.as_slice(), Some(source));
+  }
+
+  /// Finds unknown names and wrong numbers of arguments in `ast`, given the functions compiled
+  /// so far. The errors are located if `source`, the text `ast` was parsed from, is given.
+  pub fn check(&self, ast: &[ASTNode], source: Option<&str>) -> Vec<SemanticError> {
+    match self.locate(ast, source) {
+      Some((nodes, tokens, spans)) => {
+        semantics::check_located(nodes.as_slice(), tokens.as_slice(), spans.as_slice(), &self.symbols)
+      },
+      None => semantics::check(ast, &self.symbols)
+    }
+  }
+
+  /// Warnings about `ast`, to be evaluated next, located like the errors of `check`.
+  pub fn lint(&self, ast: &[ASTNode], source: Option<&str>) -> Vec<Warning> {
+    match self.locate(ast, source) {
+      Some((nodes, tokens, spans)) => {
+        lint::lint_located(nodes.as_slice(), tokens.as_slice(), spans.as_slice(), &self.operators)
+      },
+      None => lint::lint(ast, &self.operators)
+    }
+  }
+
+  // Parses `source` again to find where the nodes of `ast` are in it, None if it does not give
+  // the same nodes.
+  fn locate(&self, ast: &[ASTNode], source: Option<&str>)
+            -> Option<(Vec<(ASTNode, (usize, usize))>, Vec<Token>, Vec<Span>)> {
+    let source = match source {
+      Some(source) => source,
+      None => return None
+    };
+    let (tokens, spans): (Vec<_>, Vec<_>) = tokenize_with_spans(source).into_iter().unzip();
+    // operators defined in the source are known by now, so it parses the same again
+    let (nodes, _, _) = parse_with_positions(tokens.as_slice(), &mut self.parser_settings.clone());
+    if nodes.len() == ast.len() && nodes.iter().zip(ast.iter()).all(|(&(ref parsed, _), node)| parsed == node) {
+      Some((nodes, tokens, spans))
+    } else {
+      None
+    }
+  }
+
+  /// IR of the latest definition or declaration of function `name`.
+  pub fn function_ir(&self, name: &str) -> Option<String> {
+    self.ir_container.function_to_string(name)
+  }
+
+  /// How function `name` is declared: its `def` or `extern` line, or the signature of a
+  /// builtin. None for unknown functions and those of the prelude.
+  pub fn signature(&self, name: &str) -> Option<String> {
+    let definition = self.definitions.iter().find(|node| dependencies::node_name(node) == name);
+    match definition {
+      Some(&ExternNode(ref prototype)) => Some(format!("extern {}", prototype)),
+      Some(&FunctionNode(ref function)) => Some(format!("def {}", function.prototype)),
+      None => builtins::find(name).map(|builtin| format!("{}({})", builtin.name, builtin.args.join(", ")))
+    }
+  }
+
+  /// Words the REPL completes: keywords, commands, builtins and the functions of the session.
+  /// Operator functions are left out, they are not called by name.
+  pub fn completion_words(&self) -> Vec<String> {
+    let keywords = ["def", "extern", "if", "then", "else", "for", "in", "binary", "unary"];
+    let mut words = keywords.iter().chain(commands::NAMES.iter())
+      .chain(builtins::BUILTINS.iter().map(|builtin| &builtin.name))
+      .map(|word| word.to_string())
+      .collect::<Vec<_>>();
+    words.extend(self.ir_container.function_names().into_iter()
+                 .filter(|name| !name.starts_with("binary") && !name.starts_with("unary")));
+    words
+  }
+
+  /// Writes the definitions and externs of the session to `path`, every function after the ones
+  /// it uses, and returns how many there are. With `cache_bitcode`, the compiled definitions are
+  /// written next to it.
+  pub fn save(&self, path: &str) -> Result<usize, String> {
+    let nodes = dependencies::dependency_order(self.definitions.as_slice());
+    let mut source = String::new();
+    for node in nodes.iter() {
+      source.push_str(&format!("{}\n", pretty(node, &self.parser_settings)));
+    }
+    try!(File::create(path).and_then(|mut f| f.write_all(source.as_bytes()))
+         .map_err(|e| format!("{}: {}", path, e)));
+
+    if self.options.cache_bitcode {
+      try!(self.compile_to_bitcode(nodes.as_slice(), &bitcode_path(path)));
+    }
+    Ok(nodes.len())
+  }
+
+  /// Replays a session saved with `save` and returns the number of definitions restored.
+  /// Definitions the session already has as they are saved are skipped, so that a session can
+  /// be restored into again. The cached bitcode is used instead of compiling the source if it
+  /// is at least as new.
+  pub fn restore(&mut self, path: &str) -> Result<usize, String> {
+    let mut source = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut source))
+         .map_err(|e| format!("{}: {}", path, e)));
+
+    let cache = bitcode_path(path);
+    if self.stage == Exec && self.options.cache_bitcode && is_newer(&cache, path) {
+      if self.context.hot_reload {
+        // calls through hot reload slots are not in the cache
+        println!("Warning: ignoring {} with hot reload, compiling {} instead", cache, path);
+      } else {
+        match self.restore_compiled(&source, &cache) {
+          Ok(count) => return Ok(count),
+          Err(message) => println!("Warning: ignoring {}: {}", cache, message)
+        }
+      }
+    }
+
+    let (ast, rest, mut errors) = self.parse(tokenize(&source).as_slice());
+    if !rest.is_empty() {
+      errors.push("unexpected end of input".to_string());
+    }
+    let mut count = 0;
+    for node in ast.iter().filter(|node| dependencies::node_name(node) != "") {
+      if self.definitions.contains(node) {
+        continue
+      }
+      match node.codegen(&mut self.context, self.ir_container.get_module_provider()) {
+        Ok(_) => {
+          self.ir_container.finish_definition();
+          self.record(node);
+          count += 1;
+        },
+        Err(message) => errors.push(message)
+      }
+    }
+    self.operators = self.parser_settings.clone();
+
+    match errors.first() {
+      Some(message) => Err(format!("{}: {}", path, message)),
+      None => Ok(count)
+    }
+  }
+
+  fn restore_compiled(&mut self, source: &str, cache: &str) -> Result<usize, String> {
+    let module = try!(module::read_bitcode(cache));
+    let (ast, rest, errors) = self.parse(tokenize(source).as_slice());
+    if !errors.is_empty() || !rest.is_empty() {
+      return Err("the saved source does not parse".to_string())
+    }
+    // the module would define them a second time
+    if let Some(node) = ast.iter().find(|node| self.definitions.iter().any(|definition| {
+      dependencies::node_name(definition) == dependencies::node_name(node)
+    })) {
+      return Err(format!("the session already has {}", dependencies::node_name(node)))
+    }
+    try!(self.ir_container.add_compiled_module(module));
+    for node in ast.iter() {
+      self.record(node);
+    }
+    self.operators = self.parser_settings.clone();
+    Ok(ast.len())
+  }
+
+  // Compiles `nodes` into a module of their own. The prelude is compiled on every start, so it
+  // is only declared there.
+  fn compile_to_bitcode(&self, nodes: &[ASTNode], path: &str) -> Result<(), String> {
+    let mut module_provider = SimpleModuleProvider::new("main", &self.options.passes);
+    let mut context = Context::new();
+    context.interrupt_checks = self.context.interrupt_checks;
+
+    if let Some(ref prelude) = self.options.prelude {
+      let (ast, _, _) = parse(tokenize(prelude).as_slice(), &[], &mut default_parser_settings());
+      for node in ast.iter() {
+        if let &FunctionNode(ref function) = node {
+          try!(function.prototype.codegen(&mut context, &mut module_provider));
+        }
+      }
+    }
+    for node in nodes.iter() {
+      try!(node.codegen(&mut context, &mut module_provider));
+    }
+    module_provider.run_module_passes();
+    module::write_bitcode(module_provider.get_module(), path)
+  }
+
+  fn record(&mut self, node: &ASTNode) {
+    self.symbols.define_node(node);
+    let name = dependencies::node_name(node).to_string();
+    if name.as_str() == "" {
+      return
+    }
+    self.definitions.retain(|definition| dependencies::node_name(definition) != name);
+    self.definitions.push(node.clone());
+  }
+
+  /// Dumps everything compiled in the session, which is what the REPL does when it exits.
+  pub fn finish(&mut self) {
+    if self.stage == IR {
+      self.ir_container.run_module_passes();
+    }
+    if self.stage == IR || (self.stage == Exec && !self.options.quiet) {
+      self.ir_container.dump();
+    }
+  }
+}
+
+/// Runs the REPL until the end of the input or a quit command and returns the number of errors.
+pub fn main_loop(stage: Stage, options: &Options) -> usize {
+  let (prompt, continuation_prompt) = if options.quiet { ("", "") } else { ("> ", ".\t") };
+  let mut editor = Editor::new();
+  let mut session = Session::new(stage.clone(), options);
+  if let Some(ref path) = options.resume {
+    match session.restore(path) {
+      Ok(count) => println!("Restored {} definitions from {}", count, path),
+      Err(message) => session.report_error(&message)
+    }
+  }
 
   let mut ast = Vec::new();
   let mut prev = Vec::new();
   'main: loop {
-    print!("> ");
-    stdout.flush().unwrap();
-    input.clear();
-    stdin.read_line(&mut input).ok().expect("Failed to read input");
+    let mut input = match editor.read_line(prompt, session.completion_words().as_slice()) {
+      Some(line) => line,
+      None => break
+    };
+    // Ctrl-C
+    if input.is_empty() {
+      continue
+    }
+    // all lines of an input, for the history
+    let mut entry = input.clone();
     if input.as_str() == "quit\n" || input.as_str() == "q\n"
       || input.as_str() == "exit\n" {
       break;
     }
 
+    if input.trim_left().starts_with(":") {
+      editor.add_history(&entry);
+      match commands::parse_command(input.as_str()) {
+        Ok(commands::Quit) => break,
+        Ok(command) => if let Err(message) = commands::execute(command, &mut session) {
+          session.report_error(&message);
+        },
+        Err(message) => session.report_error(&message)
+      }
+      continue
+    }
+
     ast.clear();
     prev.clear();
     loop {
-      let tokens = tokenize(input.as_str());
+      let tokens = session.tokenize(input.as_str());
       if stage == Tokens {
         println!("{:?}", tokens);
+        editor.add_history(&entry);
         continue 'main
       };
 
       prev.extend(tokens.into_iter());
 
-      let parse_result = parse(prev.as_slice(), ast.as_slice(), &mut parser_settings);
-      match parse_result {
-        Ok((parsed_ast, rest)) => {
-          ast.extend(parsed_ast.into_iter());
-          if rest.is_empty() {
-            break
-          } else {
-            prev = rest;
-          }
-        },
-        Err(message) => {
-          println!("Error: {}", message);
-          continue 'main
+      let (parsed_ast, rest, errors) = session.parse(prev.as_slice());
+      ast.extend(parsed_ast.into_iter());
+      if !errors.is_empty() {
+        // nodes parsed around the errors are still compiled, but an incomplete rest is dropped
+        for message in errors.iter() {
+          session.report_error(message);
         }
+        break
+      }
+      if rest.is_empty() {
+        break
       }
-      print!(".\t");
-      stdout.flush().unwrap();
-      input.clear();
-      stdin.read_line(&mut input).ok().expect("Failed to read line");
+      prev = rest;
+
+      input = match editor.read_line(continuation_prompt, session.completion_words().as_slice()) {
+        Some(line) => line,
+        None => {
+          // the items completed before the end of the input are still evaluated
+          session.report_error("unexpected end of input");
+          session.eval(ast.as_slice(), Some(&entry));
+          break 'main
+        }
+      };
+      // Ctrl-C drops the whole input, including the items already completed in it
+      if input.is_empty() {
+        continue 'main
+      }
+      entry.push_str(&input);
     }
+    editor.add_history(&entry);
 
-    if stage == AST {
-      println!("{:?}", ast);
+    if ast.is_empty() {
       continue
     }
 
-    match ast.codegen(&mut builder_context, ir_container.get_module_provider()) {
-      Ok((value, runnable)) => {
-        if runnable && stage == Exec {
-          println!("=> {}", ir_container.run_function(value));
-        } else {
-          // value.dump()
-          unsafe {
-              LLVMDumpValue(value.to_ref())
-          }
-        }
+    session.eval(ast.as_slice(), Some(&entry));
+  }
+
+  session.finish();
+  session.error_count()
+}
+
+/// Runs a whole source text through `stage` and returns what would be printed for it.
+/// The whole input is treated as one chunk, so an incomplete trailing definition is an error.
+/// Only the non-executing stages are supported, Exec is an error; this is what the golden tests
+/// rely on. The IR is not optimized, so that it shows what the builder emits, e.g. which calls
+/// it marks as tail calls.
+pub fn run_stage(stage: Stage, input: &str) -> Result<String, String> {
+  if stage == Exec {
+    return Err("run_stage does not support the Exec stage".to_string())
+  }
+  let mut output = String::new();
+
+  let tokens = tokenize(input);
+  if stage == Tokens {
+    output.push_str(&format!("{:?}\n", tokens));
+    return Ok(output)
+  }
+
+  let mut parser_settings = default_parser_settings();
+  let (ast, rest, errors) = parse(tokens.as_slice(), &[], &mut parser_settings);
+  for message in errors.iter() {
+    output.push_str(&format!("Error: {}\n", message));
+  }
+  if !rest.is_empty() {
+    output.push_str("Error: unexpected end of input\n");
+  }
+
+  if stage == AST {
+    for node in ast.iter() {
+      output.push_str(&format!("{:?}\n", node));
+    }
+    return Ok(output)
+  }
+
+  let mut module_provider = SimpleModuleProvider::new("main", &PassOptions::with_opt_level(0).unwrap());
+  let mut builder_context = Context::new();
+  for node in ast.iter() {
+    if let Err(message) = node.codegen(&mut builder_context, &mut module_provider) {
+      output.push_str(&format!("Error occured: {}\n", message));
+    }
+  }
+  module_provider.run_module_passes();
+  output.push_str(&module_provider.print_to_string());
+  Ok(output)
+}
+
+/// Parses `input` and serializes the resulting AST in an external format.
+pub fn emit_ast(input: &str, format: Format) -> Result<String, Vec<String>> {
+  let mut parser_settings = default_parser_settings();
+  let (ast, rest, mut errors) = parse(tokenize(input).as_slice(), &[], &mut parser_settings);
+  if !rest.is_empty() {
+    errors.push("unexpected end of input".to_string());
+  }
+  if errors.is_empty() {
+    Ok(serialization::serialize(ast.as_slice(), format))
+  } else {
+    Err(errors)
+  }
+}
+
+/// Compiles and runs an AST that was built elsewhere, printing the value of every top-level
+/// expression like the REPL does.
+pub fn run_ast(ast: &[ASTNode], options: &Options) {
+  let mut ir_container = new_ir_container(&Exec, options);
+  let mut builder_context = Context::new();
+  builder_context.inline_across_modules = options.inline_across_modules;
+  builder_context.hot_reload = options.hot_reload;
+  builder_context.interrupt_checks = options.interruptible();
+  if let Some(ref prelude) = options.prelude {
+    load_prelude(prelude, &mut default_parser_settings(), &mut builder_context,
+                 Some(ir_container.get_module_provider()));
+    ir_container.finish_definition();
+  }
+  if options.report_tail_calls {
+    report_tail_calls(ast);
+  }
+
+  for node in ast.iter() {
+    match node.codegen(&mut builder_context, ir_container.get_module_provider()) {
+      Ok((value, runnable)) => if runnable {
+        print_result(ir_container.run_function(value));
+      } else {
+        ir_container.finish_definition();
       },
       Err(message) => println!("Error occured: {}", message)
     }
   }
+}
+
+fn report_tail_calls(ast: &[ASTNode]) {
+  for node in ast.iter() {
+    if let &FunctionNode(ref function) = node {
+      for message in tail_calls::non_tail_recursive_calls(function).iter() {
+        println!("Warning: {}", message);
+      }
+    }
+  }
+}
+
+fn print_result(result: Result<f64, String>) {
+  match result {
+    Ok(value) => println!("=> {}", value),
+    Err(message) => println!("Runtime error: {}", message)
+  }
+}
+
+// Parses the prelude, which defines operators in `parser_settings`, and compiles it unless
+// `module_provider` is None. Errors are reported, but do not stop the session.
+fn load_prelude(source: &str, parser_settings: &mut ParserSettings, context: &mut Context,
+                module_provider: Option<&mut ModuleProvider>) -> Vec<ASTNode> {
+  let (ast, rest, mut errors) = parse(tokenize(source).as_slice(), &[], parser_settings);
+  if !rest.is_empty() {
+    errors.push("unexpected end of input".to_string());
+  }
+
+  if let Some(module_provider) = module_provider {
+    for node in ast.iter() {
+      if let Err(message) = node.codegen(context, module_provider) {
+        errors.push(message);
+      }
+    }
+  }
+
+  // stdout may be the channel of a protocol
+  for message in errors.iter() {
+    let _ = writeln!(io::stderr(), "Error in prelude: {}", message);
+  }
+  ast
+}
+
+fn bitcode_path(path: &str) -> String {
+  format!("{}.bc", path)
+}
 
-  if stage == IR || stage == Exec {
-    ir_container.dump();
+// Whether `path` exists and was modified no earlier than `than`.
+fn is_newer(path: &str, than: &str) -> bool {
+  match (fs::metadata(path), fs::metadata(than)) {
+    (Ok(file), Ok(other)) => (file.mtime(), file.mtime_nsec()) >= (other.mtime(), other.mtime_nsec()),
+    _ => false
   }
 }
@@ -0,0 +1,424 @@
+//! A small line editor for the REPL: cursor movement, history that is kept in a dotfile and
+//! tab completion. The terminal is put into raw mode with termios while a line is edited; when
+//! stdin is not a terminal, lines are read as they are.
+//!
+//! The termios declarations follow the glibc ABI on Linux and are only compiled there; elsewhere
+//! lines are read as they are.
+
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::str;
+
+use libc::c_int;
+
+use self::terminal::RawMode;
+
+const HISTORY_FILE: &'static str = ".kaleidoscope_history";
+const MAX_HISTORY: usize = 1000;
+
+const STDIN: c_int = 0;
+
+extern {
+  fn isatty(fd: c_int) -> c_int;
+}
+
+/// Whether stdin is a terminal.
+pub fn stdin_is_tty() -> bool {
+  unsafe { isatty(STDIN) != 0 }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+mod terminal {
+  use libc::{c_int, c_uint};
+
+  use super::STDIN;
+
+  const TCSADRAIN: c_int = 1;
+  const ISIG: c_uint = 0o1;
+  const ICANON: c_uint = 0o2;
+  const ECHO: c_uint = 0o10;
+  const ICRNL: c_uint = 0o400;
+  const IXON: c_uint = 0o2000;
+  const VTIME: usize = 5;
+  const VMIN: usize = 6;
+
+  #[repr(C)]
+  #[derive(Clone, Copy)]
+  struct Termios {
+    c_iflag: c_uint,
+    c_oflag: c_uint,
+    c_cflag: c_uint,
+    c_lflag: c_uint,
+    c_line: u8,
+    c_cc: [u8; 32],
+    c_ispeed: c_uint,
+    c_ospeed: c_uint
+  }
+
+  extern {
+    fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+    fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+  }
+
+  // Restores the terminal settings when dropped.
+  pub struct RawMode {
+    original: Termios
+  }
+
+  impl RawMode {
+    pub fn enable() -> Option<RawMode> {
+      unsafe {
+        let mut original = Termios { c_iflag: 0, c_oflag: 0, c_cflag: 0, c_lflag: 0, c_line: 0, c_cc: [0; 32],
+                                     c_ispeed: 0, c_ospeed: 0 };
+        if tcgetattr(STDIN, &mut original) != 0 {
+          return None
+        }
+        let mut raw = original;
+        raw.c_iflag &= !(ICRNL | IXON);
+        // Ctrl-C is read as a key and cancels the line
+        raw.c_lflag &= !(ECHO | ICANON | ISIG);
+        raw.c_cc[VMIN] = 1;
+        raw.c_cc[VTIME] = 0;
+        if tcsetattr(STDIN, TCSADRAIN, &raw) != 0 {
+          return None
+        }
+        Some(RawMode { original: original })
+      }
+    }
+  }
+
+  impl Drop for RawMode {
+    fn drop(&mut self) {
+      unsafe { tcsetattr(STDIN, TCSADRAIN, &self.original) };
+    }
+  }
+}
+
+// Elsewhere lines are read as they are, even from a terminal.
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+mod terminal {
+  pub struct RawMode;
+
+  impl RawMode {
+    pub fn enable() -> Option<RawMode> {
+      None
+    }
+  }
+}
+
+pub struct Editor {
+  history: Vec<String>,
+  history_path: Option<PathBuf>,
+  tty: bool
+}
+
+impl Editor {
+  /// Creates an editor with the history saved in the home directory.
+  pub fn new() -> Editor {
+    let history_path = env::home_dir().map(|home| home.join(HISTORY_FILE));
+    let mut history = Vec::new();
+    if let Some(ref path) = history_path {
+      if let Ok(file) = File::open(path) {
+        history = BufReader::new(file).lines().filter_map(|line| line.ok()).collect();
+      }
+    }
+    if history.len() > MAX_HISTORY {
+      let skipped = history.len() - MAX_HISTORY;
+      history = history[skipped..].to_vec();
+    }
+
+    Editor {
+      history: history,
+      history_path: history_path,
+      tty: stdin_is_tty()
+    }
+  }
+
+  /// Adds a complete input to the history and appends it to the history file. Inputs spanning
+  /// several lines are joined, so that they can be recalled and edited as a whole. Once the
+  /// history is full, the oldest entry is dropped and the file is written anew, so that it does
+  /// not grow without bound.
+  pub fn add_history(&mut self, entry: &str) {
+    let entry = entry.lines().map(|line| line.trim()).collect::<Vec<_>>().join(" ");
+    if entry.is_empty() || self.history.last() == Some(&entry) {
+      return
+    }
+    self.history.push(entry);
+
+    let full = self.history.len() > MAX_HISTORY;
+    if full {
+      self.history.remove(0);
+    }
+    if let Some(ref path) = self.history_path {
+      let file = if full {
+        File::create(path)
+      } else {
+        OpenOptions::new().create(true).append(true).open(path)
+      };
+      let entries = if full { &self.history[..] } else { &self.history[self.history.len() - 1..] };
+      if let Ok(mut file) = file {
+        for entry in entries.iter() {
+          let _ = writeln!(file, "{}", entry);
+        }
+      }
+    }
+  }
+
+  /// Reads a line after showing `prompt`; Tab completes the word before the cursor with
+  /// `words`. Returns None at the end of the input. The line ends with a newline, except for
+  /// the empty line returned when Ctrl-C cancels it.
+  pub fn read_line(&mut self, prompt: &str, words: &[String]) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+
+    let raw_mode = if self.tty { RawMode::enable() } else { None };
+    if raw_mode.is_none() {
+      let mut line = String::new();
+      return match io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line)
+      }
+    }
+
+    let result = self.edit(prompt, words);
+    drop(raw_mode);
+    println!("");
+    result
+  }
+
+  fn edit(&mut self, prompt: &str, words: &[String]) -> Option<String> {
+    let mut line = LineBuffer::new(prompt);
+    // position in the history while browsing it, history.len() for the line being edited
+    let mut history_index = self.history.len();
+    let mut edited = String::new();
+
+    let stdin = io::stdin();
+    let mut bytes = stdin.lock().bytes();
+    loop {
+      let byte = match bytes.next() {
+        Some(Ok(byte)) => byte,
+        _ => return None
+      };
+      match byte {
+        b'\r' | b'\n' => return Some(line.text() + "\n"),
+        // Ctrl-C
+        3 => {
+          print!("^C");
+          return Some(String::new())
+        },
+        // Ctrl-D
+        4 => {
+          if line.is_empty() {
+            return None
+          }
+          line.delete();
+        },
+        // Ctrl-A, Ctrl-E, Ctrl-B, Ctrl-F
+        1 => line.move_to(0),
+        5 => line.move_to_end(),
+        2 => line.move_left(),
+        6 => line.move_right(),
+        // Ctrl-K, Ctrl-U
+        11 => line.kill_to_end(),
+        21 => line.kill_to_start(),
+        b'\t' => line.complete(words),
+        8 | 127 => line.backspace(),
+        27 => {
+          let sequence = (bytes.next(), bytes.next());
+          match sequence {
+            (Some(Ok(b'[')), Some(Ok(b'A'))) | (Some(Ok(b'[')), Some(Ok(b'B'))) => {
+              let up = match sequence.1 { Some(Ok(b'A')) => true, _ => false };
+              if history_index == self.history.len() {
+                edited = line.text();
+              }
+              if up && history_index > 0 {
+                history_index -= 1;
+              } else if !up && history_index < self.history.len() {
+                history_index += 1;
+              } else {
+                continue
+              }
+              let text = if history_index == self.history.len() {
+                edited.clone()
+              } else {
+                self.history[history_index].clone()
+              };
+              line.set_text(&text);
+            },
+            (Some(Ok(b'[')), Some(Ok(b'C'))) => line.move_right(),
+            (Some(Ok(b'[')), Some(Ok(b'D'))) => line.move_left(),
+            (Some(Ok(b'[')), Some(Ok(b'H'))) => line.move_to(0),
+            (Some(Ok(b'[')), Some(Ok(b'F'))) => line.move_to_end(),
+            (Some(Ok(b'[')), Some(Ok(b'3'))) => {
+              // Delete sends ESC [ 3 ~
+              bytes.next();
+              line.delete();
+            },
+            _ => ()
+          }
+        },
+        byte if byte >= 32 && byte < 127 => line.insert(byte as char),
+        byte if byte >= 128 => if let Some(c) = read_utf8(byte, &mut bytes) {
+          line.insert(c);
+        },
+        _ => ()
+      }
+      line.refresh();
+    }
+  }
+}
+
+// Decodes the character whose UTF-8 encoding starts with `first`, reading the rest of it from
+// `bytes`. None for an invalid encoding, which is then dropped.
+fn read_utf8<I: Iterator<Item=io::Result<u8>>>(first: u8, bytes: &mut I) -> Option<char> {
+  let length = match first {
+    0xc0...0xdf => 2,
+    0xe0...0xef => 3,
+    0xf0...0xf7 => 4,
+    _ => return None
+  };
+  let mut encoding = vec![first];
+  for _ in 1..length {
+    match bytes.next() {
+      Some(Ok(byte)) if byte & 0xc0 == 0x80 => encoding.push(byte),
+      _ => return None
+    }
+  }
+  str::from_utf8(&encoding).ok().and_then(|text| text.chars().next())
+}
+
+struct LineBuffer {
+  prompt: String,
+  chars: Vec<char>,
+  cursor: usize
+}
+
+impl LineBuffer {
+  fn new(prompt: &str) -> LineBuffer {
+    LineBuffer {
+      prompt: prompt.to_string(),
+      chars: Vec::new(),
+      cursor: 0
+    }
+  }
+
+  fn text(&self) -> String {
+    self.chars.iter().cloned().collect()
+  }
+
+  fn is_empty(&self) -> bool {
+    self.chars.is_empty()
+  }
+
+  fn set_text(&mut self, text: &str) {
+    self.chars = text.chars().collect();
+    self.cursor = self.chars.len();
+  }
+
+  fn insert(&mut self, c: char) {
+    self.chars.insert(self.cursor, c);
+    self.cursor += 1;
+  }
+
+  fn backspace(&mut self) {
+    if self.cursor > 0 {
+      self.cursor -= 1;
+      self.chars.remove(self.cursor);
+    }
+  }
+
+  fn delete(&mut self) {
+    if self.cursor < self.chars.len() {
+      self.chars.remove(self.cursor);
+    }
+  }
+
+  fn move_to(&mut self, position: usize) {
+    self.cursor = position;
+  }
+
+  fn move_to_end(&mut self) {
+    self.cursor = self.chars.len();
+  }
+
+  fn move_left(&mut self) {
+    if self.cursor > 0 {
+      self.cursor -= 1;
+    }
+  }
+
+  fn move_right(&mut self) {
+    if self.cursor < self.chars.len() {
+      self.cursor += 1;
+    }
+  }
+
+  fn kill_to_end(&mut self) {
+    self.chars.truncate(self.cursor);
+  }
+
+  fn kill_to_start(&mut self) {
+    self.chars = self.chars[self.cursor..].to_vec();
+    self.cursor = 0;
+  }
+
+  // Completes the word before the cursor as far as all matching words agree, and lists them
+  // if that does not add anything.
+  fn complete(&mut self, words: &[String]) {
+    let (start, candidates) = completions(words, &self.chars, self.cursor);
+    let prefix = self.chars[start..self.cursor].iter().cloned().collect::<String>();
+    if candidates.is_empty() {
+      return
+    }
+
+    let common = candidates.iter().skip(1).fold(candidates[0].to_string(), |common, word| {
+      common.chars().zip(word.chars()).take_while(|&(a, b)| a == b).map(|(a, _)| a).collect()
+    });
+    if common.len() > prefix.len() {
+      for c in common[prefix.len()..].chars() {
+        self.insert(c);
+      }
+    } else if candidates.len() > 1 {
+      print!("\n");
+      for word in candidates.iter() {
+        print!("{}  ", word);
+      }
+      print!("\n");
+    }
+  }
+
+  fn refresh(&self) {
+    let text = self.text();
+    let before_cursor = self.chars[..self.cursor].iter().cloned().collect::<String>();
+    print!("\r{}{}\x1b[K\r", self.prompt, text);
+    let column = display_width(&self.prompt, 0);
+    let column = display_width(&before_cursor, column);
+    if column > 0 {
+      print!("\x1b[{}C", column);
+    }
+    io::stdout().flush().unwrap();
+  }
+}
+
+/// Words completing the word that ends at `cursor` in `chars`, sorted and without duplicates,
+/// and the index where that word starts.
+pub fn completions(words: &[String], chars: &[char], cursor: usize) -> (usize, Vec<String>) {
+  let start = chars[..cursor].iter()
+    .rposition(|c| !(c.is_alphanumeric() || *c == '_' || *c == ':'))
+    .map(|i| i + 1)
+    .unwrap_or(0);
+  let prefix = chars[start..cursor].iter().cloned().collect::<String>();
+  let mut candidates = words.iter().filter(|word| word.starts_with(&prefix)).cloned().collect::<Vec<_>>();
+  candidates.sort();
+  candidates.dedup();
+  (start, candidates)
+}
+
+// Column reached after printing `text` from `column`, with tabs stopping every 8 columns as in
+// the continuation prompt.
+fn display_width(text: &str, column: usize) -> usize {
+  text.chars().fold(column, |column, c| if c == '\t' { (column / 8 + 1) * 8 } else { column + 1 })
+}
@@ -0,0 +1,295 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::iter;
+
+use lexer::*;
+use parser::*;
+use printer::{operand_needs_parens, pretty, unary_operand_needs_parens};
+
+const MAX_WIDTH: usize = 80;
+const INDENT: &'static str = "  ";
+
+// A top-level item: the tokens from `def`/`extern` or the start of an expression up to the
+// closing `;`, with the source lines they span.
+struct Item {
+  tokens: Vec<Token>,
+  first_line: usize,
+  last_line: usize
+}
+
+struct Comment {
+  text: String,
+  line: usize
+}
+
+/// Re-lays out Kaleidoscope source. Conditionals and loops in body position are always broken
+/// over several lines, calls that do not fit in `MAX_WIDTH` columns get one argument per line,
+/// wherever they are, and everything else is printed on one line by the pretty-printer.
+/// Comments are kept: a comment after the last item on a line stays at the end of that line and
+/// any other comment is placed before the item it was found in or in front of.
+pub fn format_source(source: &str) -> Result<String, Vec<String>> {
+  let (items, comments, blank_lines) = split_source(source);
+
+  let mut settings = default_parser_settings();
+  let mut output = String::new();
+  let mut errors = Vec::new();
+  let mut comments = comments.into_iter().peekable();
+  let mut prev_line = 0;
+
+  for (index, item) in items.iter().enumerate() {
+    let (ast, rest, item_errors) = parse(item.tokens.as_slice(), &[], &mut settings);
+    for message in item_errors.iter() {
+      errors.push(format!("line {}: {}", item.first_line, message));
+    }
+    if !rest.is_empty() {
+      errors.push(format!("line {}: unexpected end of input", item.first_line));
+    }
+
+    if !output.is_empty() && blank_lines.iter().any(|l| *l > prev_line && *l < item.first_line) {
+      output.push('\n');
+    }
+
+    // a comment on a line where the next item starts belongs to that one
+    let last_line = match items.get(index + 1) {
+      Some(next) if next.first_line == item.last_line => item.last_line - 1,
+      _ => item.last_line
+    };
+    let mut trailing = None;
+    while comments.peek().map_or(false, |c| c.line <= last_line) {
+      let comment = comments.next().unwrap();
+      if comment.line == item.last_line && comment.line >= item.first_line {
+        trailing = Some(comment.text);
+      } else {
+        output.push_str(&comment.text);
+        output.push('\n');
+      }
+    }
+
+    for (i, node) in ast.iter().enumerate() {
+      output.push_str(&format_node(node, &settings));
+      if i + 1 == ast.len() {
+        if let Some(ref text) = trailing {
+          output.push(' ');
+          output.push_str(text);
+        }
+      }
+      output.push('\n');
+    }
+    prev_line = item.last_line;
+  }
+
+  for comment in comments {
+    if !output.is_empty() && blank_lines.iter().any(|l| *l > prev_line && *l < comment.line) {
+      output.push('\n');
+    }
+    output.push_str(&comment.text);
+    output.push('\n');
+    prev_line = comment.line;
+  }
+
+  if errors.is_empty() {
+    Ok(output)
+  } else {
+    Err(errors)
+  }
+}
+
+/// Formats the given files in place, or with `check` only reports the files that are not
+/// formatted. Returns the exit status for the `fmt` command.
+pub fn format_files(paths: &[String], check: bool) -> i32 {
+  let mut status = 0;
+  for path in paths.iter() {
+    let mut source = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut source)) {
+      println!("{}: {}", path, e);
+      status = 2;
+      continue
+    }
+
+    let formatted = match format_source(&source) {
+      Ok(formatted) => formatted,
+      Err(errors) => {
+        for message in errors.iter() {
+          println!("{}: {}", path, message);
+        }
+        status = 2;
+        continue
+      }
+    };
+
+    if formatted == source {
+      continue
+    }
+
+    if check {
+      println!("{} is not formatted", path);
+      if status == 0 {
+        status = 1;
+      }
+    } else if let Err(e) = File::create(path).and_then(|mut f| f.write_all(formatted.as_bytes())) {
+      println!("{}: {}", path, e);
+      status = 2;
+    }
+  }
+  status
+}
+
+fn split_source(source: &str) -> (Vec<Item>, Vec<Comment>, Vec<usize>) {
+  let mut items = Vec::new();
+  let mut comments = Vec::new();
+  let mut blank_lines = Vec::new();
+  let mut current: Option<Item> = None;
+
+  for (i, line) in source.lines().enumerate() {
+    let line_no = i + 1;
+    let code = match line.find('#') {
+      Some(pos) => {
+        comments.push(Comment{text: line[pos..].trim_right().to_string(), line: line_no});
+        &line[..pos]
+      },
+      None => line
+    };
+    if line.trim().is_empty() {
+      blank_lines.push(line_no);
+    }
+
+    for token in tokenize(code).into_iter() {
+      let starts_item = match token {
+        Def | Extern => true,
+        _ => false
+      };
+      if starts_item && current.is_some() {
+        items.push(current.take().unwrap());
+      }
+
+      let is_delimiter = token == Delimiter;
+      match current {
+        Some(ref mut item) => {
+          item.tokens.push(token);
+          item.last_line = line_no;
+        },
+        None => current = Some(Item{tokens: vec![token], first_line: line_no, last_line: line_no})
+      }
+      if is_delimiter {
+        items.push(current.take().unwrap());
+      }
+    }
+  }
+  if let Some(item) = current {
+    items.push(item);
+  }
+
+  (items, comments, blank_lines)
+}
+
+fn format_node(node: &ASTNode, settings: &ParserSettings) -> String {
+  match node {
+    &ExternNode(_) => format!("{}", pretty(node, settings)),
+    &FunctionNode(ref function) if function.prototype.name == "" => {
+      layout_expr(&function.expression, 0, 0, 1, settings) + ";"
+    },
+    &FunctionNode(ref function) => {
+      let header = format!("def {}", pretty(&function.prototype, settings));
+      let body = layout_expr(&function.expression, 1, indentation(1).len(), 1, settings);
+      if !body.contains('\n') && fits(header.len() + 1, &body, 1) {
+        format!("{} {};", header, body)
+      } else {
+        format!("{}\n{}{};", header, indentation(1), body)
+      }
+    }
+  }
+}
+
+// Whether `text` fits on a line after `column` columns with `trailing` more to follow it.
+fn fits(column: usize, text: &str, trailing: usize) -> bool {
+  column + text.len() + trailing <= MAX_WIDTH
+}
+
+// Lays out an expression in body position that starts at `column` and is followed by
+// `trailing` characters on its last line. The first line is not indented, the following ones
+// are indented absolutely.
+fn layout_expr(expr: &Expression, level: usize, column: usize, trailing: usize, settings: &ParserSettings) -> String {
+  match expr {
+    &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+      let else_part = match **else_expr {
+        ConditionalExpr{..} => {
+          format!("else {}", layout_expr(else_expr, level, indentation(level).len() + 5, trailing, settings))
+        },
+        _ => format!("else\n{}{}", indentation(level + 1),
+                     layout_expr(else_expr, level + 1, indentation(level + 1).len(), trailing, settings))
+      };
+      format!("if {} then\n{}{}\n{}{}",
+              layout_inline(cond_expr, level, column + 3, 5, settings),
+              indentation(level + 1), layout_expr(then_expr, level + 1, indentation(level + 1).len(), 0, settings),
+              indentation(level), else_part)
+    },
+
+    &LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+      let mut header = format!("for {} = {}, {}", var_name, pretty(&**start_expr, settings), pretty(&**end_expr, settings));
+      if **step_expr != LiteralExpr(1.0) {
+        header.push_str(&format!(", {}", pretty(&**step_expr, settings)));
+      }
+      format!("{} in\n{}{}", header, indentation(level + 1),
+              layout_expr(body_expr, level + 1, indentation(level + 1).len(), trailing, settings))
+    },
+
+    _ => layout_inline(expr, level, column, trailing, settings)
+  }
+}
+
+// Lays out an expression that is not in body position, so that only the calls in it that do
+// not fit are broken and conditionals and loops stay on one line.
+fn layout_inline(expr: &Expression, level: usize, column: usize, trailing: usize, settings: &ParserSettings) -> String {
+  let inline = format!("{}", pretty(expr, settings));
+  if fits(column, &inline, trailing) {
+    return inline
+  }
+
+  match expr {
+    &CallExpr(ref name, ref args) if !args.is_empty() => {
+      let args = args.iter()
+        .map(|arg| format!("{}{}", indentation(level + 1),
+                           layout_expr(arg, level + 1, indentation(level + 1).len(), 1, settings)))
+        .collect::<Vec<_>>();
+      format!("{}(\n{}\n{})", name, args.join(",\n"), indentation(level))
+    },
+
+    &BinaryExpr(ref op, ref lhs, ref rhs) => {
+      let precedence = settings.precedence(op);
+      let lhs = layout_operand(lhs, operand_needs_parens(lhs, settings, precedence, false),
+                               level, column, op.len() + 2, settings);
+      let column = end_column(&lhs, column) + op.len() + 2;
+      let rhs = layout_operand(rhs, operand_needs_parens(rhs, settings, precedence, true),
+                               level, column, trailing, settings);
+      format!("{} {} {}", lhs, op, rhs)
+    },
+
+    &UnaryExpr(ref op, ref operand) => {
+      format!("{}{}", op, layout_operand(operand, unary_operand_needs_parens(operand),
+                                         level, column + op.len(), trailing, settings))
+    },
+
+    _ => inline
+  }
+}
+
+fn layout_operand(operand: &Expression, parenthesize: bool, level: usize, column: usize, trailing: usize,
+                  settings: &ParserSettings) -> String {
+  if parenthesize {
+    format!("({})", layout_inline(operand, level, column + 1, trailing + 1, settings))
+  } else {
+    layout_inline(operand, level, column, trailing, settings)
+  }
+}
+
+// Column at the end of `text` that starts at `column`.
+fn end_column(text: &str, column: usize) -> usize {
+  match text.rfind('\n') {
+    Some(pos) => text.len() - pos - 1,
+    None => column + text.len()
+  }
+}
+
+fn indentation(level: usize) -> String {
+  iter::repeat(INDENT).take(level).collect()
+}
@@ -0,0 +1,293 @@
+//! Runs generated code so that a fault in it does not take the REPL down with it. The code is
+//! called on its own thread with a stack of a given size. Handlers for SIGSEGV, SIGBUS and SIGFPE
+//! run on an alternate stack, so that they work after a stack overflow too, and jump back out of
+//! the generated code with `siglongjmp`.
+//!
+//! Generated code can also be interrupted by Ctrl-C or when it runs out of time. Both only set a
+//! flag, which the code polls at function entries and loop back-edges; when it is set, the code
+//! calls `interrupted` to jump out the same way.
+//!
+//! The flag and the handlers are shared by the whole process, so guarded runs take turns. The
+//! fault handlers are only installed while a run is going on: the rest of the time the previous
+//! ones are in place, i.e. the handler Rust reports stack overflows with. A fault on another
+//! thread during a run is passed on to them.
+//!
+//! `siglongjmp` skips the frames between `call_guarded` and the fault or `interrupted`, without
+//! unwinding them. These are frames of generated code, which own nothing, and of the handlers
+//! below, so only generated code may be run guarded, never Rust code holding resources.
+//!
+//! The declarations of the signal handling follow the glibc ABI on x86_64 Linux and are only
+//! compiled there; musl lays out the same structures differently. Elsewhere generated code
+//! still runs on its own thread, but unguarded: a fault ends the process, and neither Ctrl-C
+//! nor a timeout interrupts it.
+
+use std::sync::{StaticMutex, MUTEX_INIT};
+use std::sync::atomic::{AtomicBool, AtomicUsize, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT, Ordering};
+use std::thread;
+
+/// Stack size used for generated code unless another one is given.
+pub const DEFAULT_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Names under which the interrupt flag and `interrupted` are known to generated code.
+pub const INTERRUPT_FLAG_SYMBOL: &'static str = "kaleidoscope.interrupt";
+pub const INTERRUPTED_SYMBOL: &'static str = "kaleidoscope.interrupted";
+
+/// Limits for running generated code.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct Limits {
+  // stack size in bytes, 0 for `DEFAULT_STACK_SIZE`
+  pub stack_size: usize,
+  // time in milliseconds after which an evaluation is interrupted, 0 for no limit
+  pub timeout: u32
+}
+
+// reasons for an interrupt, stored in INTERRUPT
+const CTRL_C: usize = 1;
+const TIMED_OUT: usize = 2;
+
+// Polled by generated code, nonzero to interrupt it. Only read as a whole machine word.
+static INTERRUPT: AtomicUsize = ATOMIC_USIZE_INIT;
+static RUNNING: AtomicBool = ATOMIC_BOOL_INIT;
+// whether Ctrl-C outside of generated code is ignored instead of ending the process
+static KEEP_RUNNING: AtomicBool = ATOMIC_BOOL_INIT;
+// counts evaluations, so that a watchdog of a finished one does not interrupt the next
+static EVALUATION: AtomicUsize = ATOMIC_USIZE_INIT;
+// held for the whole of a guarded run
+static RUNS: StaticMutex = MUTEX_INIT;
+
+/// Address of the flag polled by generated code.
+pub fn interrupt_flag() -> *const () {
+  &INTERRUPT as *const AtomicUsize as *const ()
+}
+
+/// Called by generated code that found the interrupt flag set. Does not return.
+pub extern fn interrupted() {
+  // negative values tell interrupts apart from signals
+  signals::jump_out(-(INTERRUPT.load(Ordering::SeqCst) as i32));
+}
+
+/// Makes SIGINT only interrupt generated code, for processes that are sent it by a front-end
+/// rather than by Ctrl-C.
+pub fn ignore_idle_interrupts() {
+  KEEP_RUNNING.store(true, Ordering::SeqCst);
+  signals::install_interrupt_handler();
+}
+
+/// Calls `function`, which must be generated code, on a new thread within `limits`. A fault in
+/// the function or an interrupt is returned as an error. Runs started by several threads at
+/// once are run one after the other.
+pub fn run_guarded(function: extern "C" fn() -> f64, limits: &Limits) -> Result<f64, String> {
+  // a run that panicked has restored everything it changed
+  let _turn = RUNS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+  signals::install_interrupt_handler();
+
+  INTERRUPT.store(0, Ordering::SeqCst);
+  let evaluation = EVALUATION.fetch_add(1, Ordering::SeqCst) + 1;
+  RUNNING.store(true, Ordering::SeqCst);
+
+  if limits.timeout != 0 && signals::SUPPORTED {
+    let timeout = limits.timeout;
+    thread::spawn(move || {
+      thread::sleep_ms(timeout);
+      if EVALUATION.load(Ordering::SeqCst) == evaluation && RUNNING.load(Ordering::SeqCst) {
+        INTERRUPT.store(TIMED_OUT, Ordering::SeqCst);
+      }
+    });
+  }
+
+  let stack_size = if limits.stack_size == 0 { DEFAULT_STACK_SIZE } else { limits.stack_size };
+  let thread = thread::Builder::new()
+    .name("kaleidoscope".to_string())
+    .stack_size(stack_size)
+    .spawn(move || unsafe { signals::call_guarded(function) });
+
+  let result = match thread {
+    Ok(thread) => match thread.join() {
+      Ok(result) => result,
+      Err(_) => Err("generated code panicked".to_string())
+    },
+    Err(e) => Err(format!("cannot start a thread for generated code: {}", e))
+  };
+
+  RUNNING.store(false, Ordering::SeqCst);
+  INTERRUPT.store(0, Ordering::SeqCst);
+  result
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64", target_env = "gnu"))]
+mod signals {
+  use std::cell::Cell;
+  use std::mem;
+  use std::ptr;
+  use std::sync::{Once, ONCE_INIT};
+  use std::sync::atomic::Ordering;
+
+  use libc::{c_int, c_void, size_t};
+
+  use super::{CTRL_C, INTERRUPT, KEEP_RUNNING, RUNNING};
+
+  pub const SUPPORTED: bool = true;
+
+  const ALT_STACK_SIZE: usize = 64 * 1024;
+
+  const SIGINT: c_int = 2;
+  const SIGBUS: c_int = 7;
+  const SIGFPE: c_int = 8;
+  const SIGSEGV: c_int = 11;
+  const FAULTS: [c_int; 3] = [SIGSEGV, SIGBUS, SIGFPE];
+
+  const SA_SIGINFO: c_int = 0x4;
+  const SA_ONSTACK: c_int = 0x08000000;
+  const SIG_DFL: usize = 0;
+
+  #[repr(C)]
+  struct SigAction {
+    sa_sigaction: usize,
+    sa_mask: [u64; 16],
+    sa_flags: c_int,
+    sa_restorer: usize
+  }
+
+  #[repr(C)]
+  struct SignalStack {
+    ss_sp: *mut c_void,
+    ss_flags: c_int,
+    ss_size: size_t
+  }
+
+  // sigjmp_buf is 200 bytes
+  #[repr(C)]
+  struct SigJmpBuf {
+    buffer: [u64; 25]
+  }
+
+  extern {
+    fn sigaction(signal: c_int, action: *const SigAction, old_action: *mut SigAction) -> c_int;
+    fn sigaltstack(stack: *const SignalStack, old_stack: *mut SignalStack) -> c_int;
+    // sigsetjmp is a macro for this in glibc
+    fn __sigsetjmp(env: *mut SigJmpBuf, save_mask: c_int) -> c_int;
+    fn siglongjmp(env: *mut SigJmpBuf, value: c_int) -> !;
+    fn raise(signal: c_int) -> c_int;
+  }
+
+  thread_local!(static JUMP_BUFFER: Cell<*mut SigJmpBuf> = Cell::new(ptr::null_mut()));
+
+  static INSTALL_INTERRUPT_HANDLER: Once = ONCE_INIT;
+
+  // the fault handlers in place before the current run, in the order of FAULTS
+  static mut PREVIOUS_HANDLERS: [SigAction; 3] = [
+    SigAction { sa_sigaction: SIG_DFL, sa_mask: [0; 16], sa_flags: 0, sa_restorer: 0 },
+    SigAction { sa_sigaction: SIG_DFL, sa_mask: [0; 16], sa_flags: 0, sa_restorer: 0 },
+    SigAction { sa_sigaction: SIG_DFL, sa_mask: [0; 16], sa_flags: 0, sa_restorer: 0 }
+  ];
+
+  extern fn handle_fault(signal: c_int, _info: *mut c_void, _context: *mut c_void) {
+    let buffer = JUMP_BUFFER.with(|buffer| buffer.get());
+    unsafe {
+      if buffer.is_null() {
+        // not in generated code: put the previous handler back, which gets the signal when
+        // the faulting instruction is executed again
+        if let Some(i) = FAULTS.iter().position(|fault| *fault == signal) {
+          sigaction(signal, &PREVIOUS_HANDLERS[i], ptr::null_mut());
+        }
+        return
+      }
+      siglongjmp(buffer, signal)
+    }
+  }
+
+  extern fn handle_interrupt(signal: c_int, _info: *mut c_void, _context: *mut c_void) {
+    if RUNNING.load(Ordering::SeqCst) {
+      INTERRUPT.store(CTRL_C, Ordering::SeqCst);
+    } else if !KEEP_RUNNING.load(Ordering::SeqCst) {
+      // Ctrl-C outside of generated code ends the session as usual
+      unsafe {
+        set_handler(signal, SIG_DFL, 0, ptr::null_mut());
+        raise(signal);
+      }
+    }
+  }
+
+  pub fn jump_out(value: c_int) {
+    let buffer = JUMP_BUFFER.with(|buffer| buffer.get());
+    if !buffer.is_null() {
+      unsafe { siglongjmp(buffer, value) }
+    }
+  }
+
+  unsafe fn set_handler(signal: c_int, handler: usize, flags: c_int, previous: *mut SigAction) {
+    let action = SigAction {
+      sa_sigaction: handler,
+      sa_mask: [0; 16],
+      sa_flags: flags,
+      sa_restorer: 0
+    };
+    sigaction(signal, &action, previous);
+  }
+
+  fn describe(signal: c_int) -> String {
+    match signal {
+      SIGSEGV => "segmentation fault (stack overflow or invalid memory access)".to_string(),
+      SIGBUS => "bus error".to_string(),
+      SIGFPE => "floating point exception".to_string(),
+      -1 => "interrupted".to_string(),
+      -2 => "timed out".to_string(),
+      _ => format!("signal {}", signal)
+    }
+  }
+
+  pub fn install_interrupt_handler() {
+    INSTALL_INTERRUPT_HANDLER.call_once(|| unsafe {
+      set_handler(SIGINT, handle_interrupt as usize, SA_SIGINFO, ptr::null_mut());
+    });
+  }
+
+  // Must not be inlined: __sigsetjmp returns twice, and the frame it saves has to stay alive
+  // while the function runs. Only called by one thread at a time.
+  #[inline(never)]
+  pub unsafe fn call_guarded(function: extern "C" fn() -> f64) -> Result<f64, String> {
+    let mut alt_stack = vec![0u8; ALT_STACK_SIZE];
+    let stack = SignalStack {
+      ss_sp: alt_stack.as_mut_ptr() as *mut c_void,
+      ss_flags: 0,
+      ss_size: ALT_STACK_SIZE as size_t
+    };
+    let mut previous_stack: SignalStack = mem::zeroed();
+    sigaltstack(&stack, &mut previous_stack);
+    for (i, signal) in FAULTS.iter().enumerate() {
+      set_handler(*signal, handle_fault as usize, SA_SIGINFO | SA_ONSTACK, &mut PREVIOUS_HANDLERS[i]);
+    }
+
+    let mut buffer: SigJmpBuf = mem::zeroed();
+    JUMP_BUFFER.with(|jump_buffer| jump_buffer.set(&mut buffer));
+    let signal = __sigsetjmp(&mut buffer, 1);
+    let result = if signal == 0 {
+      Ok(function())
+    } else {
+      Err(describe(signal))
+    };
+    JUMP_BUFFER.with(|jump_buffer| jump_buffer.set(ptr::null_mut()));
+
+    for (i, signal) in FAULTS.iter().enumerate() {
+      sigaction(*signal, &PREVIOUS_HANDLERS[i], ptr::null_mut());
+    }
+    sigaltstack(&previous_stack, ptr::null_mut());
+    result
+  }
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64", target_env = "gnu")))]
+mod signals {
+  pub const SUPPORTED: bool = false;
+
+  pub fn install_interrupt_handler() {
+  }
+
+  // nothing sets the interrupt flag, so generated code never calls this
+  pub fn jump_out(_value: i32) {
+  }
+
+  pub unsafe fn call_guarded(function: extern "C" fn() -> f64) -> Result<f64, String> {
+    Ok(function())
+  }
+}
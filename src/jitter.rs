@@ -1,86 +1,266 @@
 use std;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CString;
+use std::mem;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::sync::{Mutex, Once, ONCE_INIT};
 
 use iron_llvm::{LLVMRefCtor, LLVMRef};
 use iron_llvm::core;
 use iron_llvm::core::value::{Function, FunctionRef, Value, FunctionCtor};
-use iron_llvm::core::types::{RealTypeRef, RealTypeCtor, FunctionType, FunctionTypeRef};
+use iron_llvm::core::types::{FunctionType, FunctionTypeRef};
 use iron_llvm::execution_engine::{BindingSectionMemoryManagerBuilder, ExecutionEngine, MCJITBuilder};
 use iron_llvm::execution_engine::execution_engine::FrozenModule;
 use iron_llvm::support::add_symbol;
 
+use builder::SLOT_SUFFIX;
+use guard;
+use timing::{Stats, Timer};
+use builtins;
 use module;
 use module::ModuleProvider;
+use passes::{ModulePassManager, PassOptions};
 
-use llvm_sys::prelude::LLVMValueRef;
+use llvm_sys::execution_engine::{LLVMGetFunctionAddress, LLVMGetPointerToGlobal};
+use llvm_sys::prelude::{LLVMTypeRef, LLVMValueRef};
 
 pub extern fn printd(x: f64) -> f64 {
-  println!("> {} <", x);
+  write_output(&format!("> {} <\n", x));
   x
 }
 
 pub extern fn putchard(x: f64) -> f64 {
-  print!("{}", x as u8 as char);
+  write_output(&(x as u8 as char).to_string());
   x
 }
 
+// Output of printd and putchard, collected instead of printed while capturing. Generated code
+// runs on its own thread, so this cannot be thread local.
+static mut CAPTURED: *const Mutex<Option<String>> = 0 as *const Mutex<Option<String>>;
+static CAPTURED_INIT: Once = ONCE_INIT;
+
+fn captured() -> &'static Mutex<Option<String>> {
+  unsafe {
+    CAPTURED_INIT.call_once(|| CAPTURED = mem::transmute(Box::new(Mutex::new(None::<String>))));
+    &*CAPTURED
+  }
+}
+
+fn write_output(text: &str) {
+  match *captured().lock().unwrap() {
+    Some(ref mut output) => output.push_str(text),
+    None => print!("{}", text)
+  }
+}
+
+/// Makes printd and putchard collect their output until `take_output` instead of printing it.
+pub fn capture_output() {
+  *captured().lock().unwrap() = Some(String::new());
+}
+
+/// Output collected since `capture_output`. Printing resumes afterwards.
+pub fn take_output() -> String {
+  captured().lock().unwrap().take().unwrap_or(String::new())
+}
+
 pub fn init() {
   unsafe {
     add_symbol("printd", printd as *const ());
     add_symbol("putchard", putchard as *const ());
+    add_symbol(guard::INTERRUPT_FLAG_SYMBOL, guard::interrupt_flag());
+    add_symbol(guard::INTERRUPTED_SYMBOL, guard::interrupted as *const ());
   }
+  builtins::register_symbols();
 }
 
 pub trait JITter : ModuleProvider {
   // TODO: fix https://github.com/rust-lang/rust/issues/5665
   fn get_module_provider(&mut self) -> &mut ModuleProvider;
-  fn run_function(&mut self, f: LLVMValueRef) -> f64;
+  // Returns an error instead of crashing if the generated code faults.
+  fn run_function(&mut self, f: LLVMValueRef) -> Result<f64, String>;
+  // Makes the functions of a module compiled elsewhere, e.g. loaded from bitcode, callable.
+  fn add_compiled_module(&mut self, module: core::Module) -> Result<(), String>;
+  // Ends the module holding the definitions generated so far, so that every definition can be
+  // compiled on its own when it is first needed.
+  fn finish_definition(&mut self);
+  // Frees the modules of definitions that were all replaced since. Values of their functions
+  // must not be used anymore.
+  fn remove_unused_modules(&mut self);
+  // Time spent in module passes, finalization and execution since the last call, the number
+  // of modules held and how many of them were compiled to machine code.
+  fn take_stats(&mut self) -> Stats;
+}
+
+// A closed module with the execution engine owning it. Machine code is only generated when
+// one of its functions is first looked up, e.g. to be called by generated code.
+struct EngineModule {
+  engine: ExecutionEngine,
+  module: FrozenModule,
+  // functions defined in the module, anonymous ones excluded
+  functions: Vec<String>,
+  compiled: bool
+}
+
+impl EngineModule {
+  // Frees the machine code and the module, which belongs to the engine.
+  fn remove(self) {
+    let EngineModule { engine, module, .. } = self;
+    mem::forget(module);
+    drop(engine);
+  }
 }
 
 struct ModulesContainer {
-  execution_engines: Vec<ExecutionEngine>,
-  modules: Vec<FrozenModule>
+  // closed modules by the order they were closed in
+  modules: BTreeMap<usize, EngineModule>,
+  next_id: usize,
+  // module holding the latest definition of every function
+  definitions: HashMap<String, usize>,
+  // types of the functions declared by closed modules, e.g. externs, to declare them again in
+  // the modules that call them
+  declarations: HashMap<String, LLVMTypeRef>,
+  // addresses of the functions of compiled modules
+  symbols: HashMap<String, u64>,
+  // modules whose definitions were all replaced, to be removed
+  superseded: Vec<usize>,
+  // Stub table for hot reload: one slot per function holding the address of its latest
+  // definition. Calls compiled with hot reload on load the callee from here.
+  slots: HashMap<String, Box<u64>>
 }
 
 impl ModulesContainer {
-  fn get_function_address(&self, name: &str) -> u64 {
-    for ee in &self.execution_engines {
-      let addr = ee.get_function_address(name);
-      if addr != 0 {
-        return addr;
-      }
+  // The latest compiled definition of function `name`, without going through every module.
+  fn get_definition(&self, name: &str) -> Option<FunctionRef> {
+    self.definitions.get(name)
+      .and_then(|id| self.modules.get(id))
+      .and_then(|module| module.module.get().get_function_by_name(name))
+  }
+
+  // Whether all functions of module `id` were redefined later and are only called through
+  // their slots, which the redefinitions took over, so that nothing refers to the module.
+  fn is_superseded(&self, id: usize) -> bool {
+    self.modules[&id].functions.iter()
+      .all(|name| self.definitions.get(name) != Some(&id) && self.slots.contains_key(name))
+  }
+}
+
+// Address of function `name` or of a hot reload slot, compiling the module with the latest
+// definition of the function if that was not done yet.
+fn function_address(container: &RefCell<ModulesContainer>, name: &str) -> u64 {
+  let id = {
+    let container = container.borrow();
+    if name.ends_with(SLOT_SUFFIX) {
+      let function = &name[..name.len() - SLOT_SUFFIX.len()];
+      return container.slots.get(function).map(|slot| &**slot as *const u64 as u64).unwrap_or(0)
+    }
+    if let Some(addr) = container.symbols.get(name) {
+      return *addr
+    }
+    match container.definitions.get(name) {
+      Some(id) => *id,
+      None => return 0
+    }
+  };
+  compile_module(container, id);
+  container.borrow().symbols.get(name).map(|addr| *addr).unwrap_or(0)
+}
+
+// Generates machine code for module `id` and records the addresses of its functions. Symbols
+// it refers to are resolved through `function_address`, which compiles the modules defining
+// them in turn. Later modules only refer to earlier ones, so this always ends.
+fn compile_module(container: &RefCell<ModulesContainer>, id: usize) {
+  let (engine, functions) = {
+    let mut container = container.borrow_mut();
+    let module = match container.modules.get_mut(&id) {
+      Some(module) => module,
+      None => return
+    };
+    if module.compiled {
+      return
+    }
+    module.compiled = true;
+    (module.engine.to_ref(), module.functions.clone())
+  };
+
+  // looking the first address up finalizes the module, which resolves symbols through the
+  // container, so it must not be borrowed meanwhile
+  let addresses = functions.into_iter()
+    .map(|name| {
+      let c_name = CString::new(name.clone()).unwrap();
+      let addr = unsafe { LLVMGetFunctionAddress(engine, c_name.as_ptr()) };
+      (name, addr)
+    })
+    .collect::<Vec<_>>();
+
+  let mut container = container.borrow_mut();
+  for (name, addr) in addresses.into_iter() {
+    // an older definition compiled late does not replace a newer one
+    if addr != 0 && container.definitions.get(&name) == Some(&id) {
+      // a redefinition replaces the previous address, also for already compiled callers
+      **container.slots.entry(name.clone()).or_insert(Box::new(0)) = addr;
+      container.symbols.insert(name, addr);
     }
-    0
   }
 }
 
+// Every definition goes into a module of its own, which is only compiled when one of its
+// functions is first needed and is removed once nothing can call it anymore: modules of
+// top-level expressions after they ran and modules of definitions that were all replaced.
 pub struct MCJITter {
   module_name: String,
+  pass_options: PassOptions,
   current_module: core::Module,
   func_pass_manager: core::FunctionPassManager,
-  container: Rc<RefCell<ModulesContainer>>
+  module_pass_manager: ModulePassManager,
+  container: Rc<RefCell<ModulesContainer>>,
+  limits: guard::Limits,
+  stats: Stats
 }
 
 impl MCJITter {
-  pub fn new(name: &str) -> MCJITter {
-    let (current_module, func_pass_manager) = module::new_module(name);
+  pub fn new(name: &str, pass_options: &PassOptions, limits: guard::Limits) -> MCJITter {
+    let (current_module, func_pass_manager, module_pass_manager) = module::new_module(name, pass_options);
 
     MCJITter {
       module_name: name.to_string(),
+      pass_options: pass_options.clone(),
       current_module: current_module,
       func_pass_manager: func_pass_manager,
+      module_pass_manager: module_pass_manager,
       container: Rc::new(RefCell::new(ModulesContainer {
-        execution_engines: vec![],
-        modules: vec![]
-      }))
+        modules: BTreeMap::new(),
+        next_id: 0,
+        definitions: HashMap::new(),
+        declarations: HashMap::new(),
+        symbols: HashMap::new(),
+        superseded: Vec::new(),
+        slots: HashMap::new()
+      })),
+      limits: limits,
+      stats: Stats::default()
     }
   }
 
-  fn close_current_module(&mut self) {
-    let (new_module, new_func_pass_manager) = module::new_module(&self.module_name);
+  fn close_current_module(&mut self) -> usize {
+    let timer = Timer::start();
+    self.run_module_passes();
+    self.stats.optimize_ms += timer.elapsed_ms();
+
+    let (new_module, new_func_pass_manager, new_module_pass_manager) = module::new_module(&self.module_name, &self.pass_options);
     self.func_pass_manager = new_func_pass_manager;
+    self.module_pass_manager = new_module_pass_manager;
     let current_module = std::mem::replace(&mut self.current_module, new_module);
+    self.close_module(current_module)
+  }
+
+  // Hands a module over to an execution engine of its own, without compiling it yet, and
+  // makes its definitions the latest ones. Gives the id of the module in the container.
+  fn close_module(&mut self, current_module: core::Module) -> usize {
+    let timer = Timer::start();
+    let slots = module::slot_globals(&current_module);
+    let functions = module::defined_functions(&current_module);
+    let declarations = module::declared_functions(&current_module);
 
     let container = self.container.clone();
     let memory_manager = BindingSectionMemoryManagerBuilder::new()
@@ -91,30 +271,81 @@ impl MCJITter {
           return addr;
         }
 
-        container.borrow().get_function_address(name)
+        function_address(&container, name)
       })
       .create();
 
-      let (execution_engine, module) = match MCJITBuilder::new()
-        .set_mcjit_memory_manager(Box::new(memory_manager))
-        .create(current_module) {
-          Ok((ee, module)) => (ee, module),
-          Err(msg) => panic!(msg)
-      };
+    let (execution_engine, module) = match MCJITBuilder::new()
+      .set_mcjit_memory_manager(Box::new(memory_manager))
+      .create(current_module) {
+        Ok((ee, module)) => (ee, module),
+        Err(msg) => panic!(msg)
+    };
 
-      self.container.borrow_mut().execution_engines.push(execution_engine);
-      self.container.borrow_mut().modules.push(module);
+    let (id, superseded) = {
+      let mut container = self.container.borrow_mut();
+      let id = container.next_id;
+      container.next_id += 1;
+      let mut previous = Vec::new();
+      container.declarations.extend(declarations.into_iter());
+      for name in functions.iter() {
+        // the address of the previous definition must not be found anymore
+        container.symbols.remove(name);
+        if let Some(old) = container.definitions.insert(name.clone(), id) {
+          previous.push(old);
+        }
+      }
+      container.modules.insert(id, EngineModule {
+        engine: execution_engine,
+        module: module,
+        functions: functions.clone(),
+        compiled: false
+      });
+      (id, previous)
+    };
+
+    // Slots have to hold an address before code calling through them runs, so redefinitions
+    // of functions with slots and functions a slot is created for are compiled right away.
+    let mut needed = functions.into_iter()
+      .filter(|name| self.container.borrow().slots.contains_key(name))
+      .collect::<Vec<_>>();
+    for name in slots.into_iter() {
+      let function = name[..name.len() - SLOT_SUFFIX.len()].to_string();
+      let mut container = self.container.borrow_mut();
+      // compiled code holds the address of the slot, which must stay where it is
+      if !container.slots.contains_key(&function) {
+        container.slots.insert(function.clone(), Box::new(0));
+        needed.push(function);
+      }
+    }
+    for name in needed.iter() {
+      let addr = function_address(&self.container, name);
+      **self.container.borrow_mut().slots.get_mut(name).unwrap() = addr;
+    }
+
+    self.container.borrow_mut().superseded.extend(superseded.into_iter());
+    self.stats.finalize_ms += timer.elapsed_ms();
+    id
   }
 }
 
 impl ModuleProvider for MCJITter {
   fn dump(&self) {
-    for module in self.container.borrow().modules.iter() {
-      module.get().dump();
+    for module in self.container.borrow().modules.values() {
+      module.module.get().dump();
     }
     self.current_module.dump();
   }
 
+  fn print_to_string(&self) -> String {
+    let mut result = String::new();
+    for module in self.container.borrow().modules.values() {
+      result.push_str(&module::module_to_string(module.module.get()));
+    }
+    result.push_str(&module::module_to_string(&self.current_module));
+    result
+  }
+
   fn get_module(&mut self) -> &mut core::Module {
     &mut self.current_module
   }
@@ -123,41 +354,59 @@ impl ModuleProvider for MCJITter {
     &mut self.func_pass_manager
   }
 
-  fn get_function(&mut self, name: &str) -> Option<(FunctionRef, bool)> {
-    for module in &self.container.borrow().modules {
-      let funct = match module.get().get_function_by_name(name) {
-        Some(f) => {
-          // found previously defined function
-          f
-        },
-        None => continue
-      };
-
-      let proto = match self.current_module.get_function_by_name(name) {
-        Some(f) => {
-          // function redefinition
-          if funct.count_basic_blocks() != 0 && f.count_basic_blocks() != 0 {
-            panic!("function redefinition across modules")
-          }
-          f
-        },
-        None => {
-          // function of the current module is the prototype
-          let fty = unsafe { FunctionTypeRef::from_ref(funct.get_type().to_ref()) };
-          let fty = unsafe { FunctionTypeRef::from_ref(fty.get_return_type().to_ref()) };
-          FunctionRef::new(&mut self.current_module, name, &fty)
-        }
-      };
+  fn run_module_passes(&mut self) {
+    self.module_pass_manager.run(&mut self.current_module);
+  }
 
-      if funct.count_basic_blocks() > 0 {
-        // Found previously defined function for the prototype of the current module
-        return Some((proto, true))
+  fn function_to_string(&self, name: &str) -> Option<String> {
+    let mut declaration = None;
+    if let Some((ir, defined)) = module::function_to_string(&self.current_module, name) {
+      if defined {
+        return Some(ir)
+      }
+      declaration = Some(ir);
+    }
+    // later modules hold the latest definitions
+    for module in self.container.borrow().modules.values().rev() {
+      if let Some((ir, defined)) = module::function_to_string(module.module.get(), name) {
+        if defined {
+          return Some(ir)
+        }
+        declaration = declaration.or(Some(ir));
       }
     }
+    declaration
+  }
+
+  fn function_names(&self) -> Vec<String> {
+    let mut names = module::function_names(&self.current_module);
+    for module in self.container.borrow().modules.values() {
+      names.extend(module::function_names(module.module.get()).into_iter());
+    }
+    names.sort();
+    names.dedup();
+    names
+  }
 
-    match self.current_module.get_function_by_name(name) {
-      Some(f) => Some((f, f.count_basic_blocks() > 0)),
-      None => None
+  fn get_function(&mut self, name: &str) -> Option<(FunctionRef, bool)> {
+    let compiled = self.container.borrow().get_definition(name);
+    match (self.current_module.get_function_by_name(name), compiled) {
+      // a prototype, a definition or a body copied here to be inlined
+      (Some(f), compiled) => Some((f, compiled.is_some() || f.count_basic_blocks() > 0)),
+      (None, Some(compiled)) => {
+        // the function of the current module is the prototype
+        let fty = unsafe { FunctionTypeRef::from_ref(compiled.get_type().to_ref()) };
+        let fty = unsafe { FunctionTypeRef::from_ref(fty.get_return_type().to_ref()) };
+        Some((FunctionRef::new(&mut self.current_module, name, &fty), true))
+      },
+      (None, None) => {
+        // only declared so far, by an extern
+        let fty = match self.container.borrow().declarations.get(name) {
+          Some(fty) => unsafe { FunctionTypeRef::from_ref(*fty) },
+          None => return None
+        };
+        Some((FunctionRef::new(&mut self.current_module, name, &fty), false))
+      }
     }
   }
 }
@@ -167,13 +416,53 @@ impl JITter for MCJITter {
     self
   }
 
-  fn run_function(&mut self, f: LLVMValueRef) -> f64 {
+  fn run_function(&mut self, f: LLVMValueRef) -> Result<f64, String> {
+    let id = self.close_current_module();
+    compile_module(&self.container, id);
+    let function = unsafe {
+      let engine = self.container.borrow().modules[&id].engine.to_ref();
+      let address = LLVMGetPointerToGlobal(engine, f);
+      std::mem::transmute::<_, extern "C" fn() -> f64>(address)
+    };
+    let timer = Timer::start();
+    let result = guard::run_guarded(function, &self.limits);
+    self.stats.execute_ms += timer.elapsed_ms();
+
+    // a module holding nothing but the top-level expression is never needed again
+    let mut container = self.container.borrow_mut();
+    if container.modules[&id].functions.is_empty() {
+      container.modules.remove(&id).unwrap().remove();
+    }
+    result
+  }
+
+  fn add_compiled_module(&mut self, module: core::Module) -> Result<(), String> {
+    // what was compiled so far, e.g. the prelude, may be used by the module
     self.close_current_module();
-    let f = unsafe {FunctionRef::from_ref(f)};
-    let mut args = vec![];
-    let res = self.container.borrow().execution_engines.last().expect("MCJITter went craze")
-      .run_function(&f, args.as_mut_slice());
-    let ty = RealTypeRef::get_double();
-    res.to_float(&ty)
+    self.close_module(module);
+    Ok(())
+  }
+
+  fn finish_definition(&mut self) {
+    self.close_current_module();
+  }
+
+  fn remove_unused_modules(&mut self) {
+    let mut container = self.container.borrow_mut();
+    let candidates = mem::replace(&mut container.superseded, Vec::new());
+    for id in candidates.into_iter() {
+      if container.modules.contains_key(&id) && container.is_superseded(id) {
+        container.modules.remove(&id).unwrap().remove();
+      }
+    }
+  }
+
+  fn take_stats(&mut self) -> Stats {
+    let mut stats = std::mem::replace(&mut self.stats, Stats::default());
+    let container = self.container.borrow();
+    // the current module is one too
+    stats.modules = container.modules.len() + 1;
+    stats.compiled_modules = container.modules.values().filter(|module| module.compiled).count();
+    stats
   }
 }
@@ -0,0 +1,18 @@
+//! Helpers for building the JSON of the protocols and of serialized ASTs.
+
+use rustc_serialize::json::Json;
+
+/// An object with the given members.
+pub fn object(members: Vec<(&str, Json)>) -> Json {
+  Json::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+/// An object with a "kind" member and the given ones.
+pub fn tagged(kind: &str, mut members: Vec<(&str, Json)>) -> Json {
+  members.insert(0, ("kind", string(kind)));
+  object(members)
+}
+
+pub fn string(value: &str) -> Json {
+  Json::String(value.to_string())
+}
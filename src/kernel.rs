@@ -0,0 +1,567 @@
+//! A Jupyter kernel: speaks version 5 of the kernel messaging protocol over the ZeroMQ sockets
+//! named in a connection file and evaluates cells in a session.
+//!
+//! Cells are evaluated like REPL input, with the output of `printd` and `putchard` sent as a
+//! stream and the values of top-level expressions as the result. A cell that is a colon command
+//! shows the AST of its source (`:ast`) or the IR of a function (`:ir`) as rich output, or
+//! starts over (`:reset`).
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::thread;
+
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use rustc_serialize::hex::ToHex;
+use rustc_serialize::json;
+use rustc_serialize::json::Json;
+use time;
+use zmq;
+
+use builtins;
+use commands;
+use dependencies::node_name;
+use driver::{Options, Session, Exec, Compiled, Returned, RuntimeError, CodegenError, Rejected};
+use editor;
+use guard;
+use jitter;
+use json_util::{object, string};
+use lexer::tokenize;
+use parser::*;
+use printer::pretty;
+use serialization;
+
+const PROTOCOL_VERSION: &'static str = "5.0";
+const DELIMITER: &'static [u8] = b"<IDS|MSG>";
+
+/// Contents of the connection file Jupyter starts a kernel with.
+#[derive(RustcDecodable, PartialEq, Clone, Debug)]
+pub struct ConnectionInfo {
+  pub transport: String,
+  pub ip: String,
+  pub shell_port: u16,
+  pub iopub_port: u16,
+  pub stdin_port: u16,
+  pub control_port: u16,
+  pub hb_port: u16,
+  pub key: String,
+  pub signature_scheme: String
+}
+
+impl ConnectionInfo {
+  pub fn read(path: &str) -> Result<ConnectionInfo, String> {
+    let mut content = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut content)).map_err(|e| format!("{}: {}", path, e)));
+    json::decode(&content).map_err(|e| format!("{}: {}", path, e))
+  }
+
+  fn address(&self, port: u16) -> String {
+    format!("{}://{}:{}", self.transport, self.ip, port)
+  }
+}
+
+/// Signs messages with the key of the connection. An empty key turns signing off.
+pub struct Signer {
+  key: Vec<u8>
+}
+
+impl Signer {
+  pub fn new(key: &str, scheme: &str) -> Result<Signer, String> {
+    if !key.is_empty() && scheme != "hmac-sha256" {
+      return Err(format!("unsupported signature scheme {}", scheme))
+    }
+    Ok(Signer { key: key.as_bytes().to_vec() })
+  }
+
+  /// Hex digest of the parts of a message, empty without a key.
+  pub fn sign(&self, parts: &[&[u8]]) -> String {
+    if self.key.is_empty() {
+      return String::new()
+    }
+    let mut hmac = Hmac::new(Sha256::new(), &self.key);
+    for part in parts.iter() {
+      hmac.input(part);
+    }
+    hmac.result().code().to_hex()
+  }
+}
+
+/// A message of the protocol. The identities route replies on ROUTER sockets and are the topic
+/// on the iopub socket.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Message {
+  pub identities: Vec<Vec<u8>>,
+  pub header: Json,
+  pub parent_header: Json,
+  pub metadata: Json,
+  pub content: Json
+}
+
+impl Message {
+  /// Reads a message from the frames received on a socket and checks its signature.
+  pub fn from_frames(frames: Vec<Vec<u8>>, signer: &Signer) -> Result<Message, String> {
+    let delimiter = try!(frames.iter().position(|frame| &frame[..] == DELIMITER)
+                         .ok_or("message without delimiter".to_string()));
+    if frames.len() < delimiter + 6 {
+      return Err("message with missing parts".to_string())
+    }
+
+    let parts = frames[delimiter + 2..delimiter + 6].iter().map(|frame| &frame[..]).collect::<Vec<_>>();
+    if !fixed_time_eq(&frames[delimiter + 1], signer.sign(&parts).as_bytes()) {
+      return Err("message with a bad signature".to_string())
+    }
+
+    let mut jsons = Vec::new();
+    for part in parts.iter() {
+      let text = try!(String::from_utf8(part.to_vec()).map_err(|e| format!("{}", e)));
+      jsons.push(try!(Json::from_str(&text).map_err(|e| format!("{}", e))));
+    }
+    let content = jsons.pop().unwrap();
+    let metadata = jsons.pop().unwrap();
+    let parent_header = jsons.pop().unwrap();
+    let header = jsons.pop().unwrap();
+
+    Ok(Message {
+      identities: frames[..delimiter].to_vec(),
+      header: header,
+      parent_header: parent_header,
+      metadata: metadata,
+      content: content
+    })
+  }
+
+  /// Frames to send for the message, signed.
+  pub fn to_frames(&self, signer: &Signer) -> Vec<Vec<u8>> {
+    let parts = [&self.header, &self.parent_header, &self.metadata, &self.content].iter()
+      .map(|json| json.to_string().into_bytes())
+      .collect::<Vec<_>>();
+    let signature = signer.sign(&parts.iter().map(|part| &part[..]).collect::<Vec<_>>());
+
+    let mut frames = self.identities.clone();
+    frames.push(DELIMITER.to_vec());
+    frames.push(signature.into_bytes());
+    frames.extend(parts.into_iter());
+    frames
+  }
+
+  pub fn msg_type(&self) -> &str {
+    self.header.find("msg_type").and_then(|msg_type| msg_type.as_string()).unwrap_or("")
+  }
+}
+
+/// What handling a request gave: the messages to publish on iopub, the reply, if the request
+/// has one, and whether the kernel should stop.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Handled {
+  pub published: Vec<Message>,
+  pub reply: Option<Message>,
+  pub shutdown: bool
+}
+
+// Output of a cell, in order.
+enum Output {
+  Stream(String),
+  Display(Json),
+  Result(Json),
+  Error(String, String)
+}
+
+pub struct Kernel {
+  session: Session,
+  // Jupyter session of the kernel, used in the headers of its messages
+  session_id: String,
+  messages: usize,
+  execution_count: u64
+}
+
+impl Kernel {
+  pub fn new(options: &Options) -> Kernel {
+    Kernel {
+      session: Session::new(Exec, options),
+      session_id: random_id(),
+      messages: 0,
+      execution_count: 0
+    }
+  }
+
+  /// Handles a request received on the shell or control socket.
+  pub fn handle(&mut self, request: &Message) -> Handled {
+    let mut published = Vec::new();
+    let mut shutdown = false;
+    let (reply_type, content) = match request.msg_type() {
+      "kernel_info_request" => ("kernel_info_reply", kernel_info()),
+      "execute_request" => ("execute_reply", self.execute(request, &mut published)),
+      "complete_request" => ("complete_reply", self.complete(&request.content)),
+      "inspect_request" => ("inspect_reply", self.inspect(&request.content)),
+      "is_complete_request" => ("is_complete_reply", self.is_complete(&request.content)),
+      "history_request" => ("history_reply", object(vec![("status", string("ok")), ("history", Json::Array(Vec::new()))])),
+      "comm_info_request" => ("comm_info_reply", object(vec![("status", string("ok")), ("comms", object(Vec::new()))])),
+      "shutdown_request" => {
+        shutdown = true;
+        let restart = request.content.find("restart").cloned().unwrap_or(Json::Boolean(false));
+        ("shutdown_reply", object(vec![("status", string("ok")), ("restart", restart)]))
+      },
+      _ => return Handled { published: published, reply: None, shutdown: false }
+    };
+
+    let mut reply = self.message(request, reply_type, content);
+    reply.identities = request.identities.clone();
+    Handled { published: published, reply: Some(reply), shutdown: shutdown }
+  }
+
+  /// The status message published around the handling of `request`, "busy" or "idle".
+  pub fn status(&mut self, request: &Message, state: &str) -> Message {
+    self.message(request, "status", object(vec![("execution_state", string(state))]))
+  }
+
+  // A new message in reply to `parent`, with the message type as its iopub topic.
+  fn message(&mut self, parent: &Message, msg_type: &str, content: Json) -> Message {
+    self.messages += 1;
+    let header = object(vec![
+      ("msg_id", string(&format!("{}-{}", self.session_id, self.messages))),
+      ("session", string(&self.session_id)),
+      ("username", string("kernel")),
+      ("date", string(&time::now_utc().rfc3339().to_string())),
+      ("msg_type", string(msg_type)),
+      ("version", string(PROTOCOL_VERSION))
+    ]);
+    Message {
+      identities: vec![msg_type.as_bytes().to_vec()],
+      header: header,
+      parent_header: parent.header.clone(),
+      metadata: object(Vec::new()),
+      content: content
+    }
+  }
+
+  fn execute(&mut self, request: &Message, published: &mut Vec<Message>) -> Json {
+    let code = request.content.find("code").and_then(|code| code.as_string()).unwrap_or("").to_string();
+    let silent = request.content.find("silent").and_then(|silent| silent.as_boolean()).unwrap_or(false);
+    if !silent {
+      self.execution_count += 1;
+      let input = object(vec![("code", string(&code)), ("execution_count", Json::U64(self.execution_count))]);
+      published.push(self.message(request, "execute_input", input));
+    }
+
+    let outputs = if code.trim_left().starts_with(":") { self.run_command(&code) } else { self.run_code(&code) };
+
+    let mut error = None;
+    for output in outputs.into_iter() {
+      let (msg_type, content) = match output {
+        Output::Stream(text) => ("stream", object(vec![("name", string("stdout")), ("text", string(&text))])),
+        Output::Display(data) => ("display_data", object(vec![("data", data), ("metadata", object(Vec::new()))])),
+        Output::Result(data) => ("execute_result", object(vec![
+          ("execution_count", Json::U64(self.execution_count)),
+          ("data", data),
+          ("metadata", object(Vec::new()))
+        ])),
+        Output::Error(name, value) => {
+          let content = error_content(&name, &value);
+          error = Some(content.clone());
+          ("error", content)
+        }
+      };
+      if !silent {
+        published.push(self.message(request, msg_type, content));
+      }
+    }
+
+    let mut reply = match error {
+      Some(Json::Object(mut content)) => {
+        content.insert("status".to_string(), string("error"));
+        content
+      },
+      _ => {
+        let mut content = BTreeMap::new();
+        content.insert("status".to_string(), string("ok"));
+        content.insert("user_expressions".to_string(), object(Vec::new()));
+        content
+      }
+    };
+    reply.insert("execution_count".to_string(), Json::U64(self.execution_count));
+    Json::Object(reply)
+  }
+
+  fn run_code(&mut self, code: &str) -> Vec<Output> {
+    let tokens = self.session.tokenize(code);
+    let (ast, rest, mut errors) = self.session.parse(tokens.as_slice());
+    if !rest.is_empty() {
+      errors.push("unexpected end of input".to_string());
+    }
+    // a cell with syntax errors is not run at all
+    if !errors.is_empty() {
+      return vec![Output::Error("SyntaxError".to_string(), errors.join("\n"))]
+    }
+
+    // warnings come first in the output, as in the REPL
+    let mut stdout = self.session.lint(ast.as_slice(), Some(code)).iter()
+      .map(|warning| format!("Warning: {}\n", warning))
+      .collect::<String>();
+    jitter::capture_output();
+    let evaluations = self.session.evaluate(ast.as_slice(), Some(code));
+    stdout.push_str(&jitter::take_output());
+
+    let mut values = Vec::new();
+    let mut errors = Vec::new();
+    for evaluation in evaluations.into_iter() {
+      match evaluation {
+        Returned(value) => values.push(format!("{}", value)),
+        Compiled(_) => (),
+        RuntimeError(message) => errors.push(("RuntimeError", message)),
+        CodegenError(message) => errors.push(("CompileError", message)),
+        Rejected(rejected) => for error in rejected.into_iter() {
+          errors.push(("SemanticError", format!("{}", error)));
+        }
+      }
+    }
+
+    let mut outputs = Vec::new();
+    if !stdout.is_empty() {
+      outputs.push(Output::Stream(stdout));
+    }
+    if !values.is_empty() {
+      outputs.push(Output::Result(object(vec![("text/plain", string(&values.join("\n")))])));
+    }
+    if !errors.is_empty() {
+      let messages = errors.iter().map(|&(_, ref message)| message.clone()).collect::<Vec<_>>();
+      outputs.push(Output::Error(errors[0].0.to_string(), messages.join("\n")));
+    }
+    outputs
+  }
+
+  fn run_command(&mut self, line: &str) -> Vec<Output> {
+    let output = match commands::parse_command(line) {
+      Ok(commands::AST(source)) => {
+        // operators defined in the source are not kept, as with the REPL command
+        let mut settings = self.session.parser_settings().clone();
+        let (ast, rest, mut errors) = parse(tokenize(&source).as_slice(), &[], &mut settings);
+        if !rest.is_empty() {
+          errors.push("unexpected end of input".to_string());
+        }
+        if !errors.is_empty() {
+          return vec![Output::Error("SyntaxError".to_string(), errors.join("\n"))]
+        }
+        let text = ast.iter().map(|node| format!("{:?}\n", node)).collect::<String>();
+        let source = ast.iter().map(|node| format!("{}\n", pretty(node, &settings))).collect::<String>();
+        Output::Display(object(vec![
+          ("text/plain", string(&text)),
+          ("text/html", string(&format!("<pre>{}</pre>", escape_html(&source)))),
+          ("application/json", serialization::to_json(ast.as_slice()))
+        ]))
+      },
+      Ok(commands::IR(name)) => match self.session.function_ir(&name) {
+        Some(ir) => Output::Display(object(vec![
+          ("text/plain", string(&ir)),
+          ("text/html", string(&format!("<pre>{}</pre>", escape_html(&ir))))
+        ])),
+        None => Output::Error("CommandError".to_string(), format!("unknown function {}", name))
+      },
+      Ok(commands::Reset) => {
+        self.session.reset();
+        Output::Stream("Session reset\n".to_string())
+      },
+      Ok(_) => Output::Error("CommandError".to_string(),
+                             "only :ast, :ir and :reset are available in notebooks".to_string()),
+      Err(message) => Output::Error("CommandError".to_string(), message)
+    };
+    vec![output]
+  }
+
+  fn complete(&mut self, content: &Json) -> Json {
+    let code = content.find("code").and_then(|code| code.as_string()).unwrap_or("").chars().collect::<Vec<_>>();
+    let cursor = content.find("cursor_pos").and_then(|cursor| cursor.as_u64()).map(|cursor| cursor as usize)
+      .unwrap_or(code.len());
+    let cursor = if cursor > code.len() { code.len() } else { cursor };
+
+    let (start, matches) = editor::completions(self.session.completion_words().as_slice(), &code, cursor);
+    object(vec![
+      ("status", string("ok")),
+      ("matches", Json::Array(matches.iter().map(|word| string(word)).collect())),
+      ("cursor_start", Json::U64(start as u64)),
+      ("cursor_end", Json::U64(cursor as u64)),
+      ("metadata", object(Vec::new()))
+    ])
+  }
+
+  fn inspect(&mut self, content: &Json) -> Json {
+    let code = content.find("code").and_then(|code| code.as_string()).unwrap_or("").chars().collect::<Vec<_>>();
+    let cursor = content.find("cursor_pos").and_then(|cursor| cursor.as_u64()).map(|cursor| cursor as usize)
+      .unwrap_or(code.len());
+    let name = word_at(&code, cursor);
+
+    let mut text = String::new();
+    if let Some(signature) = self.session.signature(&name) {
+      text.push_str(&signature);
+      text.push_str("\n");
+      let defined = self.session.definitions().iter().any(|node| node_name(node) == name.as_str());
+      if let (false, Some(builtin)) = (defined, builtins::find(&name)) {
+        text.push_str(builtin.description);
+        text.push_str("\n");
+      }
+    }
+    if let Some(ir) = self.session.function_ir(&name) {
+      text.push_str(&ir);
+    }
+
+    let data = if text.is_empty() { Vec::new() } else { vec![("text/plain", string(&text))] };
+    object(vec![
+      ("status", string("ok")),
+      ("found", Json::Boolean(!text.is_empty())),
+      ("data", object(data)),
+      ("metadata", object(Vec::new()))
+    ])
+  }
+
+  fn is_complete(&mut self, content: &Json) -> Json {
+    let code = content.find("code").and_then(|code| code.as_string()).unwrap_or("");
+    let mut settings = self.session.parser_settings().clone();
+    let (_, rest, errors) = parse(tokenize(code).as_slice(), &[], &mut settings);
+    let status = if !errors.is_empty() {
+      "invalid"
+    } else if !rest.is_empty() {
+      "incomplete"
+    } else {
+      "complete"
+    };
+    object(vec![("status", string(status)), ("indent", string(""))])
+  }
+}
+
+/// Serves the kernel on the sockets of `connection` until it is shut down.
+pub fn run(connection: &ConnectionInfo, options: &Options) -> Result<(), String> {
+  let signer = try!(Signer::new(&connection.key, &connection.signature_scheme));
+  let mut context = zmq::Context::new();
+  let mut shell = try!(bind(&mut context, zmq::ROUTER, &connection.address(connection.shell_port)));
+  let mut control = try!(bind(&mut context, zmq::ROUTER, &connection.address(connection.control_port)));
+  let mut iopub = try!(bind(&mut context, zmq::PUB, &connection.address(connection.iopub_port)));
+  // input requests are not supported, but the socket is expected to be there
+  let _stdin = try!(bind(&mut context, zmq::ROUTER, &connection.address(connection.stdin_port)));
+
+  let heartbeat_address = connection.address(connection.hb_port);
+  thread::spawn(move || heartbeat(&heartbeat_address));
+
+  // Jupyter interrupts kernels with SIGINT, which must not end an idle one
+  guard::ignore_idle_interrupts();
+  let mut kernel = Kernel::new(options);
+
+  loop {
+    let (shell_ready, control_ready) = {
+      let mut items = [shell.as_poll_item(zmq::POLLIN), control.as_poll_item(zmq::POLLIN)];
+      // fails with EINTR when an interrupt arrives while waiting
+      if zmq::poll(&mut items, -1).is_err() {
+        continue
+      }
+      (items[0].get_revents() & zmq::POLLIN != 0, items[1].get_revents() & zmq::POLLIN != 0)
+    };
+
+    for &(ready, is_control) in [(control_ready, true), (shell_ready, false)].iter() {
+      if !ready {
+        continue
+      }
+      let socket = if is_control { &mut control } else { &mut shell };
+      let frames = match socket.recv_multipart(0) {
+        Ok(frames) => frames,
+        Err(_) => continue
+      };
+      let request = match Message::from_frames(frames, &signer) {
+        Ok(request) => request,
+        Err(message) => {
+          let _ = writeln!(io::stderr(), "Ignoring request: {}", message);
+          continue
+        }
+      };
+
+      let busy = kernel.status(&request, "busy");
+      try!(send(&mut iopub, &busy, &signer));
+      let handled = kernel.handle(&request);
+      for message in handled.published.iter() {
+        try!(send(&mut iopub, message, &signer));
+      }
+      if let Some(ref reply) = handled.reply {
+        try!(send(socket, reply, &signer));
+      }
+      let idle = kernel.status(&request, "idle");
+      try!(send(&mut iopub, &idle, &signer));
+
+      if handled.shutdown {
+        return Ok(())
+      }
+    }
+  }
+}
+
+fn bind(context: &mut zmq::Context, socket_type: zmq::SocketType, address: &str) -> Result<zmq::Socket, String> {
+  let mut socket = try!(context.socket(socket_type).map_err(|e| format!("{}", e)));
+  try!(socket.bind(address).map_err(|e| format!("{}: {}", address, e)));
+  Ok(socket)
+}
+
+fn send(socket: &mut zmq::Socket, message: &Message, signer: &Signer) -> Result<(), String> {
+  let frames = message.to_frames(signer);
+  let parts = frames.iter().map(|frame| &frame[..]).collect::<Vec<_>>();
+  socket.send_multipart(&parts, 0).map_err(|e| format!("{}", e))
+}
+
+// Echoes heartbeats, on its own thread so that they are answered while a cell runs.
+fn heartbeat(address: &str) {
+  let mut context = zmq::Context::new();
+  let mut socket = match bind(&mut context, zmq::REP, address) {
+    Ok(socket) => socket,
+    Err(message) => {
+      let _ = writeln!(io::stderr(), "No heartbeat: {}", message);
+      return
+    }
+  };
+  loop {
+    if let Ok(ping) = socket.recv_bytes(0) {
+      let _ = socket.send(&ping, 0);
+    }
+  }
+}
+
+fn kernel_info() -> Json {
+  object(vec![
+    ("status", string("ok")),
+    ("protocol_version", string(PROTOCOL_VERSION)),
+    ("implementation", string("kaleidoscope")),
+    ("implementation_version", string(env!("CARGO_PKG_VERSION"))),
+    ("language_info", object(vec![
+      ("name", string("kaleidoscope")),
+      ("version", string(env!("CARGO_PKG_VERSION"))),
+      ("mimetype", string("text/x-kaleidoscope")),
+      ("file_extension", string(".ks"))
+    ])),
+    ("banner", string("Kaleidoscope, JIT compiled with LLVM"))
+  ])
+}
+
+fn error_content(name: &str, value: &str) -> Json {
+  let traceback = vec![string(&format!("{}: {}", name, value))];
+  object(vec![("ename", string(name)), ("evalue", string(value)), ("traceback", Json::Array(traceback))])
+}
+
+// The identifier around position `cursor` of `code`.
+fn word_at(code: &[char], cursor: usize) -> String {
+  let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+  let cursor = if cursor > code.len() { code.len() } else { cursor };
+  let start = code[..cursor].iter().rposition(|c| !is_word(c)).map(|i| i + 1).unwrap_or(0);
+  let end = code[cursor..].iter().position(|c| !is_word(c)).map(|i| cursor + i).unwrap_or(code.len());
+  code[start..end].iter().cloned().collect()
+}
+
+fn escape_html(text: &str) -> String {
+  text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
+}
+
+// Hex digits from /dev/urandom, or the time if it cannot be read.
+fn random_id() -> String {
+  let mut bytes = Vec::new();
+  match File::open("/dev/urandom").and_then(|file| file.take(16).read_to_end(&mut bytes)) {
+    Ok(16) => bytes.to_hex(),
+    _ => format!("{:x}", time::precise_time_ns())
+  }
+}
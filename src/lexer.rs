@@ -1,3 +1,5 @@
+use std::cmp;
+
 use regex::Regex;
 
 pub use self::Token::{
@@ -39,7 +41,29 @@ pub enum Token {
   Operator(String)
 }
 
+/// Position of a token in the source. Lines and columns start at 1, columns count characters.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Location {
+  pub line: usize,
+  pub column: usize
+}
+
+/// Source range of a token, `end` is the position just after it.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Span {
+  pub start: Location,
+  pub end: Location
+}
+
 pub fn tokenize(input: &str) -> Vec<Token> {
+  tokenize_with_spans(input).into_iter().map(|(token, _)| token).collect()
+}
+
+pub fn tokenize_with_locations(input: &str) -> Vec<(Token, Location)> {
+  tokenize_with_spans(input).into_iter().map(|(token, span)| (token, span.start)).collect()
+}
+
+pub fn tokenize_with_spans(input: &str) -> Vec<(Token, Span)> {
   // let comment_re = regex!(r"(?m)#.*\n");
   let comment_re = Regex::new(r"(?m)#.*\n").unwrap();
   let preprocessed = comment_re.replace_all(input, "\n");
@@ -61,8 +85,24 @@ pub fn tokenize(input: &str) -> Vec<Token> {
           r"(?P<comma>,)|",
           r"(?P<operator>\S)")).unwrap();
 
-  let result: Vec<Token> = token_re.captures_iter(preprocessed.as_str()).map(|cap| {
-    match vec!["ident", "number", "delimiter", "oppar", "clpar", "comma", "operator"].iter()
+  // Comments are replaced by the line break ending them, so lines and columns of the
+  // preprocessed input are the same as in the original one.
+  let mut location = Location{line: 1, column: 1};
+  let mut offset = 0;
+
+  let result = token_re.captures_iter(preprocessed.as_str()).map(|cap| {
+    let (start, end) = cap.pos(0).unwrap();
+    for c in preprocessed[offset..start].chars() {
+      if c == '\n' {
+        location.line += 1;
+        location.column = 1;
+      } else {
+        location.column += 1;
+      }
+    }
+    offset = start;
+
+    let token = match vec!["ident", "number", "delimiter", "oppar", "clpar", "comma", "operator"].iter()
       .find(|keyword| cap.name(keyword).is_some()) {
         None => panic!("Undefined token"),
         Some(k) => {
@@ -95,8 +135,22 @@ pub fn tokenize(input: &str) -> Vec<Token> {
             _ => panic!("Undefined token: {}", *k)
           }
         }
-      }
+      };
+    // tokens never contain line breaks
+    let end = Location{line: location.line, column: location.column + preprocessed[start..end].chars().count()};
+    (token, Span{start: location, end: end})
   }).collect::<Vec<_>>();
 
   result
 }
+
+/// Span from the start of token `start` to the end of token `end - 1`, given the spans of all
+/// tokens. Positions at the end of the input give an empty span after the last token.
+pub fn token_span(spans: &[Span], start: usize, end: usize) -> Span {
+  let after_last = spans.last().map(|span| span.end).unwrap_or(Location{line: 1, column: 1});
+  if start >= spans.len() || end <= start {
+    let location = spans.get(start).map(|span| span.start).unwrap_or(after_last);
+    return Span{start: location, end: location}
+  }
+  Span{start: spans[start].start, end: spans[cmp::min(end, spans.len()) - 1].end}
+}
@@ -1,18 +1,46 @@
 #![feature(convert)]
 #![feature(plugin)]
 #![feature(box_syntax)]
+#![feature(static_mutex)]
 
 extern crate regex;
+extern crate rustc_serialize;
 extern crate llvm_sys;
 extern crate iron_llvm;
+extern crate libc;
+#[cfg(feature = "jupyter")]
+extern crate crypto;
+extern crate time;
+#[cfg(feature = "jupyter")]
+extern crate zmq;
 
 pub mod lexer;
 pub mod context;
 pub mod builder;
+pub mod debug_info;
+pub mod builtins;
 pub mod module;
+pub mod passes;
+pub mod tail_calls;
 pub mod parser;
+pub mod prelude;
+pub mod printer;
+pub mod formatter;
+pub mod json_util;
+pub mod serialization;
+pub mod dependencies;
+pub mod semantics;
+pub mod lint;
 pub mod driver;
+pub mod commands;
+pub mod protocol;
+#[cfg(feature = "jupyter")]
+pub mod kernel;
+pub mod lsp;
+pub mod editor;
+pub mod timing;
 pub mod jitter;
+pub mod guard;
 
 #[test]
 fn it_works() {
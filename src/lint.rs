@@ -0,0 +1,266 @@
+//! Warnings about definitions that compile but probably do not do what was meant: unused
+//! parameters, loop variables hiding parameters, conditions that are always true or false,
+//! definitions of built-in operators, which are never called, and operator precedences that
+//! change or are shared with another operator.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+
+use lexer::*;
+use parser::*;
+use semantics;
+use semantics::{Locator, SymbolTable};
+
+// binary operators compiled to instructions instead of calls
+const BUILTIN_OPERATORS: &'static [&'static str] = &["+", "-", "*", "<"];
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Warning {
+  // index of the node with the warning
+  pub node: usize,
+  pub message: String,
+  pub span: Option<Span>
+}
+
+impl fmt::Display for Warning {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.span {
+      Some(span) => write!(f, "{}:{}: {}", span.start.line, span.start.column, self.message),
+      None => write!(f, "{}", self.message)
+    }
+  }
+}
+
+/// Lints `nodes` in order. `settings` are the operator precedences from before the nodes were
+/// parsed, operators defined by the nodes are added to them as they are met.
+pub fn lint(nodes: &[ASTNode], settings: &ParserSettings) -> Vec<Warning> {
+  lint_nodes(nodes.iter().map(|node| (node, None)).collect(), settings)
+}
+
+/// Like `lint`, with warnings located in the source as by `semantics::check_located`.
+pub fn lint_located(nodes: &[(ASTNode, (usize, usize))], tokens: &[Token], spans: &[Span],
+                    settings: &ParserSettings) -> Vec<Warning> {
+  lint_nodes(nodes.iter().map(|&(ref node, range)| (node, Some(Locator::new(tokens, spans, range)))).collect(),
+             settings)
+}
+
+/// Syntax errors, semantic errors and warnings of a source file, in the order of the source,
+/// each with its severity, "error" or "warning". `settings` and `symbols` are the operators and
+/// functions known before the file, e.g. from the prelude.
+pub fn lint_source(source: &str, settings: &ParserSettings, symbols: &SymbolTable) -> Vec<(Span, &'static str, String)> {
+  let (tokens, spans): (Vec<_>, Vec<_>) = tokenize_with_spans(source).into_iter().unzip();
+  let (nodes, rest, errors) = parse_with_positions(tokens.as_slice(), &mut settings.clone());
+
+  let mut findings = Vec::new();
+  for (message, position) in errors.into_iter() {
+    findings.push((token_span(&spans, position, position + 1), "error", message));
+  }
+  if !rest.is_empty() {
+    let start = tokens.len() - rest.len();
+    findings.push((token_span(&spans, start, tokens.len()), "error", "unexpected end of input".to_string()));
+  }
+
+  let node_span = |node: usize| {
+    let (start, end) = nodes[node].1;
+    token_span(&spans, start, end)
+  };
+  for error in semantics::check_located(nodes.as_slice(), tokens.as_slice(), spans.as_slice(), symbols).into_iter() {
+    findings.push((error.span.unwrap_or(node_span(error.node)), "error", error.message));
+  }
+  for warning in lint_located(nodes.as_slice(), tokens.as_slice(), spans.as_slice(), settings).into_iter() {
+    findings.push((warning.span.unwrap_or(node_span(warning.node)), "warning", warning.message));
+  }
+
+  findings.sort_by(|a, b| (a.0.start.line, a.0.start.column).cmp(&(b.0.start.line, b.0.start.column)));
+  findings
+}
+
+/// Checks and lints files, after the definitions of `prelude`, printing every error and warning
+/// with its location. Gives the exit status of `kaleidoscope lint`: 2 if a file has errors or
+/// cannot be read, 1 if there are only warnings.
+pub fn lint_files(paths: &[String], prelude: Option<&str>) -> i32 {
+  let mut settings = default_parser_settings();
+  let mut symbols = SymbolTable::new();
+  if let Some(prelude) = prelude {
+    let (nodes, _, _) = parse(tokenize(prelude).as_slice(), &[], &mut settings);
+    for node in nodes.iter() {
+      symbols.define_node(node);
+    }
+  }
+
+  let mut status = 0;
+  for path in paths.iter() {
+    let mut source = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut source)) {
+      println!("{}: {}", path, e);
+      status = 2;
+      continue
+    }
+
+    for (span, severity, message) in lint_source(&source, &settings, &symbols).into_iter() {
+      println!("{}:{}:{}: {}: {}", path, span.start.line, span.start.column, severity, message);
+      if severity == "error" {
+        status = 2;
+      } else if status == 0 {
+        status = 1;
+      }
+    }
+  }
+  status
+}
+
+fn lint_nodes(nodes: Vec<(&ASTNode, Option<Locator>)>, settings: &ParserSettings) -> Vec<Warning> {
+  let mut precedences = settings.operators().into_iter().collect::<HashMap<_, _>>();
+  let mut warnings = Vec::new();
+  for (i, (node, locator)) in nodes.into_iter().enumerate() {
+    let mut linter = Linter { locator: locator, parameters: Vec::new(), warnings: Vec::new() };
+    match node {
+      &ExternNode(ref prototype) => linter.prototype(prototype, None, &mut precedences),
+      &FunctionNode(ref function) => {
+        linter.prototype(&function.prototype, Some(&function.expression), &mut precedences);
+        linter.parameters = function.prototype.args.clone();
+        linter.expression(&function.expression);
+      }
+    }
+    warnings.extend(linter.warnings.into_iter().map(|(message, span)| Warning { node: i, message: message, span: span }));
+  }
+  warnings
+}
+
+struct Linter<'a> {
+  locator: Option<Locator<'a>>,
+  // parameters of the function being linted
+  parameters: Vec<String>,
+  warnings: Vec<(String, Option<Span>)>
+}
+
+impl<'a> Linter<'a> {
+  fn locate(&mut self, token: Token) -> Option<Span> {
+    match self.locator {
+      Some(ref mut locator) => locator.find(&token),
+      None => None
+    }
+  }
+
+  // `body` is None for externs, which neither use their parameters nor define precedences.
+  fn prototype(&mut self, prototype: &Prototype, body: Option<&Expression>, precedences: &mut HashMap<String, i32>) {
+    let span = match prototype.ftype {
+      Normal => self.locate(Ident(prototype.name.clone())),
+      UnaryOp(ref op) | BinaryOp(ref op, _) => self.locate(Operator(op.clone()))
+    };
+
+    if let (&BinaryOp(ref op, precedence), Some(_)) = (&prototype.ftype, body) {
+      if BUILTIN_OPERATORS.contains(&op.as_str()) {
+        self.warnings.push((format!("{} is a built-in operator, this definition is never called", op), span));
+      }
+      match precedences.get(op).map(|p| *p) {
+        Some(previous) if previous == precedence => (),
+        previous => {
+          if let Some(previous) = previous {
+            self.warnings.push((format!("precedence of {} changes from {} to {}", op, previous, precedence), span));
+          }
+          let mut others = precedences.iter()
+            .filter(|&(other, p)| other != op && *p == precedence)
+            .map(|(other, _)| other.clone())
+            .collect::<Vec<_>>();
+          if !others.is_empty() {
+            others.sort();
+            self.warnings.push((format!("{} has the same precedence {} as {}", op, precedence, others.join(" ")), span));
+          }
+        }
+      }
+      precedences.insert(op.clone(), precedence);
+    }
+
+    for arg in prototype.args.iter() {
+      let span = self.locate(Ident(arg.clone()));
+      if let Some(body) = body {
+        if !uses(body, arg) {
+          self.warnings.push((format!("unused parameter {}", arg), span));
+        }
+      }
+    }
+  }
+
+  fn expression(&mut self, expr: &Expression) {
+    match expr {
+      &LiteralExpr(_) | &VariableExpr(_) => (),
+
+      &BinaryExpr(_, ref lhs, ref rhs) => {
+        self.expression(lhs);
+        self.expression(rhs);
+      },
+
+      &UnaryExpr(_, ref operand) => self.expression(operand),
+
+      &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+        let span = self.locate(If);
+        match constant_value(cond_expr) {
+          Some(value) if value != 0.0 => self.warnings.push(("condition is always true".to_string(), span)),
+          Some(_) => self.warnings.push(("condition is always false".to_string(), span)),
+          None => ()
+        }
+        self.expression(cond_expr);
+        self.expression(then_expr);
+        self.expression(else_expr);
+      },
+
+      &LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+        self.locate(For);
+        let span = self.locate(Ident(var_name.clone()));
+        // the parameter is back after the loop, see `loop_codegen`
+        if self.parameters.contains(var_name) {
+          self.warnings.push((format!("loop variable {} hides the parameter of the same name", var_name), span));
+        }
+        self.expression(start_expr);
+        self.expression(end_expr);
+        self.expression(step_expr);
+        self.expression(body_expr);
+      },
+
+      &CallExpr(_, ref args) => for arg in args.iter() {
+        self.expression(arg);
+      }
+    }
+  }
+}
+
+// Whether variable `name` is read in `expr`, not counting loops with a variable of that name.
+fn uses(expr: &Expression, name: &str) -> bool {
+  match expr {
+    &LiteralExpr(_) => false,
+    &VariableExpr(ref variable) => variable == name,
+    &BinaryExpr(_, ref lhs, ref rhs) => uses(lhs, name) || uses(rhs, name),
+    &UnaryExpr(_, ref operand) => uses(operand, name),
+    &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+      uses(cond_expr, name) || uses(then_expr, name) || uses(else_expr, name)
+    },
+    &LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+      uses(start_expr, name) || (var_name != name && (uses(end_expr, name) || uses(step_expr, name) || uses(body_expr, name)))
+    },
+    &CallExpr(_, ref args) => args.iter().any(|arg| uses(arg, name))
+  }
+}
+
+// Value of an expression made of literals and built-in operators only.
+fn constant_value(expr: &Expression) -> Option<f64> {
+  match expr {
+    &LiteralExpr(value) => Some(value),
+    &BinaryExpr(ref op, ref lhs, ref rhs) => {
+      let (lhs, rhs) = match (constant_value(lhs), constant_value(rhs)) {
+        (Some(lhs), Some(rhs)) => (lhs, rhs),
+        _ => return None
+      };
+      match op.as_str() {
+        "+" => Some(lhs + rhs),
+        "-" => Some(lhs - rhs),
+        "*" => Some(lhs * rhs),
+        "<" => Some(if lhs < rhs { 1.0 } else { 0.0 }),
+        _ => None
+      }
+    },
+    _ => None
+  }
+}
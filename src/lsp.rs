@@ -0,0 +1,509 @@
+//! A language server for Kaleidoscope files, speaking the Language Server Protocol over stdio:
+//! diagnostics for syntax and semantic errors and lint warnings, go-to-definition and hover for
+//! functions and operators, document symbols and semantic tokens.
+//!
+//! Files are only tokenized and parsed, never compiled. The operators of the standard prelude
+//! and its functions are known in every file, as they are in a session.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::{BufRead, Read, Write};
+
+use rustc_serialize::json::Json;
+
+use builtins;
+use dependencies::node_name;
+use json_util::{object, string};
+use lexer::*;
+use parser::*;
+use prelude;
+use lint;
+use semantics;
+use semantics::SymbolTable;
+
+/// Token types of the semantic tokens, in the order of the legend sent to the client.
+pub const TOKEN_TYPES: &'static [&'static str] = &["keyword", "function", "parameter", "variable", "number", "operator"];
+
+const KEYWORD: u64 = 0;
+const FUNCTION: u64 = 1;
+const PARAMETER: u64 = 2;
+const VARIABLE: u64 = 3;
+const NUMBER: u64 = 4;
+const OPERATOR: u64 = 5;
+
+// severities of diagnostics
+const ERROR: u64 = 1;
+const WARNING: u64 = 2;
+
+// kinds of document symbols
+const SYMBOL_FUNCTION: u64 = 12;
+const SYMBOL_OPERATOR: u64 = 25;
+
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// A parsed file.
+struct Document {
+  tokens: Vec<Token>,
+  spans: Vec<Span>,
+  // nodes with the range of tokens they were parsed from
+  nodes: Vec<(ASTNode, (usize, usize))>,
+  // syntax errors with the range of tokens they are about
+  errors: Vec<(String, (usize, usize))>,
+  // operators of the prelude and of the file
+  settings: ParserSettings
+}
+
+impl Document {
+  fn new(text: &str, settings: &ParserSettings) -> Document {
+    let (tokens, spans): (Vec<_>, Vec<_>) = tokenize_with_spans(text).into_iter().unzip();
+    let mut settings = settings.clone();
+    let (nodes, rest, errors) = parse_with_positions(tokens.as_slice(), &mut settings);
+
+    let mut errors = errors.into_iter().map(|(message, position)| (message, (position, position + 1))).collect::<Vec<_>>();
+    if !rest.is_empty() {
+      errors.push(("unexpected end of input".to_string(), (tokens.len() - rest.len(), tokens.len())));
+    }
+
+    Document {
+      tokens: tokens,
+      spans: spans,
+      nodes: nodes,
+      errors: errors,
+      settings: settings
+    }
+  }
+
+  // Index of the token at a position of the protocol, which counts lines and characters from 0.
+  // A position just after a token counts as on it.
+  fn token_at(&self, position: &Json) -> Option<usize> {
+    let line = match position.find("line").and_then(|line| line.as_u64()) {
+      Some(line) => line as usize + 1,
+      None => return None
+    };
+    let column = match position.find("character").and_then(|character| character.as_u64()) {
+      Some(character) => character as usize + 1,
+      None => return None
+    };
+    self.spans.iter().position(|span| span.start.line == line && span.start.column <= column && column <= span.end.column)
+  }
+
+  // Name of the function token `i` refers to: a function name or an operator.
+  fn function_name(&self, i: usize) -> Option<String> {
+    match self.tokens[i] {
+      Ident(ref name) => Some(name.clone()),
+      Operator(ref op) => {
+        let previous = if i == 0 { None } else { self.tokens.get(i - 1) };
+        let unary = match previous {
+          Some(&Binary) => false,
+          Some(&Unary) => true,
+          // an operator is unary where an operand is expected
+          Some(&Ident(_)) | Some(&Number(_)) | Some(&RightParen) => false,
+          _ => true
+        };
+        Some(format!("{}{}", if unary { "unary" } else { "binary" }, op))
+      },
+      _ => None
+    }
+  }
+
+  // The last node defining or declaring function `name`.
+  fn definition(&self, name: &str) -> Option<usize> {
+    self.nodes.iter().rposition(|&(ref node, _)| node_name(node) == name)
+  }
+
+  // Index of the token naming the function of node `n`, an identifier or an operator.
+  fn name_token(&self, n: usize) -> usize {
+    let start = (self.nodes[n].1).0;
+    match self.tokens.get(start + 1) {
+      Some(&Binary) | Some(&Unary) => start + 2,
+      _ => start + 1
+    }
+  }
+
+  fn range(&self, start: usize, end: usize) -> Json {
+    range(token_span(&self.spans, start, end))
+  }
+}
+
+pub struct Server {
+  documents: HashMap<String, Document>,
+  // operators after the prelude
+  settings: ParserSettings,
+  prelude: Vec<ASTNode>,
+  // functions of the prelude
+  symbols: SymbolTable,
+  shut_down: bool,
+  exited: bool
+}
+
+impl Server {
+  pub fn new() -> Server {
+    let mut settings = default_parser_settings();
+    let (prelude, _, _) = parse(tokenize(prelude::STANDARD).as_slice(), &[], &mut settings);
+    let mut symbols = SymbolTable::new();
+    for node in prelude.iter() {
+      symbols.define_node(node);
+    }
+    Server {
+      documents: HashMap::new(),
+      settings: settings,
+      prelude: prelude,
+      symbols: symbols,
+      shut_down: false,
+      exited: false
+    }
+  }
+
+  /// Whether the client sent `exit`.
+  pub fn exited(&self) -> bool {
+    self.exited
+  }
+
+  /// Handles a message from the client and gives the messages to send back to it.
+  pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+    let method = match message.find("method").and_then(|method| method.as_string()) {
+      Some(method) => method.to_string(),
+      // responses to requests of the server, which sends none
+      None => return Vec::new()
+    };
+    let params = message.find("params").cloned().unwrap_or(Json::Null);
+
+    match message.find("id") {
+      Some(id) if self.shut_down && method.as_str() != "shutdown" => {
+        vec![response(id.clone(), Err((INVALID_REQUEST, "the server is shut down".to_string())))]
+      },
+      Some(id) => {
+        let result = self.request(&method, &params);
+        vec![response(id.clone(), result)]
+      },
+      None => self.notification(&method, &params)
+    }
+  }
+
+  fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+    match method {
+      "initialize" => Ok(object(vec![
+        ("capabilities", object(vec![
+          // whole documents are sent on every change
+          ("textDocumentSync", Json::U64(1)),
+          ("definitionProvider", Json::Boolean(true)),
+          ("hoverProvider", Json::Boolean(true)),
+          ("documentSymbolProvider", Json::Boolean(true)),
+          ("semanticTokensProvider", object(vec![
+            ("legend", object(vec![
+              ("tokenTypes", Json::Array(TOKEN_TYPES.iter().map(|name| string(name)).collect())),
+              ("tokenModifiers", Json::Array(Vec::new()))
+            ])),
+            ("full", Json::Boolean(true))
+          ]))
+        ])),
+        ("serverInfo", object(vec![("name", string("kaleidoscope-lsp"))]))
+      ])),
+      "shutdown" => {
+        self.shut_down = true;
+        Ok(Json::Null)
+      },
+      "textDocument/definition" => {
+        let (uri, document) = try!(self.document(params));
+        Ok(definition(uri, document, params))
+      },
+      "textDocument/hover" => {
+        let (_, document) = try!(self.document(params));
+        Ok(self.hover(document, params))
+      },
+      "textDocument/documentSymbol" => {
+        let (_, document) = try!(self.document(params));
+        Ok(document_symbols(document))
+      },
+      "textDocument/semanticTokens/full" => {
+        let (_, document) = try!(self.document(params));
+        Ok(object(vec![("data", Json::Array(semantic_tokens(document).into_iter().map(Json::U64).collect()))]))
+      },
+      _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method)))
+    }
+  }
+
+  fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+    let uri = params.find_path(&["textDocument", "uri"]).and_then(|uri| uri.as_string()).unwrap_or("").to_string();
+    match method {
+      "exit" => {
+        self.exited = true;
+        Vec::new()
+      },
+      "textDocument/didOpen" => {
+        let text = params.find_path(&["textDocument", "text"]).and_then(|text| text.as_string()).unwrap_or("");
+        self.open(&uri, text)
+      },
+      "textDocument/didChange" => {
+        // with full synchronization the last change is the whole text
+        let changes = params.find("contentChanges").and_then(|changes| changes.as_array());
+        match changes.and_then(|changes| changes.last()).and_then(|change| change.find("text")).and_then(|text| text.as_string()) {
+          Some(text) => self.open(&uri, text),
+          None => Vec::new()
+        }
+      },
+      "textDocument/didClose" => {
+        self.documents.remove(&uri);
+        vec![publish_diagnostics(&uri, Vec::new())]
+      },
+      _ => Vec::new()
+    }
+  }
+
+  fn open(&mut self, uri: &str, text: &str) -> Vec<Json> {
+    let document = Document::new(text, &self.settings);
+    // the diagnostics borrow the document, which is kept below
+    let diagnostics = {
+      let mut diagnostics = document.errors.iter().map(|&(ref message, (start, end))| {
+        diagnostic(document.range(start, end), ERROR, message)
+      }).collect::<Vec<_>>();
+      let node_range = |node: usize, span: Option<Span>| match span {
+        Some(span) => range(span),
+        None => {
+          let (start, end) = document.nodes[node].1;
+          document.range(start, end)
+        }
+      };
+      let errors = semantics::check_located(document.nodes.as_slice(), document.tokens.as_slice(),
+                                            document.spans.as_slice(), &self.symbols);
+      for error in errors.iter() {
+        diagnostics.push(diagnostic(node_range(error.node, error.span), ERROR, &error.message));
+      }
+      let warnings = lint::lint_located(document.nodes.as_slice(), document.tokens.as_slice(),
+                                        document.spans.as_slice(), &self.settings);
+      for warning in warnings.iter() {
+        diagnostics.push(diagnostic(node_range(warning.node, warning.span), WARNING, &warning.message));
+      }
+      diagnostics
+    };
+    self.documents.insert(uri.to_string(), document);
+    vec![publish_diagnostics(uri, diagnostics)]
+  }
+
+  fn document<'a>(&'a self, params: &'a Json) -> Result<(&'a str, &'a Document), (i64, String)> {
+    let uri = try!(params.find_path(&["textDocument", "uri"]).and_then(|uri| uri.as_string())
+                   .ok_or((INVALID_PARAMS, "missing textDocument.uri".to_string())));
+    match self.documents.get(uri) {
+      Some(document) => Ok((uri, document)),
+      None => Err((INVALID_PARAMS, format!("{} is not open", uri)))
+    }
+  }
+
+  fn hover(&self, document: &Document, params: &Json) -> Json {
+    let i = match params.find("position").and_then(|position| document.token_at(position)) {
+      Some(i) => i,
+      None => return Json::Null
+    };
+    let name = match document.function_name(i) {
+      Some(name) => name,
+      None => return Json::Null
+    };
+
+    let builtin_operator = if name.starts_with("binary") { document.settings.precedence(&name[6..]) } else { None };
+
+    let text = if let Some(n) = document.definition(&name) {
+      describe(&document.nodes[n].0)
+    } else if let Some(node) = self.prelude.iter().rev().find(|node| node_name(node) == name.as_str()) {
+      format!("{}\n\nDefined in the prelude.", describe(node))
+    } else if let Some(builtin) = builtins::find(&name) {
+      format!("```kaleidoscope\n{}({})\n```\n\n{}", builtin.name, builtin.args.join(", "), builtin.description)
+    } else if let Some(precedence) = builtin_operator {
+      format!("Built-in binary operator `{}`, precedence {}.", &name[6..], precedence)
+    } else {
+      return Json::Null
+    };
+
+    object(vec![
+      ("contents", object(vec![("kind", string("markdown")), ("value", string(&text))])),
+      ("range", document.range(i, i + 1))
+    ])
+  }
+}
+
+/// Reads a message with its Content-Length header. Returns None at the end of the input.
+pub fn read_message(input: &mut BufRead) -> Result<Option<Json>, String> {
+  let mut length = None;
+  loop {
+    let mut line = String::new();
+    if try!(input.read_line(&mut line).map_err(|e| format!("{}", e))) == 0 {
+      return Ok(None)
+    }
+    let line = line.trim();
+    if line.is_empty() {
+      break
+    }
+    if line.to_lowercase().starts_with("content-length:") {
+      length = line["content-length:".len()..].trim().parse::<u64>().ok();
+    }
+  }
+
+  let length = try!(length.ok_or("message without Content-Length".to_string()));
+  let mut content = Vec::new();
+  try!(input.take(length).read_to_end(&mut content).map_err(|e| format!("{}", e)));
+  let content = try!(String::from_utf8(content).map_err(|e| format!("{}", e)));
+  Json::from_str(&content).map(Some).map_err(|e| format!("{}", e))
+}
+
+pub fn write_message(output: &mut Write, message: &Json) -> io::Result<()> {
+  let content = message.to_string();
+  try!(write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content));
+  output.flush()
+}
+
+/// Serves a client until it sends `exit` or the input ends.
+pub fn serve(input: &mut BufRead, output: &mut Write) -> Result<(), String> {
+  let mut server = Server::new();
+  while !server.exited() {
+    let message = match try!(read_message(input)) {
+      Some(message) => message,
+      None => break
+    };
+    for reply in server.handle(&message).iter() {
+      try!(write_message(output, reply).map_err(|e| format!("{}", e)));
+    }
+  }
+  Ok(())
+}
+
+fn definition(uri: &str, document: &Document, params: &Json) -> Json {
+  let name = params.find("position").and_then(|position| document.token_at(position))
+    .and_then(|i| document.function_name(i));
+  match name.and_then(|name| document.definition(&name)) {
+    Some(n) => {
+      let i = document.name_token(n);
+      object(vec![("uri", string(uri)), ("range", document.range(i, i + 1))])
+    },
+    None => Json::Null
+  }
+}
+
+// Hover text for a definition or an extern.
+fn describe(node: &ASTNode) -> String {
+  let (code, prototype) = match node {
+    &ExternNode(ref prototype) => (format!("extern {}", prototype), prototype),
+    &FunctionNode(ref function) => (format!("def {}", function.prototype), &function.prototype)
+  };
+  let mut text = format!("```kaleidoscope\n{}\n```", code);
+  match prototype.ftype {
+    BinaryOp(_, precedence) => text.push_str(&format!("\n\nBinary operator, precedence {}.", precedence)),
+    UnaryOp(_) => text.push_str("\n\nUnary operator."),
+    Normal => ()
+  }
+  text
+}
+
+fn document_symbols(document: &Document) -> Json {
+  // top-level expressions have no name
+  let nodes = document.nodes.iter().enumerate().filter(|&(_, &(ref node, _))| !node_name(node).is_empty());
+  let symbols = nodes.map(|(n, &(ref node, (start, end)))| {
+    let prototype = match node {
+      &ExternNode(ref prototype) => prototype,
+      &FunctionNode(ref function) => &function.prototype
+    };
+    let kind = match prototype.ftype {
+      Normal => SYMBOL_FUNCTION,
+      _ => SYMBOL_OPERATOR
+    };
+    let i = document.name_token(n);
+    object(vec![
+      ("name", string(&prototype.name)),
+      ("detail", string(&format!("{}", prototype))),
+      ("kind", Json::U64(kind)),
+      ("range", document.range(start, end)),
+      ("selectionRange", document.range(i, i + 1))
+    ])
+  }).collect();
+  Json::Array(symbols)
+}
+
+/// Semantic tokens of a document, encoded relative to each other as the protocol wants them.
+fn semantic_tokens(document: &Document) -> Vec<u64> {
+  let mut types = document.tokens.iter().enumerate().map(|(i, token)| match *token {
+    Def | Extern | If | Then | Else | For | In | Binary | Unary => Some(KEYWORD),
+    Number(_) => Some(NUMBER),
+    Operator(_) => Some(OPERATOR),
+    Ident(_) => match (document.tokens.get(i + 1), if i == 0 { None } else { document.tokens.get(i - 1) }) {
+      (Some(&LeftParen), _) | (_, Some(&Def)) | (_, Some(&Extern)) => Some(FUNCTION),
+      _ => Some(VARIABLE)
+    },
+    Delimiter | LeftParen | RightParen | Comma => None
+  }).collect::<Vec<_>>();
+
+  // arguments are parameters wherever they are used in their function
+  for (n, &(ref node, (_, end))) in document.nodes.iter().enumerate() {
+    let args = match node {
+      &ExternNode(ref prototype) => &prototype.args,
+      &FunctionNode(ref function) => &function.prototype.args
+    };
+    for i in document.name_token(n) + 1..end {
+      if let Ident(ref name) = document.tokens[i] {
+        if args.contains(name) && types[i] == Some(VARIABLE) {
+          types[i] = Some(PARAMETER);
+        }
+      }
+    }
+  }
+
+  let mut data = Vec::new();
+  let (mut previous_line, mut previous_column) = (0, 0);
+  for (span, token_type) in document.spans.iter().zip(types.into_iter()) {
+    let token_type = match token_type {
+      Some(token_type) => token_type,
+      None => continue
+    };
+    let (line, column) = (span.start.line as u64 - 1, span.start.column as u64 - 1);
+    data.push(line - previous_line);
+    data.push(if line == previous_line { column - previous_column } else { column });
+    data.push((span.end.column - span.start.column) as u64);
+    data.push(token_type);
+    data.push(0);
+    previous_line = line;
+    previous_column = column;
+  }
+  data
+}
+
+fn diagnostic(range: Json, severity: u64, message: &str) -> Json {
+  object(vec![
+    ("range", range),
+    ("severity", Json::U64(severity)),
+    ("source", string("kaleidoscope")),
+    ("message", string(message))
+  ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+  object(vec![
+    ("jsonrpc", string("2.0")),
+    ("method", string("textDocument/publishDiagnostics")),
+    ("params", object(vec![("uri", string(uri)), ("diagnostics", Json::Array(diagnostics))]))
+  ])
+}
+
+fn response(id: Json, result: Result<Json, (i64, String)>) -> Json {
+  let mut response = BTreeMap::new();
+  response.insert("jsonrpc".to_string(), string("2.0"));
+  response.insert("id".to_string(), id);
+  match result {
+    Ok(result) => {
+      response.insert("result".to_string(), result);
+    },
+    Err((code, message)) => {
+      response.insert("error".to_string(), object(vec![("code", Json::I64(code)), ("message", string(&message))]));
+    }
+  }
+  Json::Object(response)
+}
+
+fn range(span: Span) -> Json {
+  object(vec![("start", position(span.start)), ("end", position(span.end))])
+}
+
+fn position(location: Location) -> Json {
+  object(vec![
+    ("line", Json::U64(location.line as u64 - 1)),
+    ("character", Json::U64(location.column as u64 - 1))
+  ])
+}
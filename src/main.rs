@@ -12,27 +12,182 @@ extern crate iron_llvm;
 
 use docopt::Docopt;
 use kaleidoscope::driver;
+use kaleidoscope::editor;
+use kaleidoscope::formatter;
+use kaleidoscope::guard::Limits;
+use kaleidoscope::lint;
+use kaleidoscope::prelude;
+use kaleidoscope::protocol;
+use kaleidoscope::serialization;
+use kaleidoscope::serialization::Format;
+use kaleidoscope::passes::PassOptions;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
 
 const USAGE: &'static str = "
-Usage: kaleidoscope [(-l | -p | -i)]
+Usage:
+  kaleidoscope [(-l | -p | -i)] [-O LEVEL | --passes=<list>] [-g] [--inline-across-modules] [--hot-reload] [--report-tail-calls] [--stack-size=<mb>] [--timeout=<ms>] [--prelude=<path> | --no-prelude] [--resume=<file>] [--cache-bitcode] [--stats] [--quiet] [--protocol=<name>]
+  kaleidoscope --emit=<format> <source>
+  kaleidoscope run-ast [--format=<format>] [-O LEVEL | --passes=<list>] [--inline-across-modules] [--report-tail-calls] [--stack-size=<mb>] [--timeout=<ms>] [--prelude=<path> | --no-prelude] <ast-file>
+  kaleidoscope fmt [--check] <file>...
+  kaleidoscope lint [--prelude=<path> | --no-prelude] <file>...
 
 Options:
-  -l  Run only lexer and show its output.
-  -p  Run only parser and show its output.
-  -i  Run only IR builder and show its output.
+  -l                 Run only lexer and show its output.
+  -p                 Run only parser and show its output.
+  -i                 Run only IR builder and show its output.
+  -O LEVEL           Optimization level from 0 to 3 [default: 1].
+  --passes=<list>    Comma separated passes to run instead of an optimization level:
+                     basicaa, instcombine, reassociate, gvn, simplifycfg, tailcallelim,
+                     loop-rotate, licm, loop-unroll, loop-vectorize, slp-vectorize, inline,
+                     globaldce. The passes before the first inline or globaldce run on
+                     every function as it is defined, the others on whole modules.
+  -g                 Attach debug info to the generated code: a subprogram for every
+                     function and the line and column of every expression, for gdb and
+                     perf. Lines count from the first input, in a source named <stdin>.
+  --inline-across-modules
+                     Let the inliner see small functions compiled for earlier inputs.
+                     Needs the inline pass, e.g. from -O2. Has no effect with
+                     --hot-reload, whose calls always go to the latest definition.
+  --hot-reload       Allow redefining functions in the REPL. Callers compiled before use
+                     the new definition too.
+  --report-tail-calls
+                     Warn about recursive calls that are not in tail position and so are
+                     not turned into loops.
+  --stack-size=<mb>  Stack size in megabytes for running generated code [default: 8].
+  --timeout=<ms>     Interrupt a top-level expression that runs longer than this many
+                     milliseconds, 0 for no limit [default: 0]. Ctrl-C interrupts one too
+                     when stdin is a terminal. Either way generated code polls a flag at
+                     every function entry and loop iteration, a load and a branch that is
+                     left out when there is neither a timeout nor a terminal.
+  --prelude=<path>   Compile this file before the first input instead of the standard
+                     prelude. Can also be set with KALEIDOSCOPE_PRELUDE.
+  --no-prelude       Start without any definitions.
+  --resume=<file>    Restore a session saved with :save before the first prompt.
+  --cache-bitcode    Let :save also write the compiled definitions to <file>.bc, which
+                     :restore and --resume then load instead of compiling the source.
+  --stats            After every input, show the time spent tokenizing, parsing, generating
+                     code, optimizing, finalizing and executing it, the instructions
+                     before and after optimization, the modules held by the JIT and how
+                     many of them were compiled.
+  --quiet            Print only results and errors: no prompts and no IR of definitions.
+                     This is the default when stdin is not a terminal. The exit status
+                     is 1 if there were errors.
+  --protocol=<name>  Answer requests read from stdin instead of running the REPL. The
+                     only protocol is json: one request and one response per line, with
+                     captured output, diagnostics and IR. Not for -l and -p.
+  --emit=<format>    Print the AST of a source file as ast-json or ast-sexpr.
+  --format=<format>  Format of the AST file to run, ast-json or ast-sexpr.
+                     Guessed from the file extension (.json) by default.
+  --check            Only check that the files are formatted, exit with a non-zero status if not.
+
+The lint command reports syntax errors, unknown names, wrong numbers of arguments and
+warnings: unused parameters, loop variables hiding parameters, constant conditions,
+definitions of built-in operators and operator precedences that change or are already
+taken. The exit status is 2 if there were errors and 1 if there were only warnings.
 ";
 
+#[allow(non_snake_case)]
 #[derive(Debug, RustcDecodable)]
 struct Args {
+  cmd_fmt: bool,
+  cmd_lint: bool,
+  cmd_run_ast: bool,
+  arg_file: Vec<String>,
+  arg_source: String,
+  arg_ast_file: String,
+  flag_emit: Option<String>,
+  flag_format: Option<String>,
   flag_l: bool,
   flag_p: bool,
   flag_i: bool,
+  flag_check: bool,
+  flag_O: u32,
+  flag_passes: Option<String>,
+  flag_g: bool,
+  flag_inline_across_modules: bool,
+  flag_hot_reload: bool,
+  flag_report_tail_calls: bool,
+  flag_stack_size: usize,
+  flag_timeout: u32,
+  flag_prelude: Option<String>,
+  flag_no_prelude: bool,
+  flag_resume: Option<String>,
+  flag_cache_bitcode: bool,
+  flag_stats: bool,
+  flag_quiet: bool,
+  flag_protocol: Option<String>,
 }
 
 fn main() {
   // let args: Args = Args::docopt().decode().unwrap_or_else(|e| e.exit());
   let args: Args = Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|e| e.exit());
 
+  let pass_options = match args.flag_passes {
+    Some(ref list) => PassOptions::with_pass_list(list),
+    None => PassOptions::with_opt_level(args.flag_O)
+  };
+  let options = driver::Options {
+    passes: pass_options.unwrap_or_else(|message| {
+      println!("Error: {}", message);
+      std::process::exit(1);
+    }),
+    inline_across_modules: args.flag_inline_across_modules,
+    hot_reload: args.flag_hot_reload,
+    report_tail_calls: args.flag_report_tail_calls,
+    limits: Limits {
+      stack_size: args.flag_stack_size * 1024 * 1024,
+      timeout: args.flag_timeout
+    },
+    prelude: load_prelude(&args),
+    cache_bitcode: args.flag_cache_bitcode,
+    resume: args.flag_resume.clone(),
+    stats: args.flag_stats,
+    quiet: args.flag_quiet || !editor::stdin_is_tty(),
+    interactive: editor::stdin_is_tty(),
+    debug_info: args.flag_g
+  };
+
+  if args.cmd_fmt {
+    std::process::exit(formatter::format_files(&args.arg_file, args.flag_check));
+  }
+
+  if args.cmd_lint {
+    std::process::exit(lint::lint_files(&args.arg_file, options.prelude.as_ref().map(|prelude| prelude.as_str())));
+  }
+
+  if let Some(ref name) = args.flag_emit {
+    let format = parse_format(name);
+    match driver::emit_ast(read_file(&args.arg_source).as_str(), format) {
+      Ok(output) => print!("{}", output),
+      Err(errors) => {
+        for message in errors.iter() {
+          println!("Error: {}", message);
+        }
+        std::process::exit(1);
+      }
+    }
+    return
+  }
+
+  if args.cmd_run_ast {
+    let format = match args.flag_format {
+      Some(ref name) => parse_format(name),
+      None if args.arg_ast_file.ends_with(".json") => serialization::JSON,
+      None => serialization::SExpr
+    };
+    match serialization::deserialize(read_file(&args.arg_ast_file).as_str(), format) {
+      Ok(ast) => driver::run_ast(ast.as_slice(), &options),
+      Err(message) => {
+        println!("Error: {}", message);
+        std::process::exit(1);
+      }
+    }
+    return
+  }
+
   let stage = if args.flag_l {
     driver::Tokens
   } else if args.flag_i {
@@ -43,5 +198,52 @@ fn main() {
     driver::Exec
   };
 
-  driver::main_loop(stage);
+  if let Some(ref name) = args.flag_protocol {
+    if name.as_str() != "json" {
+      println!("Unknown protocol: {}", name);
+      std::process::exit(1);
+    }
+    if stage == driver::Tokens || stage == driver::AST {
+      println!("Error: --protocol needs the default or the -i stage");
+      std::process::exit(1);
+    }
+    protocol::serve(stage, &options);
+    return
+  }
+
+  let errors = driver::main_loop(stage, &options);
+  if options.quiet && errors > 0 {
+    std::process::exit(1);
+  }
+}
+
+fn parse_format(name: &str) -> Format {
+  match Format::from_name(name) {
+    Some(format) => format,
+    None => {
+      println!("Unknown AST format: {}", name);
+      std::process::exit(1);
+    }
+  }
+}
+
+fn read_file(path: &str) -> String {
+  let mut content = String::new();
+  match File::open(path).and_then(|mut f| f.read_to_string(&mut content)) {
+    Ok(_) => content,
+    Err(e) => {
+      println!("{}: {}", path, e);
+      std::process::exit(1);
+    }
+  }
+}
+
+fn load_prelude(args: &Args) -> Option<String> {
+  if args.flag_no_prelude {
+    return None
+  }
+  match args.flag_prelude.clone().or(env::var(prelude::PRELUDE_VARIABLE).ok()) {
+    Some(path) => Some(read_file(&path)),
+    None => Some(prelude::STANDARD.to_string())
+  }
 }
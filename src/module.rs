@@ -1,28 +1,54 @@
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use iron_llvm::{LLVMRef, LLVMRefCtor};
 use iron_llvm::core;
 use iron_llvm::core::value::{FunctionRef, Function};
 
 use jitter::JITter;
+use passes::{ModulePassManager, PassOptions};
+use timing::Stats;
+
+use llvm_sys::LLVMLinkage::LLVMAvailableExternallyLinkage;
+use llvm_sys::core::{LLVMDisposeMessage, LLVMPrintModuleToString};
+use llvm_sys::core::{LLVMGetFirstFunction, LLVMGetLinkage, LLVMGetNextFunction, LLVMGetValueName, LLVMIsDeclaration};
+use llvm_sys::core::{LLVMGetFirstGlobal, LLVMGetNextGlobal};
+use llvm_sys::core::{LLVMGetNamedFunction, LLVMPrintValueToString};
+use llvm_sys::core::{LLVMGetFirstBasicBlock, LLVMGetFirstInstruction, LLVMGetNextBasicBlock, LLVMGetNextInstruction};
+use llvm_sys::core::{LLVMCreateMemoryBufferWithContentsOfFile, LLVMDisposeMemoryBuffer, LLVMGetGlobalContext};
+use llvm_sys::bit_reader::LLVMParseBitcodeInContext;
+use llvm_sys::bit_writer::LLVMWriteBitcodeToFile;
 
-use llvm_sys::prelude::LLVMValueRef;
+use builder::SLOT_SUFFIX;
+use llvm_sys::core::{LLVMGetElementType, LLVMTypeOf};
+use llvm_sys::prelude::{LLVMTypeRef, LLVMValueRef};
 
 pub trait ModuleProvider {
   fn dump(&self);
+  fn print_to_string(&self) -> String;
   fn get_module(&mut self) -> &mut core::Module;
   fn get_function(&mut self, name: &str) -> Option<(FunctionRef, bool)>;
   fn get_pass_manager(&mut self) -> &mut core::FunctionPassManager;
+  fn run_module_passes(&mut self);
+  // IR of the latest definition of a function, or of its declaration if there is none
+  fn function_to_string(&self, name: &str) -> Option<String>;
+  // names of all functions defined or declared so far
+  fn function_names(&self) -> Vec<String>;
 }
 
 pub struct SimpleModuleProvider {
   module: core::Module,
   func_pass_manager: core::FunctionPassManager,
+  module_pass_manager: ModulePassManager,
 }
 
 impl SimpleModuleProvider {
-  pub fn new(name: &str) -> SimpleModuleProvider {
-    let (module, func_pass_manager) = new_module(name);
+  pub fn new(name: &str, pass_options: &PassOptions) -> SimpleModuleProvider {
+    let (module, func_pass_manager, module_pass_manager) = new_module(name, pass_options);
     SimpleModuleProvider {
       module: module,
-      func_pass_manager: func_pass_manager
+      func_pass_manager: func_pass_manager,
+      module_pass_manager: module_pass_manager
     }
   }
 }
@@ -32,9 +58,24 @@ impl JITter for SimpleModuleProvider {
     self
   }
 
-  fn run_function(&mut self, _f: LLVMValueRef) -> f64 {
+  fn run_function(&mut self, _f: LLVMValueRef) -> Result<f64, String> {
     panic!("not implemented")
   }
+
+  fn add_compiled_module(&mut self, _module: core::Module) -> Result<(), String> {
+    Err("compiled modules can only be added to the JIT".to_string())
+  }
+
+  // everything stays in the one module that is printed
+  fn finish_definition(&mut self) {
+  }
+
+  fn remove_unused_modules(&mut self) {
+  }
+
+  fn take_stats(&mut self) -> Stats {
+    Stats { modules: 1, ..Stats::default() }
+  }
 }
 
 impl ModuleProvider for SimpleModuleProvider {
@@ -42,6 +83,10 @@ impl ModuleProvider for SimpleModuleProvider {
     self.module.dump();
   }
 
+  fn print_to_string(&self) -> String {
+    module_to_string(&self.module)
+  }
+
   fn get_module(&mut self) -> &mut core::Module {
     &mut self.module
   }
@@ -56,17 +101,165 @@ impl ModuleProvider for SimpleModuleProvider {
   fn get_pass_manager(&mut self) -> &mut core::FunctionPassManager {
     &mut self.func_pass_manager
   }
+
+  fn run_module_passes(&mut self) {
+    self.module_pass_manager.run(&mut self.module);
+  }
+
+  fn function_to_string(&self, name: &str) -> Option<String> {
+    function_to_string(&self.module, name).map(|(ir, _)| ir)
+  }
+
+  fn function_names(&self) -> Vec<String> {
+    function_names(&self.module)
+  }
 }
 
-pub fn new_module(name: &str) -> (core::Module, core::FunctionPassManager) {
+pub fn new_module(name: &str, pass_options: &PassOptions) -> (core::Module, core::FunctionPassManager, ModulePassManager) {
   let module = core::Module::new(name);
-  let mut function_passmanager = core::FunctionPassManager::new(&module);
-  function_passmanager.add_basic_alias_analysis_pass();
-  function_passmanager.add_instruction_combining_pass();
-  function_passmanager.add_reassociate_pass();
-  function_passmanager.add_GVN_pass();
-  function_passmanager.add_CFG_simplification_pass();
-  function_passmanager.initialize();
-
-  (module, function_passmanager)
+  let function_passmanager = pass_options.new_function_pass_manager(&module);
+  let module_passmanager = pass_options.new_module_pass_manager();
+
+  (module, function_passmanager, module_passmanager)
+}
+
+pub fn module_to_string(module: &core::Module) -> String {
+  unsafe {
+    let message = LLVMPrintModuleToString(module.to_ref());
+    let result = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeMessage(message);
+    result
+  }
+}
+
+/// Names of the functions that have a body in `module` which is emitted by code generation.
+pub fn defined_functions(module: &core::Module) -> Vec<String> {
+  let mut names = Vec::new();
+  unsafe {
+    let mut function = LLVMGetFirstFunction(module.to_ref());
+    while !function.is_null() {
+      let name = CStr::from_ptr(LLVMGetValueName(function)).to_string_lossy().into_owned();
+      if LLVMIsDeclaration(function) == 0 && LLVMGetLinkage(function) != LLVMAvailableExternallyLinkage
+        && !name.is_empty() {
+        names.push(name);
+      }
+      function = LLVMGetNextFunction(function);
+    }
+  }
+  names
+}
+
+/// Names and function types of the functions `module` only declares, e.g. externs, leaving out
+/// intrinsics and runtime functions.
+pub fn declared_functions(module: &core::Module) -> Vec<(String, LLVMTypeRef)> {
+  let mut declarations = Vec::new();
+  unsafe {
+    let mut function = LLVMGetFirstFunction(module.to_ref());
+    while !function.is_null() {
+      let name = CStr::from_ptr(LLVMGetValueName(function)).to_string_lossy().into_owned();
+      if LLVMIsDeclaration(function) != 0 && !name.starts_with("llvm.") && !name.starts_with("kaleidoscope.") {
+        declarations.push((name, LLVMGetElementType(LLVMTypeOf(function))));
+      }
+      function = LLVMGetNextFunction(function);
+    }
+  }
+  declarations
+}
+
+/// Names of the functions defined or declared in `module`, leaving out anonymous functions,
+/// intrinsics and runtime functions.
+pub fn function_names(module: &core::Module) -> Vec<String> {
+  let mut names = Vec::new();
+  unsafe {
+    let mut function = LLVMGetFirstFunction(module.to_ref());
+    while !function.is_null() {
+      let name = CStr::from_ptr(LLVMGetValueName(function)).to_string_lossy().into_owned();
+      if !name.is_empty() && !name.starts_with("llvm.") && !name.starts_with("kaleidoscope.") {
+        names.push(name);
+      }
+      function = LLVMGetNextFunction(function);
+    }
+  }
+  names
+}
+
+pub fn count_instructions(function: LLVMValueRef) -> usize {
+  let mut count = 0;
+  unsafe {
+    let mut block = LLVMGetFirstBasicBlock(function);
+    while !block.is_null() {
+      let mut instruction = LLVMGetFirstInstruction(block);
+      while !instruction.is_null() {
+        count += 1;
+        instruction = LLVMGetNextInstruction(instruction);
+      }
+      block = LLVMGetNextBasicBlock(block);
+    }
+  }
+  count
+}
+
+/// Names of the hot reload slots `module` refers to.
+pub fn slot_globals(module: &core::Module) -> Vec<String> {
+  let mut names = Vec::new();
+  unsafe {
+    let mut global = LLVMGetFirstGlobal(module.to_ref());
+    while !global.is_null() {
+      let name = CStr::from_ptr(LLVMGetValueName(global)).to_string_lossy().into_owned();
+      if name.ends_with(SLOT_SUFFIX) {
+        names.push(name);
+      }
+      global = LLVMGetNextGlobal(global);
+    }
+  }
+  names
+}
+
+/// IR of function `name` in `module` and whether it has a body there.
+pub fn function_to_string(module: &core::Module, name: &str) -> Option<(String, bool)> {
+  let name = CString::new(name).unwrap();
+  unsafe {
+    let function = LLVMGetNamedFunction(module.to_ref(), name.as_ptr());
+    if function.is_null() {
+      return None
+    }
+    Some((value_to_string(function), LLVMIsDeclaration(function) == 0))
+  }
+}
+
+pub fn value_to_string(value: LLVMValueRef) -> String {
+  unsafe { take_message(LLVMPrintValueToString(value)) }
+}
+
+pub fn write_bitcode(module: &core::Module, path: &str) -> Result<(), String> {
+  let c_path = CString::new(path).unwrap();
+  if unsafe { LLVMWriteBitcodeToFile(module.to_ref(), c_path.as_ptr()) } != 0 {
+    return Err(format!("{}: cannot write bitcode", path))
+  }
+  Ok(())
+}
+
+pub fn read_bitcode(path: &str) -> Result<core::Module, String> {
+  let c_path = CString::new(path).unwrap();
+  unsafe {
+    let mut buffer = ptr::null_mut();
+    let mut message = ptr::null_mut();
+    if LLVMCreateMemoryBufferWithContentsOfFile(c_path.as_ptr(), &mut buffer, &mut message) != 0 {
+      return Err(take_message(message))
+    }
+
+    let mut module = ptr::null_mut();
+    let failed = LLVMParseBitcodeInContext(LLVMGetGlobalContext(), buffer, &mut module, &mut message) != 0;
+    LLVMDisposeMemoryBuffer(buffer);
+    if failed {
+      return Err(take_message(message))
+    }
+    Ok(core::Module::from_ref(module))
+  }
+}
+
+unsafe fn take_message(message: *mut ::libc::c_char) -> String {
+  let result = CStr::from_ptr(message).to_string_lossy().into_owned();
+  LLVMDisposeMessage(message);
+  result
 }
@@ -65,13 +65,29 @@ pub struct ParserSettings {
   operator_precedence: HashMap<String, i32>
 }
 
+impl ParserSettings {
+  pub fn precedence(&self, operator: &str) -> Option<i32> {
+    self.operator_precedence.get(operator).map(|p| *p)
+  }
+
+  /// Binary operators with their precedences, highest precedence first.
+  pub fn operators(&self) -> Vec<(String, i32)> {
+    let mut operators = self.operator_precedence.iter()
+      .map(|(operator, precedence)| (operator.clone(), *precedence))
+      .collect::<Vec<_>>();
+    operators.sort_by(|a, b| (b.1, &a.0).cmp(&(a.1, &b.0)));
+    operators
+  }
+}
+
 enum PartParsingResult<T> {
   Good(T, Vec<Token>),
   NotComplete,
   Bad(String)
 }
 
-pub type ParsingResult = Result<(Vec<ASTNode>, Vec<Token>), String>;
+/// Nodes parsed successfully, tokens of an incomplete trailing item and all syntax errors found.
+pub type ParsingResult = (Vec<ASTNode>, Vec<Token>, Vec<String>);
 
 pub fn default_parser_settings() -> ParserSettings {
   let mut operator_precedence = HashMap::new();
@@ -84,17 +100,34 @@ pub fn default_parser_settings() -> ParserSettings {
   ParserSettings{operator_precedence: operator_precedence}
 }
 
+pub type PositionedParsingResult = (Vec<(ASTNode, (usize, usize))>, Vec<Token>, Vec<(String, usize)>);
+
+// Parsing does not stop at the first error. After a bad item the tokens are skipped up to the
+// next synchronization point (`;`, `def` or `extern`) and parsing resumes from there, so that
+// every syntax error of the input is reported at once.
 pub fn parse(tokens: &[Token], parsed_trees: &[ASTNode], settings: &mut ParserSettings) -> ParsingResult {
+  let (positioned, rest, errors) = parse_with_positions(tokens, settings);
+  let mut asts = parsed_trees.to_vec();
+  asts.extend(positioned.into_iter().map(|(ast_node, _)| ast_node));
+  (asts, rest, errors.into_iter().map(|(message, _)| message).collect())
+}
+
+/// Like `parse`, but also gives the range of tokens every node was parsed from, end excluded,
+/// and the index of the token every error was found at: the last token the bad item consumed,
+/// or `tokens.len()` if it ran into the end of the input without consuming any.
+pub fn parse_with_positions(tokens: &[Token], settings: &mut ParserSettings) -> PositionedParsingResult {
   let mut rest = tokens.to_vec();
   rest.reverse();
 
-  let mut asts = parsed_trees.to_vec();
+  let mut asts = Vec::new();
+  let mut errors = Vec::new();
 
   loop {
     let cur_token = match rest.last() {
       Some(t) => t.clone(),
       None => break
     };
+    let start = tokens.len() - rest.len();
 
     let result = match cur_token {
       Def => parse_function(&mut rest, settings),
@@ -104,14 +137,44 @@ pub fn parse(tokens: &[Token], parsed_trees: &[ASTNode], settings: &mut ParserSe
     };
 
     match result {
-      Good(ast_node, _) => asts.push(ast_node),
+      Good(ast_node, _) => asts.push((ast_node, (start, tokens.len() - rest.len()))),
       NotComplete => break,
-      Bad(message) => return Err(message),
+      Bad(message) => {
+        let end = tokens.len() - rest.len();
+        // the token the error was found at was consumed along with the item
+        errors.push((message, if end > start { end - 1 } else { end }));
+        let last = if end > start + 1 { Some(&tokens[end - 1]) } else { None };
+        synchronize(&mut rest, last);
+      }
     }
   }
 
   rest.reverse();
-  Ok((asts, rest))
+  (asts, rest, errors)
+}
+
+// Skips the remains of a bad item. `rest` is reversed, so the next token is the last one.
+// `last` is the last token the item consumed, after its first one. If it is a `;`, the item is
+// over already; a `def` or `extern` starts the next item, which is put back.
+fn synchronize(rest: &mut Vec<Token>, last: Option<&Token>) {
+  match last {
+    Some(&Delimiter) => return,
+    Some(&Def) | Some(&Extern) => {
+      rest.push(last.unwrap().clone());
+      return
+    },
+    _ => ()
+  }
+  loop {
+    match rest.last() {
+      None | Some(&Def) | Some(&Extern) => break,
+      Some(&Delimiter) => {
+        rest.pop();
+        break
+      },
+      _ => {rest.pop();}
+    }
+  }
 }
 
 macro_rules! parse_try(
@@ -0,0 +1,151 @@
+use iron_llvm::LLVMRef;
+use iron_llvm::core;
+
+use llvm_sys::core::{LLVMCreatePassManager, LLVMDisposePassManager, LLVMRunPassManager};
+use llvm_sys::prelude::LLVMPassManagerRef;
+use llvm_sys::transforms::ipo::{LLVMAddFunctionInliningPass, LLVMAddGlobalDCEPass};
+use llvm_sys::transforms::scalar::*;
+use llvm_sys::transforms::vectorize::{LLVMAddLoopVectorizePass, LLVMAddSLPVectorizePass};
+
+pub use self::PassKind::{FunctionPass, ModulePass};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PassKind {
+  // works on one function at a time, so it can run as soon as a function is defined
+  FunctionPass,
+  // needs a whole module
+  ModulePass
+}
+
+type AddPass = unsafe extern "C" fn(LLVMPassManagerRef);
+
+fn pass_by_name(name: &str) -> Option<(PassKind, AddPass)> {
+  let pass: (PassKind, AddPass) = match name {
+    "basicaa" => (FunctionPass, LLVMAddBasicAliasAnalysisPass),
+    "instcombine" => (FunctionPass, LLVMAddInstructionCombiningPass),
+    "reassociate" => (FunctionPass, LLVMAddReassociatePass),
+    "gvn" => (FunctionPass, LLVMAddGVNPass),
+    "simplifycfg" => (FunctionPass, LLVMAddCFGSimplificationPass),
+    "tailcallelim" => (FunctionPass, LLVMAddTailCallEliminationPass),
+    "loop-rotate" => (FunctionPass, LLVMAddLoopRotatePass),
+    "licm" => (FunctionPass, LLVMAddLICMPass),
+    "loop-unroll" => (FunctionPass, LLVMAddLoopUnrollPass),
+    "loop-vectorize" => (FunctionPass, LLVMAddLoopVectorizePass),
+    "slp-vectorize" => (FunctionPass, LLVMAddSLPVectorizePass),
+    "inline" => (ModulePass, LLVMAddFunctionInliningPass),
+    "globaldce" => (ModulePass, LLVMAddGlobalDCEPass),
+    _ => return None
+  };
+  Some(pass)
+}
+
+// The passes of the -O levels. -O1 is cheap enough to run on every definition; tailcallelim
+// keeps self recursion in tail position from growing the stack. From -O2 on, the loop passes
+// run after inlining, on the loops of the inlined functions too.
+const O1: &'static str = "basicaa,instcombine,reassociate,gvn,simplifycfg,tailcallelim";
+const O2_MODULE: &'static str = "inline,instcombine,gvn,simplifycfg,loop-rotate,licm,globaldce";
+const O3_MODULE: &'static str = "inline,instcombine,gvn,simplifycfg,loop-rotate,licm,loop-unroll,loop-vectorize,\
+                                 slp-vectorize,globaldce";
+
+/// Optimizations run on the generated code: either one of the `-O` levels or an explicit list
+/// of pass names given with `--passes`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct PassOptions {
+  function_passes: Vec<String>,
+  module_passes: Vec<String>
+}
+
+impl PassOptions {
+  pub fn with_opt_level(level: u32) -> Result<PassOptions, String> {
+    match level {
+      0 => PassOptions::with_pass_list(""),
+      1 => PassOptions::with_pass_list(O1),
+      2 => PassOptions::with_pass_list(&format!("{},{}", O1, O2_MODULE)),
+      3 => PassOptions::with_pass_list(&format!("{},{}", O1, O3_MODULE)),
+      _ => Err(format!("invalid optimization level {}", level))
+    }
+  }
+
+  /// Builds the pipeline from a comma separated list of pass names. The passes before the
+  /// first one that needs a whole module (`inline`, `globaldce`) are run on every definition,
+  /// that one and all after it on whole modules.
+  pub fn with_pass_list(list: &str) -> Result<PassOptions, String> {
+    let mut options = PassOptions {
+      function_passes: vec![],
+      module_passes: vec![]
+    };
+
+    for name in list.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+      match pass_by_name(name) {
+        Some((FunctionPass, _)) if options.module_passes.is_empty() => options.function_passes.push(name.to_string()),
+        Some(_) => options.module_passes.push(name.to_string()),
+        None => return Err(format!("unknown pass '{}'", name))
+      }
+    }
+    Ok(options)
+  }
+
+  /// Passes run on every definition.
+  pub fn function_passes(&self) -> &[String] {
+    &self.function_passes
+  }
+
+  /// Passes run on whole modules.
+  pub fn module_passes(&self) -> &[String] {
+    &self.module_passes
+  }
+
+  /// Whether no pass is run at all, as with -O0.
+  pub fn is_empty(&self) -> bool {
+    self.function_passes.is_empty() && self.module_passes.is_empty()
+  }
+
+  pub fn new_function_pass_manager(&self, module: &core::Module) -> core::FunctionPassManager {
+    let mut function_passmanager = core::FunctionPassManager::new(module);
+    for name in self.function_passes.iter() {
+      let (_, add_pass) = pass_by_name(name).unwrap();
+      unsafe { add_pass(function_passmanager.to_ref()) };
+    }
+    function_passmanager.initialize();
+    function_passmanager
+  }
+
+  pub fn new_module_pass_manager(&self) -> ModulePassManager {
+    let pass_manager = ModulePassManager {
+      pass_manager: unsafe { LLVMCreatePassManager() },
+      empty: self.module_passes.is_empty()
+    };
+    for name in self.module_passes.iter() {
+      let (_, add_pass) = pass_by_name(name).unwrap();
+      unsafe { add_pass(pass_manager.pass_manager) };
+    }
+    pass_manager
+  }
+}
+
+impl Default for PassOptions {
+  fn default() -> PassOptions {
+    PassOptions::with_opt_level(1).unwrap()
+  }
+}
+
+pub struct ModulePassManager {
+  pass_manager: LLVMPassManagerRef,
+  empty: bool
+}
+
+impl ModulePassManager {
+  /// Returns true if the module was modified.
+  pub fn run(&mut self, module: &mut core::Module) -> bool {
+    if self.empty {
+      return false
+    }
+    unsafe { LLVMRunPassManager(self.pass_manager, module.to_ref()) != 0 }
+  }
+}
+
+impl Drop for ModulePassManager {
+  fn drop(&mut self) {
+    unsafe { LLVMDisposePassManager(self.pass_manager) };
+  }
+}
@@ -0,0 +1,7 @@
+//! Definitions compiled into every session before the first prompt.
+
+/// The prelude used unless another one is given with `--prelude` or `KALEIDOSCOPE_PRELUDE`.
+pub const STANDARD: &'static str = include_str!("prelude.ks");
+
+/// Environment variable naming a prelude file.
+pub const PRELUDE_VARIABLE: &'static str = "KALEIDOSCOPE_PRELUDE";
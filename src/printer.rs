@@ -0,0 +1,176 @@
+use std::fmt;
+
+use parser::*;
+
+/// Displays an AST value as Kaleidoscope source, parenthesized according to the operator
+/// precedences of the given settings. The plain `Display` impls use the default precedences.
+pub struct Pretty<'a, T: 'a> {
+  value: &'a T,
+  settings: &'a ParserSettings
+}
+
+pub fn pretty<'a, T>(value: &'a T, settings: &'a ParserSettings) -> Pretty<'a, T> {
+  Pretty {
+    value: value,
+    settings: settings
+  }
+}
+
+impl<'a> fmt::Display for Pretty<'a, ASTNode> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write_node(f, self.value, self.settings)
+  }
+}
+
+impl<'a> fmt::Display for Pretty<'a, Prototype> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write_prototype(f, self.value)
+  }
+}
+
+impl<'a> fmt::Display for Pretty<'a, Expression> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write_expr(f, self.value, self.settings)
+  }
+}
+
+impl fmt::Display for ASTNode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write_node(f, self, &default_parser_settings())
+  }
+}
+
+impl fmt::Display for Prototype {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write_prototype(f, self)
+  }
+}
+
+impl fmt::Display for Expression {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write_expr(f, self, &default_parser_settings())
+  }
+}
+
+fn write_node(f: &mut fmt::Formatter, node: &ASTNode, settings: &ParserSettings) -> fmt::Result {
+  match node {
+    &ExternNode(ref prototype) => {
+      try!(write!(f, "extern "));
+      try!(write_prototype(f, prototype));
+    },
+    &FunctionNode(ref function) => {
+      // top-level expressions are wrapped in anonymous functions by the parser
+      if function.prototype.name != "" {
+        try!(write!(f, "def "));
+        try!(write_prototype(f, &function.prototype));
+        try!(write!(f, " "));
+      }
+      try!(write_expr(f, &function.expression, settings));
+    }
+  }
+  write!(f, ";")
+}
+
+fn write_prototype(f: &mut fmt::Formatter, prototype: &Prototype) -> fmt::Result {
+  match prototype.ftype {
+    Normal => try!(write!(f, "{}", prototype.name)),
+    UnaryOp(ref op) => try!(write!(f, "unary{}", op)),
+    BinaryOp(ref op, precedence) => try!(write!(f, "binary{} {} ", op, precedence))
+  }
+  write!(f, "({})", prototype.args.join(", "))
+}
+
+fn write_expr(f: &mut fmt::Formatter, expr: &Expression, settings: &ParserSettings) -> fmt::Result {
+  match expr {
+    &LiteralExpr(ref value) => write!(f, "{}", value),
+
+    &VariableExpr(ref name) => write!(f, "{}", name),
+
+    &BinaryExpr(ref op, ref lhs, ref rhs) => {
+      let precedence = settings.precedence(op);
+      try!(write_operand(f, lhs, settings, precedence, false));
+      try!(write!(f, " {} ", op));
+      write_operand(f, rhs, settings, precedence, true)
+    },
+
+    &UnaryExpr(ref op, ref operand) => {
+      try!(write!(f, "{}", op));
+      write_parenthesized(f, operand, settings, unary_operand_needs_parens(operand))
+    },
+
+    &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+      try!(write!(f, "if "));
+      try!(write_expr(f, cond_expr, settings));
+      try!(write!(f, " then "));
+      try!(write_expr(f, then_expr, settings));
+      try!(write!(f, " else "));
+      write_expr(f, else_expr, settings)
+    },
+
+    &LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+      try!(write!(f, "for {} = ", var_name));
+      try!(write_expr(f, start_expr, settings));
+      try!(write!(f, ", "));
+      try!(write_expr(f, end_expr, settings));
+      // a missing step is parsed as 1.0
+      if **step_expr != LiteralExpr(1.0) {
+        try!(write!(f, ", "));
+        try!(write_expr(f, step_expr, settings));
+      }
+      try!(write!(f, " in "));
+      write_expr(f, body_expr, settings)
+    },
+
+    &CallExpr(ref name, ref args) => {
+      try!(write!(f, "{}(", name));
+      for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+          try!(write!(f, ", "));
+        }
+        try!(write_expr(f, arg, settings));
+      }
+      write!(f, ")")
+    }
+  }
+}
+
+/// Whether `operand` of a binary operator with `parent_precedence` needs parentheses. Binary
+/// operators are left associative, so an operand of the same precedence needs them only on the
+/// right. Operators with unknown precedence are always parenthesized. Conditionals and loops
+/// extend as far to the right as possible and are parenthesized too.
+pub fn operand_needs_parens(operand: &Expression, settings: &ParserSettings,
+                            parent_precedence: Option<i32>, is_rhs: bool) -> bool {
+  match operand {
+    &BinaryExpr(ref op, _, _) => match (settings.precedence(op), parent_precedence) {
+      (Some(precedence), Some(parent)) => precedence < parent || (is_rhs && precedence == parent),
+      _ => true
+    },
+    &ConditionalExpr{..} | &LoopExpr{..} => true,
+    _ => false
+  }
+}
+
+/// Whether the operand of a unary operator needs parentheses: the parser reads only a primary
+/// expression after one.
+pub fn unary_operand_needs_parens(operand: &Expression) -> bool {
+  match operand {
+    &BinaryExpr(..) | &ConditionalExpr{..} | &LoopExpr{..} => true,
+    _ => false
+  }
+}
+
+fn write_operand(f: &mut fmt::Formatter, operand: &Expression, settings: &ParserSettings,
+                 parent_precedence: Option<i32>, is_rhs: bool) -> fmt::Result {
+  write_parenthesized(f, operand, settings, operand_needs_parens(operand, settings, parent_precedence, is_rhs))
+}
+
+fn write_parenthesized(f: &mut fmt::Formatter, expr: &Expression, settings: &ParserSettings,
+                       parenthesize: bool) -> fmt::Result {
+  if parenthesize {
+    try!(write!(f, "("));
+    try!(write_expr(f, expr, settings));
+    write!(f, ")")
+  } else {
+    write_expr(f, expr, settings)
+  }
+}
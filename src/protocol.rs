@@ -0,0 +1,244 @@
+//! A machine-readable REPL for editors and notebooks: requests are read from stdin and
+//! responses written to stdout, one JSON object per line.
+//!
+//! Every request has a "method" and may have an "id", which is copied into the response:
+//!
+//!   {"id": 1, "method": "eval", "code": "def f(x) x + 1; f(2);", "ir": true}
+//!   {"id": 2, "method": "complete", "code": "f(sq", "cursor": 4}
+//!   {"id": 3, "method": "inspect", "name": "f"}
+//!   {"id": 4, "method": "reset"}
+//!
+//! Responses have a "status", "ok" or "error"; failed requests also have a "message". An eval
+//! is "ok" even if the code has errors, those are reported as diagnostics.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{BufRead, Write};
+
+use rustc_serialize::json::Json;
+
+use builtins;
+use dependencies::node_name;
+use driver::{Options, Session, Stage, Compiled, Returned, RuntimeError, CodegenError, Rejected};
+use editor;
+use jitter;
+use json_util::{object, string};
+use lexer::{token_span, tokenize_with_spans, Location, Span};
+use module;
+use parser::FunctionNode;
+use tail_calls;
+
+pub struct Server {
+  session: Session
+}
+
+impl Server {
+  /// Creates a server evaluating in `stage`, which is Exec or IR.
+  pub fn new(stage: Stage, options: &Options) -> Server {
+    Server {
+      session: Session::new(stage, options)
+    }
+  }
+
+  /// Answers one request line.
+  pub fn handle_line(&mut self, line: &str) -> String {
+    let request = match Json::from_str(line) {
+      Ok(request @ Json::Object(_)) => request,
+      Ok(_) => return failure(Json::Null, "request is not an object").to_string(),
+      Err(e) => return failure(Json::Null, &format!("{}", e)).to_string()
+    };
+    let id = request.find("id").cloned().unwrap_or(Json::Null);
+
+    let result = match request.find("method").and_then(|method| method.as_string()) {
+      Some("eval") => self.eval(&request),
+      Some("complete") => self.complete(&request),
+      Some("inspect") => self.inspect(&request),
+      Some("reset") => {
+        self.session.reset();
+        Ok(Vec::new())
+      },
+      Some(method) => Err(format!("unknown method {}", method)),
+      None => Err("missing method".to_string())
+    };
+
+    match result {
+      Ok(members) => {
+        let mut response = response(id, "ok");
+        for (key, value) in members.into_iter() {
+          response.insert(key.to_string(), value);
+        }
+        Json::Object(response).to_string()
+      },
+      Err(message) => failure(id, &message).to_string()
+    }
+  }
+
+  fn eval(&mut self, request: &Json) -> Result<Vec<(&'static str, Json)>, String> {
+    let code = try!(string_member(request, "code"));
+    let with_ir = request.find("ir").and_then(|ir| ir.as_boolean()).unwrap_or(false);
+
+    let (tokens, spans): (Vec<_>, Vec<_>) = tokenize_with_spans(&code).into_iter().unzip();
+    let (nodes, rest, errors) = self.session.parse_with_positions(tokens.as_slice());
+
+    let mut diagnostics = Vec::new();
+    for (message, position) in errors.into_iter() {
+      diagnostics.push(diagnostic("error", "parse", &message, token_span(&spans, position, position + 1)));
+    }
+    if !rest.is_empty() {
+      let start = tokens.len() - rest.len();
+      diagnostics.push(diagnostic("error", "parse", "unexpected end of input", token_span(&spans, start, tokens.len())));
+    }
+
+    let ast = nodes.iter().map(|&(ref node, _)| node.clone()).collect::<Vec<_>>();
+    if self.session.options().report_tail_calls {
+      for (node, &(_, (start, end))) in ast.iter().zip(nodes.iter()) {
+        if let &FunctionNode(ref function) = node {
+          for message in tail_calls::non_tail_recursive_calls(function).iter() {
+            diagnostics.push(diagnostic("warning", "tail-calls", message, token_span(&spans, start, end)));
+          }
+        }
+      }
+    }
+
+    for warning in self.session.lint(ast.as_slice(), Some(&code)).into_iter() {
+      let (start, end) = nodes[warning.node].1;
+      let span = warning.span.unwrap_or(token_span(&spans, start, end));
+      diagnostics.push(diagnostic("warning", "lint", &warning.message, span));
+    }
+
+    jitter::capture_output();
+    let evaluations = self.session.evaluate(ast.as_slice(), Some(&code));
+    let output = jitter::take_output();
+
+    let mut results = Vec::new();
+    for ((node, &(_, (start, end))), evaluation) in ast.iter().zip(nodes.iter()).zip(evaluations.into_iter()) {
+      let span = token_span(&spans, start, end);
+      let result = match evaluation {
+        Returned(value) => object(vec![("kind", string("value")), ("value", Json::F64(value))]),
+        Compiled(function) => {
+          let name = node_name(node);
+          let mut members = if name.is_empty() {
+            vec![("kind", string("expression"))]
+          } else {
+            vec![("kind", string("definition")), ("name", string(name))]
+          };
+          if with_ir {
+            members.push(("ir", string(&module::value_to_string(function))));
+          }
+          object(members)
+        },
+        RuntimeError(message) => {
+          diagnostics.push(diagnostic("error", "runtime", &message, span));
+          object(vec![("kind", string("error")), ("message", string(&message))])
+        },
+        CodegenError(message) => {
+          diagnostics.push(diagnostic("error", "codegen", &message, span));
+          object(vec![("kind", string("error")), ("message", string(&message))])
+        },
+        Rejected(errors) => {
+          for error in errors.iter() {
+            diagnostics.push(diagnostic("error", "semantic", &error.message, error.span.unwrap_or(span)));
+          }
+          let messages = errors.iter().map(|error| error.message.clone()).collect::<Vec<_>>();
+          object(vec![("kind", string("error")), ("message", string(&messages.join("\n")))])
+        }
+      };
+      results.push(result);
+    }
+
+    Ok(vec![
+      ("results", Json::Array(results)),
+      ("stdout", string(&output)),
+      ("diagnostics", Json::Array(diagnostics))
+    ])
+  }
+
+  fn complete(&mut self, request: &Json) -> Result<Vec<(&'static str, Json)>, String> {
+    let code = try!(string_member(request, "code")).chars().collect::<Vec<_>>();
+    let cursor = match request.find("cursor") {
+      Some(cursor) => try!(cursor.as_u64().ok_or("member 'cursor' is not a position".to_string())) as usize,
+      None => code.len()
+    };
+    if cursor > code.len() {
+      return Err(format!("cursor {} is after the end of the code", cursor))
+    }
+
+    let (start, matches) = editor::completions(self.session.completion_words().as_slice(), &code, cursor);
+    Ok(vec![
+      ("matches", Json::Array(matches.iter().map(|word| string(word)).collect())),
+      ("start", Json::U64(start as u64)),
+      ("end", Json::U64(cursor as u64))
+    ])
+  }
+
+  fn inspect(&mut self, request: &Json) -> Result<Vec<(&'static str, Json)>, String> {
+    let name = try!(string_member(request, "name"));
+    let mut members = Vec::new();
+
+    if let Some(signature) = self.session.signature(&name) {
+      members.push(("signature", string(&signature)));
+    }
+    let defined = self.session.definitions().iter().any(|node| node_name(node) == name.as_str());
+    if let (false, Some(builtin)) = (defined, builtins::find(&name)) {
+      members.push(("description", string(builtin.description)));
+    }
+    // functions of the prelude are only known by their IR
+    if let Some(ir) = self.session.function_ir(&name) {
+      members.push(("ir", string(&ir)));
+    }
+
+    members.insert(0, ("found", Json::Boolean(!members.is_empty())));
+    Ok(members)
+  }
+}
+
+/// Answers requests from stdin until it ends.
+pub fn serve(stage: Stage, options: &Options) {
+  let mut server = Server::new(stage, options);
+  let stdin = io::stdin();
+  for line in stdin.lock().lines() {
+    let line = match line {
+      Ok(line) => line,
+      Err(_) => break
+    };
+    if line.trim().is_empty() {
+      continue
+    }
+    println!("{}", server.handle_line(&line));
+    io::stdout().flush().unwrap();
+  }
+}
+
+fn diagnostic(severity: &str, phase: &str, message: &str, span: Span) -> Json {
+  object(vec![
+    ("severity", string(severity)),
+    ("phase", string(phase)),
+    ("message", string(message)),
+    ("span", object(vec![("start", location(span.start)), ("end", location(span.end))]))
+  ])
+}
+
+fn location(location: Location) -> Json {
+  object(vec![("line", Json::U64(location.line as u64)), ("column", Json::U64(location.column as u64))])
+}
+
+fn response(id: Json, status: &str) -> BTreeMap<String, Json> {
+  let mut response = BTreeMap::new();
+  response.insert("id".to_string(), id);
+  response.insert("status".to_string(), string(status));
+  response
+}
+
+fn failure(id: Json, message: &str) -> Json {
+  let mut response = response(id, "error");
+  response.insert("message".to_string(), string(message));
+  Json::Object(response)
+}
+
+fn string_member(json: &Json, key: &str) -> Result<String, String> {
+  match json.find(key) {
+    Some(&Json::String(ref value)) => Ok(value.clone()),
+    Some(_) => Err(format!("member '{}' is not a string", key)),
+    None => Err(format!("missing member '{}'", key))
+  }
+}
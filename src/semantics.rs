@@ -0,0 +1,249 @@
+//! Name resolution and arity checking of parsed definitions, done before any IR is emitted:
+//! unknown variables, functions and operators, calls with the wrong number of arguments and
+//! redeclarations with another number of arguments.
+//!
+//! The AST does not know where it came from. When the tokens a node was parsed from are given,
+//! errors are located by walking the tokens along with the node, which meets names and
+//! operators in the same order as they appear in the source.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use builtins;
+use lexer::*;
+use parser::*;
+
+/// Functions and operators known at some point of a session, with their numbers of arguments.
+/// Builtins are always known, but can be shadowed by definitions with any number of arguments.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct SymbolTable {
+  functions: HashMap<String, usize>
+}
+
+impl SymbolTable {
+  pub fn new() -> SymbolTable {
+    SymbolTable { functions: HashMap::new() }
+  }
+
+  pub fn define(&mut self, prototype: &Prototype) {
+    // top-level expressions are anonymous and cannot be called
+    if !prototype.name.is_empty() {
+      self.functions.insert(prototype.name.clone(), prototype.args.len());
+    }
+  }
+
+  /// Defines the function of a definition or extern.
+  pub fn define_node(&mut self, node: &ASTNode) {
+    match node {
+      &ExternNode(ref prototype) => self.define(prototype),
+      &FunctionNode(ref function) => self.define(&function.prototype)
+    }
+  }
+
+  /// Number of arguments of function `name`, None if it is unknown.
+  pub fn arity(&self, name: &str) -> Option<usize> {
+    match self.functions.get(name) {
+      Some(arity) => Some(*arity),
+      None => builtins::find(name).map(|builtin| builtin.args.len())
+    }
+  }
+
+  /// Defined and declared functions, operators included, not sorted.
+  pub fn names(&self) -> Vec<String> {
+    self.functions.keys().cloned().collect()
+  }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct SemanticError {
+  // index of the node with the error
+  pub node: usize,
+  pub message: String,
+  pub span: Option<Span>
+}
+
+impl fmt::Display for SemanticError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.span {
+      Some(span) => write!(f, "{}:{}: {}", span.start.line, span.start.column, self.message),
+      None => write!(f, "{}", self.message)
+    }
+  }
+}
+
+/// Checks `nodes` in order against the functions known before them and the ones they define.
+/// A node with errors defines nothing. `symbols` itself is not changed.
+pub fn check(nodes: &[ASTNode], symbols: &SymbolTable) -> Vec<SemanticError> {
+  check_nodes(nodes.iter().map(|node| (node, None)).collect(), symbols)
+}
+
+/// Like `check`, with errors located in the source: `nodes` come with the ranges of `tokens`
+/// they were parsed from, as given by `parse_with_positions`, and `spans` are those of the tokens.
+pub fn check_located(nodes: &[(ASTNode, (usize, usize))], tokens: &[Token], spans: &[Span],
+                     symbols: &SymbolTable) -> Vec<SemanticError> {
+  check_nodes(nodes.iter().map(|&(ref node, range)| (node, Some(Locator::new(tokens, spans, range)))).collect(),
+              symbols)
+}
+
+fn check_nodes(nodes: Vec<(&ASTNode, Option<Locator>)>, symbols: &SymbolTable) -> Vec<SemanticError> {
+  let mut symbols = symbols.clone();
+  let mut errors = Vec::new();
+  for (i, (node, locator)) in nodes.into_iter().enumerate() {
+    let (prototype, node_errors) = {
+      let mut checker = Checker { symbols: &symbols, variables: Vec::new(), locator: locator, errors: Vec::new() };
+      let prototype = match node {
+        &ExternNode(ref prototype) => {
+          checker.prototype(prototype);
+          prototype
+        },
+        &FunctionNode(ref function) => {
+          checker.prototype(&function.prototype);
+          checker.variables = function.prototype.args.clone();
+          checker.expression(&function.expression, &function.prototype);
+          &function.prototype
+        }
+      };
+      (prototype, checker.errors)
+    };
+
+    if node_errors.is_empty() {
+      symbols.define(prototype);
+    }
+    errors.extend(node_errors.into_iter().map(|(message, span)| SemanticError { node: i, message: message, span: span }));
+  }
+  errors
+}
+
+/// Finds the tokens of the names, keywords and operators of a node as the node is walked in
+/// the order of the source: every token is looked for after the last one found.
+pub struct Locator<'a> {
+  tokens: &'a [Token],
+  spans: &'a [Span],
+  // next token to look at, and the end of the node
+  next: usize,
+  end: usize
+}
+
+impl<'a> Locator<'a> {
+  /// `range` is the range of `tokens` the node was parsed from, `spans` are those of the tokens.
+  pub fn new(tokens: &'a [Token], spans: &'a [Span], range: (usize, usize)) -> Locator<'a> {
+    Locator { tokens: tokens, spans: spans, next: range.0, end: range.1 }
+  }
+
+  pub fn find(&mut self, wanted: &Token) -> Option<Span> {
+    match (self.next..self.end).find(|&i| self.tokens[i] == *wanted) {
+      Some(i) => {
+        self.next = i + 1;
+        Some(self.spans[i])
+      },
+      None => None
+    }
+  }
+}
+
+struct Checker<'a> {
+  symbols: &'a SymbolTable,
+  // variables in scope, innermost last
+  variables: Vec<String>,
+  locator: Option<Locator<'a>>,
+  errors: Vec<(String, Option<Span>)>
+}
+
+impl<'a> Checker<'a> {
+  fn locate(&mut self, token: Token) -> Option<Span> {
+    match self.locator {
+      Some(ref mut locator) => locator.find(&token),
+      None => None
+    }
+  }
+
+  fn prototype(&mut self, prototype: &Prototype) {
+    let span = match prototype.ftype {
+      Normal => self.locate(Ident(prototype.name.clone())),
+      UnaryOp(ref op) | BinaryOp(ref op, _) => self.locate(Operator(op.clone()))
+    };
+    for arg in prototype.args.iter() {
+      self.locate(Ident(arg.clone()));
+    }
+
+    if let Some(arity) = self.symbols.functions.get(&prototype.name) {
+      if *arity != prototype.args.len() {
+        self.errors.push((format!("{} was declared with {} arguments, not {}",
+                                  prototype.name, arity, prototype.args.len()), span));
+      }
+    }
+  }
+
+  // `function` is the prototype of the function being checked, which can call itself.
+  fn expression(&mut self, expr: &Expression, function: &Prototype) {
+    match expr {
+      &LiteralExpr(_) => (),
+
+      &VariableExpr(ref name) => {
+        let span = self.locate(Ident(name.clone()));
+        if !self.variables.contains(name) {
+          self.errors.push((format!("unknown variable {}", name), span));
+        }
+      },
+
+      &BinaryExpr(ref op, ref lhs, ref rhs) => {
+        self.expression(lhs, function);
+        let span = self.locate(Operator(op.clone()));
+        match op.as_str() {
+          "+" | "-" | "*" | "<" => (),
+          _ => if self.arity(&("binary".to_string() + op), function).is_none() {
+            self.errors.push((format!("unknown binary operator {}", op), span));
+          }
+        }
+        self.expression(rhs, function);
+      },
+
+      &UnaryExpr(ref op, ref operand) => {
+        let span = self.locate(Operator(op.clone()));
+        if self.arity(&("unary".to_string() + op), function).is_none() {
+          self.errors.push((format!("unknown unary operator {}", op), span));
+        }
+        self.expression(operand, function);
+      },
+
+      &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+        self.expression(cond_expr, function);
+        self.expression(then_expr, function);
+        self.expression(else_expr, function);
+      },
+
+      &LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+        self.locate(Ident(var_name.clone()));
+        // the loop variable is not in scope in its start value
+        self.expression(start_expr, function);
+        self.variables.push(var_name.clone());
+        self.expression(end_expr, function);
+        self.expression(step_expr, function);
+        self.expression(body_expr, function);
+        self.variables.pop();
+      },
+
+      &CallExpr(ref name, ref args) => {
+        let span = self.locate(Ident(name.clone()));
+        match self.arity(name, function) {
+          None => self.errors.push((format!("unknown function {}", name), span)),
+          Some(arity) if arity != args.len() => {
+            self.errors.push((format!("{} takes {} arguments, not {}", name, arity, args.len()), span));
+          },
+          _ => ()
+        }
+        for arg in args.iter() {
+          self.expression(arg, function);
+        }
+      }
+    }
+  }
+
+  fn arity(&self, name: &str, function: &Prototype) -> Option<usize> {
+    if name == function.name {
+      Some(function.args.len())
+    } else {
+      self.symbols.arity(name)
+    }
+  }
+}
@@ -0,0 +1,499 @@
+use std::fmt;
+use std::i32;
+
+use rustc_serialize::json::Json;
+
+use json_util::{string, tagged};
+use parser::*;
+
+pub use self::Format::{JSON, SExpr};
+
+/// Stable external representations of the AST, for tools that cannot read `Debug` output.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Format {
+  JSON,
+  SExpr
+}
+
+impl Format {
+  pub fn from_name(name: &str) -> Option<Format> {
+    match name {
+      "ast-json" | "json" => Some(JSON),
+      "ast-sexpr" | "sexpr" => Some(SExpr),
+      _ => None
+    }
+  }
+}
+
+pub fn serialize(nodes: &[ASTNode], format: Format) -> String {
+  match format {
+    JSON => to_json(nodes).pretty().to_string() + "\n",
+    SExpr => nodes.iter().map(|node| format!("{}\n", node_to_sexpr(node))).collect()
+  }
+}
+
+pub fn to_json(nodes: &[ASTNode]) -> Json {
+  Json::Array(nodes.iter().map(node_to_json).collect())
+}
+
+pub fn deserialize(input: &str, format: Format) -> Result<Vec<ASTNode>, String> {
+  match format {
+    JSON => {
+      let json = try!(Json::from_str(input).map_err(|e| format!("{}", e)));
+      match json {
+        Json::Array(items) => items.iter().map(node_from_json).collect(),
+        _ => Err("expected an array of nodes".to_string())
+      }
+    },
+    SExpr => {
+      let mut chars = input.chars().peekable();
+      let mut nodes = Vec::new();
+      loop {
+        skip_whitespace(&mut chars);
+        if chars.peek().is_none() {
+          return Ok(nodes)
+        }
+        let sexpr = try!(read_sexpr(&mut chars));
+        nodes.push(try!(node_from_sexpr(&sexpr)));
+      }
+    }
+  }
+}
+
+// JSON
+//
+// Every node is an object with a "kind" member; the other members depend on the kind.
+
+fn node_to_json(node: &ASTNode) -> Json {
+  match node {
+    &ExternNode(ref prototype) => tagged("extern", vec![("prototype", prototype_to_json(prototype))]),
+    &FunctionNode(ref function) => tagged("function", vec![
+      ("prototype", prototype_to_json(&function.prototype)),
+      ("body", expr_to_json(&function.expression))])
+  }
+}
+
+fn prototype_to_json(prototype: &Prototype) -> Json {
+  let ftype = match prototype.ftype {
+    Normal => tagged("normal", vec![]),
+    UnaryOp(ref op) => tagged("unary", vec![("operator", string(op))]),
+    BinaryOp(ref op, precedence) => tagged("binary", vec![
+      ("operator", string(op)),
+      ("precedence", Json::I64(precedence as i64))])
+  };
+  tagged("prototype", vec![
+    ("name", string(&prototype.name)),
+    ("type", ftype),
+    ("args", Json::Array(prototype.args.iter().map(|a| string(a)).collect()))])
+}
+
+fn expr_to_json(expr: &Expression) -> Json {
+  match expr {
+    &LiteralExpr(value) => tagged("literal", vec![("value", Json::F64(value))]),
+    &VariableExpr(ref name) => tagged("variable", vec![("name", string(name))]),
+    &BinaryExpr(ref op, ref lhs, ref rhs) => tagged("binary", vec![
+      ("operator", string(op)),
+      ("lhs", expr_to_json(lhs)),
+      ("rhs", expr_to_json(rhs))]),
+    &UnaryExpr(ref op, ref operand) => tagged("unary", vec![
+      ("operator", string(op)),
+      ("operand", expr_to_json(operand))]),
+    &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => tagged("if", vec![
+      ("cond", expr_to_json(cond_expr)),
+      ("then", expr_to_json(then_expr)),
+      ("else", expr_to_json(else_expr))]),
+    &LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => tagged("for", vec![
+      ("var", string(var_name)),
+      ("start", expr_to_json(start_expr)),
+      ("end", expr_to_json(end_expr)),
+      ("step", expr_to_json(step_expr)),
+      ("body", expr_to_json(body_expr))]),
+    &CallExpr(ref name, ref args) => tagged("call", vec![
+      ("callee", string(name)),
+      ("args", Json::Array(args.iter().map(expr_to_json).collect()))])
+  }
+}
+
+fn member<'a>(json: &'a Json, key: &str) -> Result<&'a Json, String> {
+  json.find(key).ok_or(format!("missing member '{}' in {}", key, json))
+}
+
+fn string_member(json: &Json, key: &str) -> Result<String, String> {
+  let value = try!(member(json, key));
+  value.as_string().map(|s| s.to_string()).ok_or(format!("member '{}' is not a string in {}", key, json))
+}
+
+fn array_member<'a>(json: &'a Json, key: &str) -> Result<&'a Vec<Json>, String> {
+  let value = try!(member(json, key));
+  value.as_array().ok_or(format!("member '{}' is not an array in {}", key, json))
+}
+
+fn boxed_expr_member(json: &Json, key: &str) -> Result<Box<Expression>, String> {
+  Ok(Box::new(try!(expr_from_json(try!(member(json, key))))))
+}
+
+fn node_from_json(json: &Json) -> Result<ASTNode, String> {
+  match try!(string_member(json, "kind")).as_str() {
+    "extern" => Ok(ExternNode(try!(prototype_from_json(try!(member(json, "prototype")))))),
+    "function" => Ok(FunctionNode(Function{
+      prototype: try!(prototype_from_json(try!(member(json, "prototype")))),
+      expression: try!(expr_from_json(try!(member(json, "body"))))
+    })),
+    kind => Err(format!("unknown node kind '{}'", kind))
+  }
+}
+
+fn prototype_from_json(json: &Json) -> Result<Prototype, String> {
+  let ftype_json = try!(member(json, "type"));
+  // operators are named after their symbol, as by the parser
+  let (name, ftype) = match try!(string_member(ftype_json, "kind")).as_str() {
+    "normal" => (try!(string_member(json, "name")), Normal),
+    "unary" => {
+      let op = try!(string_member(ftype_json, "operator"));
+      ("unary".to_string() + &op, UnaryOp(op))
+    },
+    "binary" => {
+      let precedence = try!(member(ftype_json, "precedence"));
+      let precedence = try!(precedence.as_i64().ok_or(format!("precedence is not an integer in {}", ftype_json)));
+      if precedence < 0 || precedence > i32::MAX as i64 {
+        return Err(format!("invalid precedence {} in {}", precedence, ftype_json))
+      }
+      let op = try!(string_member(ftype_json, "operator"));
+      ("binary".to_string() + &op, BinaryOp(op, precedence as i32))
+    },
+    kind => return Err(format!("unknown function type '{}'", kind))
+  };
+  if let Some(given) = json.find("name") {
+    if given.as_string() != Some(&name) {
+      return Err(format!("name {} does not match the function type in {}", given, json))
+    }
+  }
+
+  let mut args = Vec::new();
+  for arg in try!(array_member(json, "args")).iter() {
+    args.push(try!(arg.as_string().ok_or(format!("argument is not a string in {}", json))).to_string());
+  }
+  match ftype {
+    UnaryOp(_) if args.len() != 1 => return Err(format!("unary operator expects one argument in {}", json)),
+    BinaryOp(..) if args.len() != 2 => return Err(format!("binary operator expects two arguments in {}", json)),
+    _ => ()
+  }
+
+  Ok(Prototype{name: name, ftype: ftype, args: args})
+}
+
+fn expr_from_json(json: &Json) -> Result<Expression, String> {
+  match try!(string_member(json, "kind")).as_str() {
+    "literal" => {
+      let value = try!(member(json, "value"));
+      Ok(LiteralExpr(try!(value.as_f64().ok_or(format!("value is not a number in {}", json)))))
+    },
+    "variable" => Ok(VariableExpr(try!(string_member(json, "name")))),
+    "binary" => Ok(BinaryExpr(try!(string_member(json, "operator")),
+                              try!(boxed_expr_member(json, "lhs")),
+                              try!(boxed_expr_member(json, "rhs")))),
+    "unary" => Ok(UnaryExpr(try!(string_member(json, "operator")),
+                            try!(boxed_expr_member(json, "operand")))),
+    "if" => Ok(ConditionalExpr{cond_expr: try!(boxed_expr_member(json, "cond")),
+                               then_expr: try!(boxed_expr_member(json, "then")),
+                               else_expr: try!(boxed_expr_member(json, "else"))}),
+    "for" => Ok(LoopExpr{var_name: try!(string_member(json, "var")),
+                         start_expr: try!(boxed_expr_member(json, "start")),
+                         end_expr: try!(boxed_expr_member(json, "end")),
+                         step_expr: try!(boxed_expr_member(json, "step")),
+                         body_expr: try!(boxed_expr_member(json, "body"))}),
+    "call" => {
+      let mut args = Vec::new();
+      for arg in try!(array_member(json, "args")).iter() {
+        args.push(try!(expr_from_json(arg)));
+      }
+      Ok(CallExpr(try!(string_member(json, "callee")), args))
+    },
+    kind => Err(format!("unknown expression kind '{}'", kind))
+  }
+}
+
+// S-expressions
+//
+//   node       := (extern PROTOTYPE) | (def PROTOTYPE EXPR) | (expr EXPR)
+//   PROTOTYPE  := (function NAME (ARG*)) | (unary "OP" (ARG)) | (binary "OP" PRECEDENCE (ARG ARG))
+//   EXPR       := (lit NUMBER) | (var NAME) | (binary "OP" EXPR EXPR) | (unary "OP" EXPR)
+//               | (if EXPR EXPR EXPR) | (for NAME EXPR EXPR EXPR EXPR) | (call NAME EXPR*)
+//
+// Names are bare symbols, operators are always quoted because they can be any character.
+
+#[derive(PartialEq, Clone, Debug)]
+enum Sexpr {
+  Symbol(String),
+  Str(String),
+  List(Vec<Sexpr>)
+}
+
+impl fmt::Display for Sexpr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      &Sexpr::Symbol(ref name) => write!(f, "{}", name),
+      &Sexpr::Str(ref value) => write!(f, "\"{}\"", value.replace("\\", "\\\\").replace("\"", "\\\"")),
+      &Sexpr::List(ref items) => {
+        try!(write!(f, "("));
+        for (i, item) in items.iter().enumerate() {
+          if i > 0 {
+            try!(write!(f, " "));
+          }
+          try!(write!(f, "{}", item));
+        }
+        write!(f, ")")
+      }
+    }
+  }
+}
+
+fn symbol(name: &str) -> Sexpr {
+  Sexpr::Symbol(name.to_string())
+}
+
+fn list(head: &str, mut items: Vec<Sexpr>) -> Sexpr {
+  items.insert(0, symbol(head));
+  Sexpr::List(items)
+}
+
+fn node_to_sexpr(node: &ASTNode) -> Sexpr {
+  match node {
+    &ExternNode(ref prototype) => list("extern", vec![prototype_to_sexpr(prototype)]),
+    &FunctionNode(ref function) if function.prototype.name == "" => {
+      list("expr", vec![expr_to_sexpr(&function.expression)])
+    },
+    &FunctionNode(ref function) => {
+      list("def", vec![prototype_to_sexpr(&function.prototype), expr_to_sexpr(&function.expression)])
+    }
+  }
+}
+
+fn prototype_to_sexpr(prototype: &Prototype) -> Sexpr {
+  let args = Sexpr::List(prototype.args.iter().map(|a| symbol(a)).collect());
+  match prototype.ftype {
+    Normal => list("function", vec![symbol(&prototype.name), args]),
+    UnaryOp(ref op) => list("unary", vec![Sexpr::Str(op.clone()), args]),
+    BinaryOp(ref op, precedence) => list("binary", vec![Sexpr::Str(op.clone()), symbol(&precedence.to_string()), args])
+  }
+}
+
+fn expr_to_sexpr(expr: &Expression) -> Sexpr {
+  match expr {
+    &LiteralExpr(value) => list("lit", vec![symbol(&value.to_string())]),
+    &VariableExpr(ref name) => list("var", vec![symbol(name)]),
+    &BinaryExpr(ref op, ref lhs, ref rhs) => {
+      list("binary", vec![Sexpr::Str(op.clone()), expr_to_sexpr(lhs), expr_to_sexpr(rhs)])
+    },
+    &UnaryExpr(ref op, ref operand) => list("unary", vec![Sexpr::Str(op.clone()), expr_to_sexpr(operand)]),
+    &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+      list("if", vec![expr_to_sexpr(cond_expr), expr_to_sexpr(then_expr), expr_to_sexpr(else_expr)])
+    },
+    &LoopExpr{ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+      list("for", vec![symbol(var_name), expr_to_sexpr(start_expr), expr_to_sexpr(end_expr),
+                       expr_to_sexpr(step_expr), expr_to_sexpr(body_expr)])
+    },
+    &CallExpr(ref name, ref args) => {
+      let mut items = vec![symbol(name)];
+      items.extend(args.iter().map(expr_to_sexpr));
+      list("call", items)
+    }
+  }
+}
+
+type Chars<'a> = ::std::iter::Peekable<::std::str::Chars<'a>>;
+
+fn skip_whitespace(chars: &mut Chars) {
+  while chars.peek().map_or(false, |c| c.is_whitespace()) {
+    chars.next();
+  }
+}
+
+fn read_sexpr(chars: &mut Chars) -> Result<Sexpr, String> {
+  skip_whitespace(chars);
+  match chars.next() {
+    None => Err("unexpected end of input".to_string()),
+    Some(')') => Err("unexpected ')'".to_string()),
+    Some('(') => {
+      let mut items = Vec::new();
+      loop {
+        skip_whitespace(chars);
+        match chars.peek().map(|c| *c) {
+          None => return Err("unclosed '('".to_string()),
+          Some(')') => {
+            chars.next();
+            return Ok(Sexpr::List(items))
+          },
+          _ => items.push(try!(read_sexpr(chars)))
+        }
+      }
+    },
+    Some('"') => {
+      let mut value = String::new();
+      loop {
+        match chars.next() {
+          None => return Err("unterminated string".to_string()),
+          Some('"') => return Ok(Sexpr::Str(value)),
+          Some('\\') => match chars.next() {
+            Some(c) => value.push(c),
+            None => return Err("unterminated string".to_string())
+          },
+          Some(c) => value.push(c)
+        }
+      }
+    },
+    Some(c) => {
+      let mut name = c.to_string();
+      while chars.peek().map_or(false, |c| !c.is_whitespace() && *c != '(' && *c != ')') {
+        name.push(chars.next().unwrap());
+      }
+      Ok(Sexpr::Symbol(name))
+    }
+  }
+}
+
+fn expect_form<'a>(sexpr: &'a Sexpr) -> Result<(&'a str, &'a [Sexpr]), String> {
+  match sexpr {
+    &Sexpr::List(ref items) => match items.first() {
+      Some(&Sexpr::Symbol(ref head)) => Ok((head.as_str(), &items[1..])),
+      _ => Err(format!("expected a form starting with a symbol, found {}", sexpr))
+    },
+    _ => Err(format!("expected a list, found {}", sexpr))
+  }
+}
+
+fn expect_arity(form: &Sexpr, args: &[Sexpr], arity: usize) -> Result<(), String> {
+  if args.len() == arity {
+    Ok(())
+  } else {
+    Err(format!("expected {} elements after the head of {}", arity, form))
+  }
+}
+
+fn expect_symbol(sexpr: &Sexpr) -> Result<String, String> {
+  match sexpr {
+    &Sexpr::Symbol(ref name) => Ok(name.clone()),
+    _ => Err(format!("expected a symbol, found {}", sexpr))
+  }
+}
+
+fn expect_string(sexpr: &Sexpr) -> Result<String, String> {
+  match sexpr {
+    &Sexpr::Str(ref value) => Ok(value.clone()),
+    _ => Err(format!("expected a string, found {}", sexpr))
+  }
+}
+
+fn expect_symbols(sexpr: &Sexpr) -> Result<Vec<String>, String> {
+  match sexpr {
+    &Sexpr::List(ref items) => items.iter().map(expect_symbol).collect(),
+    _ => Err(format!("expected a list of symbols, found {}", sexpr))
+  }
+}
+
+fn node_from_sexpr(sexpr: &Sexpr) -> Result<ASTNode, String> {
+  let (head, args) = try!(expect_form(sexpr));
+  match head {
+    "extern" => {
+      try!(expect_arity(sexpr, args, 1));
+      Ok(ExternNode(try!(prototype_from_sexpr(&args[0]))))
+    },
+    "def" => {
+      try!(expect_arity(sexpr, args, 2));
+      Ok(FunctionNode(Function{prototype: try!(prototype_from_sexpr(&args[0])),
+                               expression: try!(expr_from_sexpr(&args[1]))}))
+    },
+    "expr" => {
+      try!(expect_arity(sexpr, args, 1));
+      let prototype = Prototype{name: "".to_string(), ftype: Normal, args: vec![]};
+      Ok(FunctionNode(Function{prototype: prototype, expression: try!(expr_from_sexpr(&args[0]))}))
+    },
+    _ => Err(format!("unknown node {}", sexpr))
+  }
+}
+
+fn prototype_from_sexpr(sexpr: &Sexpr) -> Result<Prototype, String> {
+  let (head, args) = try!(expect_form(sexpr));
+  match head {
+    "function" => {
+      try!(expect_arity(sexpr, args, 2));
+      Ok(Prototype{name: try!(expect_symbol(&args[0])), ftype: Normal, args: try!(expect_symbols(&args[1]))})
+    },
+    "unary" => {
+      try!(expect_arity(sexpr, args, 2));
+      let op = try!(expect_string(&args[0]));
+      let operands = try!(expect_symbols(&args[1]));
+      if operands.len() != 1 {
+        return Err(format!("unary operator expects one argument in {}", sexpr))
+      }
+      Ok(Prototype{name: "unary".to_string() + &op, ftype: UnaryOp(op), args: operands})
+    },
+    "binary" => {
+      try!(expect_arity(sexpr, args, 3));
+      let op = try!(expect_string(&args[0]));
+      let precedence = try!(try!(expect_symbol(&args[1])).parse::<i32>().ok().and_then(|p| if p < 0 { None } else { Some(p) })
+                            .ok_or(format!("invalid precedence in {}", sexpr)));
+      let operands = try!(expect_symbols(&args[2]));
+      if operands.len() != 2 {
+        return Err(format!("binary operator expects two arguments in {}", sexpr))
+      }
+      Ok(Prototype{name: "binary".to_string() + &op, ftype: BinaryOp(op, precedence), args: operands})
+    },
+    _ => Err(format!("unknown prototype {}", sexpr))
+  }
+}
+
+fn boxed_expr_from_sexpr(sexpr: &Sexpr) -> Result<Box<Expression>, String> {
+  Ok(Box::new(try!(expr_from_sexpr(sexpr))))
+}
+
+fn expr_from_sexpr(sexpr: &Sexpr) -> Result<Expression, String> {
+  let (head, args) = try!(expect_form(sexpr));
+  match head {
+    "lit" => {
+      try!(expect_arity(sexpr, args, 1));
+      let value = try!(try!(expect_symbol(&args[0])).parse::<f64>()
+                       .map_err(|_| format!("invalid number in {}", sexpr)));
+      Ok(LiteralExpr(value))
+    },
+    "var" => {
+      try!(expect_arity(sexpr, args, 1));
+      Ok(VariableExpr(try!(expect_symbol(&args[0]))))
+    },
+    "binary" => {
+      try!(expect_arity(sexpr, args, 3));
+      Ok(BinaryExpr(try!(expect_string(&args[0])),
+                    try!(boxed_expr_from_sexpr(&args[1])),
+                    try!(boxed_expr_from_sexpr(&args[2]))))
+    },
+    "unary" => {
+      try!(expect_arity(sexpr, args, 2));
+      Ok(UnaryExpr(try!(expect_string(&args[0])), try!(boxed_expr_from_sexpr(&args[1]))))
+    },
+    "if" => {
+      try!(expect_arity(sexpr, args, 3));
+      Ok(ConditionalExpr{cond_expr: try!(boxed_expr_from_sexpr(&args[0])),
+                         then_expr: try!(boxed_expr_from_sexpr(&args[1])),
+                         else_expr: try!(boxed_expr_from_sexpr(&args[2]))})
+    },
+    "for" => {
+      try!(expect_arity(sexpr, args, 5));
+      Ok(LoopExpr{var_name: try!(expect_symbol(&args[0])),
+                  start_expr: try!(boxed_expr_from_sexpr(&args[1])),
+                  end_expr: try!(boxed_expr_from_sexpr(&args[2])),
+                  step_expr: try!(boxed_expr_from_sexpr(&args[3])),
+                  body_expr: try!(boxed_expr_from_sexpr(&args[4]))})
+    },
+    "call" => {
+      if args.is_empty() {
+        return Err(format!("missing callee in {}", sexpr))
+      }
+      let mut call_args = Vec::new();
+      for arg in args[1..].iter() {
+        call_args.push(try!(expr_from_sexpr(arg)));
+      }
+      Ok(CallExpr(try!(expect_symbol(&args[0])), call_args))
+    },
+    _ => Err(format!("unknown expression {}", sexpr))
+  }
+}
@@ -0,0 +1,56 @@
+//! Finds self-recursive calls that are not in tail position. Only tail calls are turned into
+//! loops by the `tailcallelim` pass, every other recursive call still takes stack space.
+
+use parser::*;
+
+/// Describes every call of `function` to itself that is not in tail position.
+pub fn non_tail_recursive_calls(function: &Function) -> Vec<String> {
+  let mut calls = Vec::new();
+  let name = function.prototype.name.as_str();
+  if name != "" {
+    collect(&function.expression, name, true, &mut calls);
+  }
+
+  calls.into_iter()
+    .map(|call| format!("recursive call `{}` in {} is not in tail position", call, function.prototype))
+    .collect()
+}
+
+// The body of a function is in tail position, and so are both branches of a conditional in
+// tail position. Everything else is evaluated before the function returns.
+fn collect(expr: &Expression, name: &str, tail: bool, calls: &mut Vec<String>) {
+  match expr {
+    &LiteralExpr(_) | &VariableExpr(_) => (),
+    &BinaryExpr(ref op, ref lhs, ref rhs) => {
+      collect(lhs, name, false, calls);
+      collect(rhs, name, false, calls);
+      if !tail && "binary".to_string() + op == name {
+        calls.push(format!("{}", expr));
+      }
+    },
+    &UnaryExpr(ref op, ref operand) => {
+      collect(operand, name, false, calls);
+      if !tail && "unary".to_string() + op == name {
+        calls.push(format!("{}", expr));
+      }
+    },
+    &ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+      collect(cond_expr, name, false, calls);
+      collect(then_expr, name, tail, calls);
+      collect(else_expr, name, tail, calls);
+    },
+    &LoopExpr{ref start_expr, ref end_expr, ref step_expr, ref body_expr, ..} => {
+      for e in vec![start_expr, end_expr, step_expr, body_expr].into_iter() {
+        collect(e, name, false, calls);
+      }
+    },
+    &CallExpr(ref callee, ref args) => {
+      for arg in args.iter() {
+        collect(arg, name, false, calls);
+      }
+      if !tail && callee.as_str() == name {
+        calls.push(format!("{}", expr));
+      }
+    }
+  }
+}
@@ -0,0 +1,69 @@
+//! Wall clock timing of REPL evaluations.
+
+use time::precise_time_ns;
+
+pub struct Timer {
+  start: u64
+}
+
+impl Timer {
+  pub fn start() -> Timer {
+    Timer { start: precise_time_ns() }
+  }
+
+  pub fn elapsed_ms(&self) -> f64 {
+    (precise_time_ns() - self.start) as f64 / 1000000.0
+  }
+}
+
+/// Time spent in each phase of evaluating an input and the size of the code generated for it.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct Stats {
+  pub tokenize_ms: f64,
+  pub parse_ms: f64,
+  pub codegen_ms: f64,
+  // function passes while generating code, module passes before finalization
+  pub optimize_ms: f64,
+  // handing modules to MCJIT, compiling them and resolving their symbols
+  pub finalize_ms: f64,
+  pub execute_ms: f64,
+  // instructions of the functions defined, before and after the function passes
+  pub instructions_before: usize,
+  pub instructions_after: usize,
+  // modules the JIT holds after the input and how many of them were compiled to machine code
+  pub modules: usize,
+  pub compiled_modules: usize
+}
+
+impl Stats {
+  /// Adds up the times and instruction counts; the module counts are taken from
+  /// `other` if it has any.
+  pub fn merge(&mut self, other: &Stats) {
+    self.tokenize_ms += other.tokenize_ms;
+    self.parse_ms += other.parse_ms;
+    self.codegen_ms += other.codegen_ms;
+    self.optimize_ms += other.optimize_ms;
+    self.finalize_ms += other.finalize_ms;
+    self.execute_ms += other.execute_ms;
+    self.instructions_before += other.instructions_before;
+    self.instructions_after += other.instructions_after;
+    if other.modules != 0 {
+      self.modules = other.modules;
+      self.compiled_modules = other.compiled_modules;
+    }
+  }
+
+  pub fn total_ms(&self) -> f64 {
+    self.tokenize_ms + self.parse_ms + self.codegen_ms + self.optimize_ms + self.finalize_ms + self.execute_ms
+  }
+
+  pub fn report(&self) -> String {
+    format!("tokenize {:.3} ms, parse {:.3} ms, codegen {:.3} ms, optimize {:.3} ms, finalize {:.3} ms, \
+             execute {:.3} ms, total {:.3} ms\n\
+             instructions: {} before optimization, {} after\n\
+             modules: {}, compiled: {}\n",
+            self.tokenize_ms, self.parse_ms, self.codegen_ms, self.optimize_ms, self.finalize_ms,
+            self.execute_ms, self.total_ms(), self.instructions_before, self.instructions_after,
+            self.modules, self.compiled_modules)
+  }
+}
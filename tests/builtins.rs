@@ -0,0 +1,17 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::builtins::*;
+
+#[test]
+fn lookup() {
+  assert_eq!(find("pow").map(|builtin| builtin.args.len()), Some(2));
+  assert_eq!(find("sqrt").map(|builtin| builtin.args.len()), Some(1));
+  assert!(find("printd").is_none());
+}
+
+#[test]
+fn listing_shows_signatures() {
+  let listing = listing();
+  assert_eq!(listing.lines().count(), BUILTINS.len());
+  assert!(listing.lines().any(|line| line.starts_with("max(x, y)")));
+}
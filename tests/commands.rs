@@ -0,0 +1,26 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::commands::*;
+use kaleidoscope::parser::default_parser_settings;
+
+#[test]
+fn parses_commands() {
+  assert_eq!(parse_command(":help\n"), Ok(Help));
+  assert_eq!(parse_command(":q"), Ok(Quit));
+  assert_eq!(parse_command(":ast  1 + 2;\n"), Ok(AST("1 + 2;".to_string())));
+  assert_eq!(parse_command(":load lib/math.ks"), Ok(Load("lib/math.ks".to_string())));
+}
+
+#[test]
+fn rejects_bad_commands() {
+  assert!(parse_command(":frobnicate").is_err());
+  assert!(parse_command(":ir").is_err());
+}
+
+#[test]
+fn operators_by_precedence() {
+  let operators = default_parser_settings().operators();
+  assert_eq!(operators[0], ("*".to_string(), 40));
+  assert_eq!(operators[1], ("+".to_string(), 20));
+  assert_eq!(operators[2], ("-".to_string(), 20));
+}
@@ -0,0 +1,51 @@
+extern crate kaleidoscope;
+extern crate iron_llvm;
+
+use kaleidoscope::builder::IRBuilder;
+use kaleidoscope::context::Context;
+use kaleidoscope::debug_info::DebugInfo;
+use kaleidoscope::lexer::*;
+use kaleidoscope::module::{ModuleProvider, SimpleModuleProvider};
+use kaleidoscope::parser::*;
+use kaleidoscope::passes::PassOptions;
+
+use iron_llvm::LLVMRef;
+
+// IR of the module `source` is compiled into, at -O0, with or without debug info.
+fn compile(source: &str, debug_info: bool) -> String {
+  let (tokens, spans): (Vec<_>, Vec<_>) = tokenize_with_spans(source).into_iter().unzip();
+  let (nodes, _, errors) = parse_with_positions(tokens.as_slice(), &mut default_parser_settings());
+  assert!(errors.is_empty());
+
+  let mut context = Context::new();
+  if debug_info {
+    let mut debug_info = DebugInfo::new("test.ks", context.context.to_ref(), false);
+    let located = nodes.iter().map(|&(ref node, range)| (node, range)).collect::<Vec<_>>();
+    debug_info.locate(located.as_slice(), tokens.as_slice(), spans.as_slice());
+    context.debug_info = Some(debug_info);
+  }
+  let mut module_provider = SimpleModuleProvider::new("main", &PassOptions::with_opt_level(0).unwrap());
+  for &(ref node, _) in nodes.iter() {
+    node.codegen(&mut context, &mut module_provider).unwrap();
+  }
+  module_provider.print_to_string()
+}
+
+#[test]
+fn describes_functions_and_locations() {
+  let ir = compile("def f(x)\n  x * 2;\n\nf(1) + 1;\n", true);
+  assert!(ir.contains("!llvm.dbg.cu"));
+  assert!(ir.contains("!\"Debug Info Version\""));
+  // the subprograms, with the lines of the functions
+  assert!(ir.contains("!\"0x2e\\00f\\00f\\00\\001\\00"));
+  assert!(ir.contains("!\"0x2e\\00__anon_expr\\00__anon_expr\\00\\004\\00"));
+  assert!(ir.contains("!\"test.ks\""));
+  assert!(ir.lines().filter(|line| line.contains("fmul") || line.contains("call")).all(|line| line.contains("!dbg")));
+}
+
+#[test]
+fn is_only_emitted_with_g() {
+  let ir = compile("def f(x)\n  x * 2;\n", false);
+  assert!(!ir.contains("!dbg"));
+  assert!(!ir.contains("llvm.dbg.cu"));
+}
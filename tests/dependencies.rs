@@ -0,0 +1,31 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::dependencies::*;
+use kaleidoscope::lexer::tokenize;
+use kaleidoscope::parser::*;
+
+fn names(source: &str) -> Vec<String> {
+  // `%` has to be known before the definition using it is parsed
+  let mut settings = default_parser_settings();
+  parse(tokenize("def binary% 50 (a b) a - b;").as_slice(), &[], &mut settings);
+  let (ast, _, errors) = parse(tokenize(source).as_slice(), &[], &mut settings);
+  assert!(errors.is_empty());
+  dependency_order(ast.as_slice()).iter().map(|node| node_name(node).to_string()).collect()
+}
+
+#[test]
+fn callees_come_first() {
+  // as left by redefining `half` and `binary%` after their callers
+  assert_eq!(names("def quarter(x) half(half(x));
+                    def mod(a b) a % b;
+                    extern printd(x);
+                    def half(x) printd(x) * 0.5;
+                    def binary% 50 (a b) a - b;"),
+             vec!["printd", "half", "quarter", "binary%", "mod"]);
+}
+
+#[test]
+fn keeps_independent_order_and_recursion() {
+  assert_eq!(names("def b(x) x; def fib(n) if n < 2 then n else fib(n - 1) + fib(n - 2); def a(x) x;"),
+             vec!["b", "fib", "a"]);
+}
@@ -0,0 +1,31 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::editor::completions;
+
+fn complete(words: &[&str], line: &str, cursor: usize) -> (usize, Vec<String>) {
+  let words = words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+  let chars = line.chars().collect::<Vec<_>>();
+  completions(&words, &chars, cursor)
+}
+
+#[test]
+fn completes_the_word_before_the_cursor() {
+  let words = ["fib", "def", "for", "foo", "fib"];
+  assert_eq!(complete(&words, "1 + f", 5), (4, vec!["fib".to_string(), "foo".to_string(), "for".to_string()]));
+  assert_eq!(complete(&words, "fo(1)", 2), (0, vec!["foo".to_string(), "for".to_string()]));
+  assert_eq!(complete(&words, "x + de", 6), (4, vec!["def".to_string()]));
+  assert_eq!(complete(&words, "x + y", 5), (4, vec![]));
+}
+
+#[test]
+fn completes_commands_and_empty_words() {
+  let words = [":ast", ":ir", "x"];
+  assert_eq!(complete(&words, ":a", 2), (0, vec![":ast".to_string()]));
+  assert_eq!(complete(&words, "1 + ", 4), (4, vec![":ast".to_string(), ":ir".to_string(), "x".to_string()]));
+}
+
+#[test]
+fn counts_characters_rather_than_bytes() {
+  let words = ["größe", "grün"];
+  assert_eq!(complete(&words, "1 + grö", 7), (4, vec!["größe".to_string()]));
+}
@@ -0,0 +1,80 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::formatter::format_source;
+
+const UNFORMATTED: &'static str = "# Operators
+def binary| 5 (LHS RHS) if LHS then 1 else if RHS then 1 else 0;   # or
+
+
+def fib(x) if x < 3 then 1 else fib(x-1)+fib(x-2);
+extern printd(x);
+def count(n)
+    for i = 1, i < n in   # loop
+    printd(i);
+def long(first) averyveryverylongfunctionname(first + 1000000, first * 2000000, first - 3000000, other(first));
+";
+
+const FORMATTED: &'static str = "# Operators
+def binary| 5 (LHS, RHS)
+  if LHS then
+    1
+  else if RHS then
+    1
+  else
+    0; # or
+
+def fib(x)
+  if x < 3 then
+    1
+  else
+    fib(x - 1) + fib(x - 2);
+extern printd(x);
+# loop
+def count(n)
+  for i = 1, i < n in
+    printd(i);
+def long(first)
+  averyveryverylongfunctionname(
+    first + 1000000,
+    first * 2000000,
+    first - 3000000,
+    other(first)
+  );
+";
+
+#[test]
+fn formats_layout_and_keeps_comments() {
+  assert_eq!(format_source(UNFORMATTED).unwrap(), FORMATTED);
+}
+
+#[test]
+fn formatting_is_idempotent() {
+  assert_eq!(format_source(FORMATTED).unwrap(), FORMATTED);
+}
+
+#[test]
+fn reports_syntax_errors_with_lines() {
+  assert_eq!(format_source("def ok(x) x;\ndef bad(x) x + ;\n"),
+             Err(vec!["line 2: unknown token when expecting an expression".to_string()]));
+}
+
+#[test]
+fn breaks_long_calls_in_operands() {
+  assert_eq!(format_source("def g(x) 1 + averyveryverylongfunctionname(x + 1000000, x * 2000000, x - 3000000, other(x));\n").unwrap(),
+             "def g(x)\n  1 + averyveryverylongfunctionname(\n    x + 1000000,\n    x * 2000000,\n    x - 3000000,\n    other(x)\n  );\n");
+}
+
+#[test]
+fn fills_lines_up_to_the_width() {
+  // 80 columns
+  let fits = "def w(x) f(x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, abcd);\n";
+  assert_eq!(format_source(fits).unwrap(), fits);
+  let longer = "def w(x) f(x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, abcde);\n";
+  assert_eq!(format_source(longer).unwrap(), "def w(x)\n  f(x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, x + 1, abcde);\n");
+}
+
+#[test]
+fn keeps_comments_with_the_last_item_on_their_line() {
+  assert_eq!(format_source("def a(x) x; def b(x) x; # b\n").unwrap(), "def a(x) x;\ndef b(x) x; # b\n");
+  assert_eq!(format_source("def a(x) x; def b(x) # b\n  x;\n").unwrap(), "def a(x) x;\n# b\ndef b(x) x;\n");
+}
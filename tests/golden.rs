@@ -0,0 +1,154 @@
+// Golden-file tests for the non-executing driver stages.
+//
+// Every `tests/cases/<name>.ks` is run through `driver::run_stage` and compared against
+// `<name>.tokens`, `<name>.ast` and `<name>.ir` next to it. A case that should check other
+// stages names them on a line like `# stages: tokens, ast`. The `jit` stage runs the case in a
+// JIT session at -O2 with cross-module inlining, where every definition goes into a module of
+// its own, and compares the IR the session keeps for its definitions against `<name>.jit.ir`.
+// A missing expected file is a failure. Run with `KALEIDOSCOPE_BLESS=1` to (re)write the
+// expected files from the current output, then review the diff before committing it.
+
+extern crate kaleidoscope;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use kaleidoscope::driver;
+use kaleidoscope::driver::{Exec, Options, Session, Stage};
+use kaleidoscope::parser::FunctionNode;
+use kaleidoscope::passes::PassOptions;
+
+const BLESS_VAR: &'static str = "KALEIDOSCOPE_BLESS";
+const STAGES_PREFIX: &'static str = "# stages:";
+const DEFAULT_STAGES: [&'static str; 3] = ["tokens", "ast", "ir"];
+
+fn cases_dir() -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("cases")
+}
+
+fn read_file(path: &Path) -> Option<String> {
+  let mut content = String::new();
+  match File::open(path) {
+    Ok(mut file) => {
+      file.read_to_string(&mut content).unwrap();
+      Some(content)
+    },
+    Err(_) => None
+  }
+}
+
+fn write_file(path: &Path, content: &str) {
+  let mut file = File::create(path).unwrap();
+  file.write_all(content.as_bytes()).unwrap();
+}
+
+// Describes the first line where both outputs disagree, which is usually enough to see
+// what went wrong; the full picture is in the diff after blessing.
+fn describe_mismatch(expected: &str, actual: &str) -> String {
+  let mut expected_lines = expected.lines();
+  let mut actual_lines = actual.lines();
+  let mut line = 1;
+  loop {
+    match (expected_lines.next(), actual_lines.next()) {
+      (Some(e), Some(a)) if e == a => line += 1,
+      (e, a) => return format!("line {}:\n  expected: {}\n  actual:   {}",
+                               line, e.unwrap_or("<end of output>"), a.unwrap_or("<end of output>"))
+    }
+  }
+}
+
+// The optimized IR of the definitions in `source`, one after the other.
+fn jit_ir(source: &str) -> String {
+  let options = Options {
+    passes: PassOptions::with_opt_level(2).unwrap(),
+    inline_across_modules: true,
+    ..Options::default()
+  };
+  let mut session = Session::new(Exec, &options);
+  let tokens = session.tokenize(source);
+  let (ast, _, _) = session.parse(tokens.as_slice());
+  session.evaluate(ast.as_slice(), Some(source));
+
+  ast.iter()
+    .filter_map(|node| match node {
+      &FunctionNode(ref function) => session.function_ir(&function.prototype.name),
+      _ => None
+    })
+    .map(|ir| format!("{}\n", ir.trim()))
+    .collect()
+}
+
+// The stages a case checks.
+fn stages(source: &str) -> Vec<String> {
+  match source.lines().find(|line| line.starts_with(STAGES_PREFIX)) {
+    Some(line) => line[STAGES_PREFIX.len()..].split(',').map(|stage| stage.trim().to_string()).collect(),
+    None => DEFAULT_STAGES.iter().map(|stage| stage.to_string()).collect()
+  }
+}
+
+fn run(stage: &str, source: &str) -> Result<(String, &'static str), String> {
+  let (stage, extension): (Stage, &'static str) = match stage {
+    "tokens" => (driver::Tokens, "tokens"),
+    "ast" => (driver::AST, "ast"),
+    "ir" => (driver::IR, "ir"),
+    "jit" => return Ok((jit_ir(source), "jit.ir")),
+    _ => return Err(format!("unknown stage '{}'", stage))
+  };
+  driver::run_stage(stage, source).map(|output| (output, extension))
+}
+
+fn check_stage(case: &Path, source: &str, stage: &str, bless: bool, failures: &mut Vec<String>) {
+  let (actual, extension) = match run(stage, source) {
+    Ok(output) => output,
+    Err(message) => {
+      failures.push(format!("{}: {}", case.display(), message));
+      return
+    }
+  };
+  let expected_path = case.with_extension(extension);
+
+  if bless {
+    write_file(&expected_path, &actual);
+    return
+  }
+
+  match read_file(&expected_path) {
+    Some(ref expected) if *expected == actual => (),
+    Some(expected) => failures.push(format!("{}: {}",
+                                            expected_path.display(),
+                                            describe_mismatch(&expected, &actual))),
+    None => failures.push(format!("{}: missing", expected_path.display()))
+  }
+}
+
+#[test]
+fn golden_cases() {
+  let bless = env::var(BLESS_VAR).is_ok();
+
+  let mut cases = fs::read_dir(&cases_dir()).unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("ks"))
+    .collect::<Vec<_>>();
+  cases.sort();
+  assert!(!cases.is_empty(), "no golden cases found in {}", cases_dir().display());
+
+  let mut failures = Vec::new();
+  for case in cases.iter() {
+    let source = read_file(case).unwrap();
+    for stage in stages(&source).iter() {
+      check_stage(case, &source, stage, bless, &mut failures);
+    }
+  }
+
+  if !failures.is_empty() {
+    panic!("{} golden file(s) out of date (rerun with {}=1 to update):\n{}",
+           failures.len(), BLESS_VAR, failures.join("\n"));
+  }
+}
+
+#[test]
+fn run_stage_rejects_exec() {
+  assert!(driver::run_stage(Exec, "1;").is_err());
+}
@@ -0,0 +1,67 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::guard::{run_guarded, Limits};
+
+extern "C" fn answer() -> f64 {
+  42.0
+}
+
+#[test]
+fn returns_value() {
+  assert_eq!(run_guarded(answer, &Limits::default()), Ok(42.0));
+}
+
+// faults and interrupts are only caught where guard.rs handles signals
+#[cfg(all(target_os = "linux", target_arch = "x86_64", target_env = "gnu"))]
+mod signals {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::thread;
+
+  use kaleidoscope::guard::{interrupt_flag, interrupted, run_guarded, Limits};
+
+  use super::answer;
+
+  extern "C" fn poll_forever() -> f64 {
+    // what generated code does at loop back-edges
+    let flag = unsafe { &*(interrupt_flag() as *const AtomicUsize) };
+    loop {
+      if flag.load(Ordering::SeqCst) != 0 {
+        interrupted();
+      }
+    }
+  }
+
+  extern "C" fn null_read() -> f64 {
+    unsafe { *(0 as *const f64) }
+  }
+
+  #[test]
+  fn catches_fault() {
+    assert!(run_guarded(null_read, &Limits::default()).is_err());
+    // the handlers keep working after a fault
+    assert!(run_guarded(null_read, &Limits { stack_size: 1024 * 1024, timeout: 0 }).is_err());
+    assert_eq!(run_guarded(answer, &Limits::default()), Ok(42.0));
+  }
+
+  #[test]
+  fn times_out() {
+    let limits = Limits { stack_size: 0, timeout: 50 };
+    assert_eq!(run_guarded(poll_forever, &limits), Err("timed out".to_string()));
+  }
+
+  #[test]
+  fn runs_from_several_threads() {
+    let threads = (0..4).map(|i| thread::spawn(move || {
+      if i % 2 == 0 {
+        run_guarded(answer, &Limits::default())
+      } else {
+        run_guarded(poll_forever, &Limits { stack_size: 0, timeout: 20 })
+      }
+    })).collect::<Vec<_>>();
+
+    for (i, thread) in threads.into_iter().enumerate() {
+      let expected = if i % 2 == 0 { Ok(42.0) } else { Err("timed out".to_string()) };
+      assert_eq!(thread.join().unwrap(), expected);
+    }
+  }
+}
@@ -0,0 +1,74 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::builder::IRBuilder;
+use kaleidoscope::context::Context;
+use kaleidoscope::driver::{Evaluation, Exec, Options, Returned, Session};
+use kaleidoscope::lexer::tokenize;
+use kaleidoscope::module::{ModuleProvider, SimpleModuleProvider};
+use kaleidoscope::parser::*;
+use kaleidoscope::passes::PassOptions;
+
+fn evaluate(session: &mut Session, source: &str) -> Vec<Evaluation> {
+  let tokens = session.tokenize(source);
+  let (ast, _, errors) = session.parse(tokens.as_slice());
+  assert!(errors.is_empty());
+  session.evaluate(ast.as_slice(), Some(source))
+}
+
+// Compiles `source` without the semantic checks, which would reject failing definitions.
+fn compile(source: &str, context: &mut Context, module_provider: &mut SimpleModuleProvider) -> Result<(), String> {
+  let (ast, _, _) = parse(tokenize(source).as_slice(), &[], &mut default_parser_settings());
+  ast.codegen(context, module_provider).map(|_| ())
+}
+
+#[test]
+fn callers_see_redefinitions() {
+  let mut session = Session::new(Exec, &Options { hot_reload: true, ..Options::default() });
+  evaluate(&mut session, "def f(x) x + 1; def g(x) f(x) * 2;");
+  assert_eq!(evaluate(&mut session, "g(1);"), vec![Returned(4.0)]);
+
+  evaluate(&mut session, "def f(x) x + 2;");
+  assert_eq!(evaluate(&mut session, "g(1); f(1);"), vec![Returned(6.0), Returned(3.0)]);
+}
+
+#[test]
+fn redefines_in_the_same_module() {
+  let mut context = Context::new();
+  context.hot_reload = true;
+  let mut module_provider = SimpleModuleProvider::new("main", &PassOptions::with_opt_level(0).unwrap());
+  compile("def f(x) x + 1; def f(x) x * 2;", &mut context, &mut module_provider).unwrap();
+
+  let ir = module_provider.print_to_string();
+  assert_eq!(ir.matches("define double @f(").count(), 1);
+  assert!(ir.contains("fmul") && !ir.contains("fadd"));
+}
+
+#[test]
+fn failed_redefinition_keeps_the_old_body() {
+  let mut context = Context::new();
+  context.hot_reload = true;
+  let mut module_provider = SimpleModuleProvider::new("main", &PassOptions::with_opt_level(0).unwrap());
+  compile("def f(x) x + 1;", &mut context, &mut module_provider).unwrap();
+  assert!(compile("def f(x) y;", &mut context, &mut module_provider).is_err());
+
+  let ir = module_provider.print_to_string();
+  assert_eq!(ir.matches("define double @").count(), 1);
+  assert!(ir.contains("fadd"));
+  assert!(context.reloadable_functions.contains("f"));
+
+  // a failed first definition is not reloadable
+  assert!(compile("def h(x) y;", &mut context, &mut module_provider).is_err());
+  assert!(!context.reloadable_functions.contains("h"));
+  assert!(!module_provider.print_to_string().contains("@h"));
+}
+
+#[test]
+fn tail_recursion_stays_a_loop() {
+  let mut session = Session::new(Exec, &Options { hot_reload: true, ..Options::default() });
+  let count = "def count(n acc) if n < 1 then acc else count(n - 1, acc + 1);";
+  evaluate(&mut session, count);
+  // redefined, so that the function is reloadable while its body is generated
+  evaluate(&mut session, count);
+  assert!(!session.function_ir("count").unwrap().contains(".slot"));
+  assert_eq!(evaluate(&mut session, "count(10000000, 0);"), vec![Returned(10000000.0)]);
+}
@@ -0,0 +1,56 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::driver::{Evaluation, Exec, Options, Returned, Session};
+use kaleidoscope::guard::{Limits, INTERRUPT_FLAG_SYMBOL};
+
+fn evaluate(session: &mut Session, source: &str) -> Vec<Evaluation> {
+  let tokens = session.tokenize(source);
+  let (ast, _, errors) = session.parse(tokens.as_slice());
+  assert!(errors.is_empty());
+  session.evaluate(ast.as_slice(), Some(source))
+}
+
+#[test]
+fn compiles_definitions_when_first_needed() {
+  let mut session = Session::new(Exec, &Options::default());
+  evaluate(&mut session, "def a(x) x + 1; def b(x) a(x) * 2; def unused(x) x;");
+  // a module for every definition and the current one
+  assert_eq!((session.last_stats().modules, session.last_stats().compiled_modules), (4, 0));
+
+  assert_eq!(evaluate(&mut session, "b(1);"), vec![Returned(4.0)]);
+  // the module of the expression is gone and unused is still not compiled
+  assert_eq!((session.last_stats().modules, session.last_stats().compiled_modules), (4, 2));
+}
+
+#[test]
+fn removes_replaced_definitions() {
+  let mut session = Session::new(Exec, &Options { hot_reload: true, ..Options::default() });
+  evaluate(&mut session, "def f(x) x; def g(x) f(x) + 1;");
+  assert_eq!(evaluate(&mut session, "g(1);"), vec![Returned(2.0)]);
+
+  evaluate(&mut session, "def f(x) x * 10;");
+  assert_eq!(evaluate(&mut session, "g(1);"), vec![Returned(11.0)]);
+  // the modules of g and of the second f, and the current one
+  assert_eq!(session.last_stats().modules, 3);
+}
+
+#[test]
+fn checks_for_interrupts_only_when_interruptible() {
+  let polls = |options: &Options| {
+    let mut session = Session::new(Exec, options);
+    evaluate(&mut session, "def f(x) x;");
+    session.function_ir("f").unwrap().contains(INTERRUPT_FLAG_SYMBOL)
+  };
+  assert!(!polls(&Options::default()));
+  assert!(polls(&Options { interactive: true, ..Options::default() }));
+  assert!(polls(&Options { limits: Limits { stack_size: 0, timeout: 100 }, ..Options::default() }));
+}
+
+#[test]
+fn calls_externs_declared_in_earlier_inputs() {
+  let mut session = Session::new(Exec, &Options::default());
+  evaluate(&mut session, "extern putchard(x);");
+  assert_eq!(evaluate(&mut session, "putchard(65);"), vec![Returned(65.0)]);
+  evaluate(&mut session, "def shout(x) putchard(x) + 1;");
+  assert_eq!(evaluate(&mut session, "shout(66);"), vec![Returned(67.0)]);
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "jupyter")]
+
+extern crate kaleidoscope;
+extern crate rustc_serialize;
+
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::Json;
+
+use kaleidoscope::driver::Options;
+use kaleidoscope::kernel::*;
+
+fn request(msg_type: &str, content: &str) -> Message {
+  let mut header = BTreeMap::new();
+  header.insert("msg_id".to_string(), Json::String("1".to_string()));
+  header.insert("msg_type".to_string(), Json::String(msg_type.to_string()));
+  Message {
+    identities: vec![b"client".to_vec()],
+    header: Json::Object(header),
+    parent_header: Json::Object(BTreeMap::new()),
+    metadata: Json::Object(BTreeMap::new()),
+    content: Json::from_str(content).unwrap()
+  }
+}
+
+#[test]
+fn frames_round_trip() {
+  let signer = Signer::new("secret", "hmac-sha256").unwrap();
+  let message = request("kernel_info_request", "{}");
+  let frames = message.to_frames(&signer);
+  assert_eq!(&frames[0][..], b"client");
+  assert_eq!(&frames[1][..], b"<IDS|MSG>");
+  assert_eq!(frames[2].len(), 64);
+  assert_eq!(Message::from_frames(frames.clone(), &signer), Ok(message));
+
+  let other = Signer::new("other", "hmac-sha256").unwrap();
+  assert!(Message::from_frames(frames, &other).is_err());
+}
+
+#[test]
+fn signs_with_hmac_sha256() {
+  let signer = Signer::new("key", "hmac-sha256").unwrap();
+  assert_eq!(signer.sign(&[&b"The quick brown fox "[..], &b"jumps over the lazy dog"[..]]),
+             "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+  assert_eq!(Signer::new("", "").unwrap().sign(&[&b"{}"[..]]), "");
+}
+
+#[test]
+fn answers_requests() {
+  let mut kernel = Kernel::new(&Options::default());
+
+  let handled = kernel.handle(&request("kernel_info_request", "{}"));
+  let reply = handled.reply.unwrap();
+  assert_eq!(reply.msg_type(), "kernel_info_reply");
+  assert_eq!(reply.identities, vec![b"client".to_vec()]);
+  assert_eq!(reply.content.find_path(&["language_info", "name"]).unwrap().as_string(), Some("kaleidoscope"));
+
+  let handled = kernel.handle(&request("execute_request", r#"{"code": "def square(x) x * x;", "silent": false}"#));
+  assert_eq!(handled.reply.unwrap().content.find("status").unwrap().as_string(), Some("ok"));
+  assert_eq!(handled.published[0].msg_type(), "execute_input");
+
+  let handled = kernel.handle(&request("complete_request", r#"{"code": "squ", "cursor_pos": 3}"#));
+  let content = handled.reply.unwrap().content;
+  assert_eq!(content.find("matches").unwrap(), &Json::Array(vec![Json::String("square".to_string())]));
+
+  let handled = kernel.handle(&request("inspect_request", r#"{"code": "square(2)", "cursor_pos": 2, "detail_level": 0}"#));
+  let content = handled.reply.unwrap().content;
+  assert_eq!(content.find("found").unwrap(), &Json::Boolean(true));
+  assert!(content.find_path(&["data", "text/plain"]).unwrap().as_string().unwrap().starts_with("def square(x)"));
+
+  let handled = kernel.handle(&request("is_complete_request", r#"{"code": "def f(x)"}"#));
+  assert_eq!(handled.reply.unwrap().content.find("status").unwrap().as_string(), Some("incomplete"));
+}
+
+#[test]
+fn renders_ast() {
+  let mut kernel = Kernel::new(&Options::default());
+  let handled = kernel.handle(&request("execute_request", r#"{"code": ":ast 1 + 2;"}"#));
+  let display = handled.published.iter().find(|message| message.msg_type() == "display_data").unwrap();
+  assert!(display.content.find_path(&["data", "application/json"]).unwrap().is_array());
+
+  let handled = kernel.handle(&request("execute_request", r#"{"code": ":ir nothing"}"#));
+  let reply = handled.reply.unwrap();
+  assert_eq!(reply.content.find("status").unwrap().as_string(), Some("error"));
+  assert_eq!(reply.content.find("ename").unwrap().as_string(), Some("CommandError"));
+}
+
+#[test]
+fn shows_warnings() {
+  let mut kernel = Kernel::new(&Options::default());
+  let handled = kernel.handle(&request("execute_request", r#"{"code": "def f(x y) x;"}"#));
+  let stream = handled.published.iter().find(|message| message.msg_type() == "stream").unwrap();
+  assert!(stream.content.find("text").unwrap().as_string().unwrap().starts_with("Warning: "));
+}
@@ -0,0 +1,11 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::lexer::*;
+
+#[test]
+fn token_locations() {
+  let tokens = tokenize_with_locations("# comment\ndef f(x)\n  x + 1;");
+  let locations = tokens.iter().map(|&(_, l)| (l.line, l.column)).collect::<Vec<_>>();
+  assert_eq!(tokens[0].0, Def);
+  assert_eq!(locations, vec![(2, 1), (2, 5), (2, 6), (2, 7), (2, 8), (3, 3), (3, 5), (3, 7), (3, 8)]);
+}
@@ -0,0 +1,42 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::lexer::tokenize;
+use kaleidoscope::lint::*;
+use kaleidoscope::parser::*;
+use kaleidoscope::semantics::SymbolTable;
+
+fn messages(source: &str) -> Vec<String> {
+  let settings = default_parser_settings();
+  let (ast, _, errors) = parse(tokenize(source).as_slice(), &[], &mut settings.clone());
+  assert!(errors.is_empty());
+  lint(ast.as_slice(), &settings).iter().map(|warning| warning.message.clone()).collect()
+}
+
+#[test]
+fn warns_about_functions() {
+  assert_eq!(messages("def f(x y) for x = 1, x < 3 in y; extern g(a);"),
+             vec!["unused parameter x", "loop variable x hides the parameter of the same name"]);
+  // the start value of the loop is the parameter
+  assert_eq!(messages("def f(x) for x = x, x < 3 in 0;"), vec!["loop variable x hides the parameter of the same name"]);
+  assert_eq!(messages("if 1 < 2 then 1 else 0; if 2 * 0 then 1 else 0; def f(x) if x then 1 else 0;"),
+             vec!["condition is always true", "condition is always false"]);
+}
+
+#[test]
+fn warns_about_operators() {
+  assert_eq!(messages("def binary+ 20 (a b) a - b;"), vec!["+ is a built-in operator, this definition is never called"]);
+  assert_eq!(messages("def binary% 40 (a b) a; def binary% 40 (a b) b; def binary% 50 (a b) a * b;"),
+             vec!["% has the same precedence 40 as *", "unused parameter b", "unused parameter a",
+                  "precedence of % changes from 40 to 50"]);
+}
+
+#[test]
+fn locates_findings() {
+  let source = "def f(x) 1;\ng(1);\ndef h(n) if 1 then n else 0;";
+  let findings = lint_source(source, &default_parser_settings(), &SymbolTable::new()).into_iter()
+    .map(|(span, severity, message)| format!("{}:{}: {}: {}", span.start.line, span.start.column, severity, message))
+    .collect::<Vec<_>>();
+  assert_eq!(findings, vec!["1:7: warning: unused parameter x",
+                            "2:1: error: unknown function g",
+                            "3:10: warning: condition is always true"]);
+}
@@ -0,0 +1,104 @@
+extern crate kaleidoscope;
+extern crate rustc_serialize;
+
+use std::io::Cursor;
+
+use rustc_serialize::json::Json;
+
+use kaleidoscope::lsp::*;
+
+const URI: &'static str = "file:///tmp/test.ks";
+
+const SOURCE: &'static str = "def square(x) x * x;\ndef binary% 30 (a b) a - b;\nsquare(3) % 2;\ndef 1(x) x;\n";
+
+fn request(id: u64, method: &str, params: &str) -> String {
+  format!(r#"{{"jsonrpc": "2.0", "id": {}, "method": "{}", "params": {}}}"#, id, method, params)
+}
+
+fn notification(method: &str, params: &str) -> String {
+  format!(r#"{{"jsonrpc": "2.0", "method": "{}", "params": {}}}"#, method, params)
+}
+
+fn at_position(line: u64, character: u64) -> String {
+  format!(r#"{{"textDocument": {{"uri": "{}"}}, "position": {{"line": {}, "character": {}}}}}"#, URI, line, character)
+}
+
+// Plays a client sending `messages` and gives what the server sent back.
+fn run(messages: &[String]) -> Vec<Json> {
+  let mut input = Vec::new();
+  for message in messages.iter() {
+    input.extend(format!("Content-Length: {}\r\n\r\n{}", message.len(), message).into_bytes());
+  }
+  let mut output = Vec::new();
+  serve(&mut Cursor::new(input), &mut output).unwrap();
+
+  let mut output = Cursor::new(output);
+  let mut replies = Vec::new();
+  while let Some(reply) = read_message(&mut output).unwrap() {
+    replies.push(reply);
+  }
+  replies
+}
+
+fn result(replies: &[Json], id: u64) -> &Json {
+  let reply = replies.iter().find(|reply| reply.find("id") == Some(&Json::U64(id))).unwrap();
+  reply.find("result").unwrap()
+}
+
+fn range(json: &Json) -> (u64, u64, u64, u64) {
+  let get = |path: &[&str]| json.find_path(path).unwrap().as_u64().unwrap();
+  (get(&["start", "line"]), get(&["start", "character"]), get(&["end", "line"]), get(&["end", "character"]))
+}
+
+#[test]
+fn scripted_session() {
+  let open = format!(r#"{{"textDocument": {{"uri": "{}", "languageId": "kaleidoscope", "version": 1, "text": {}}}}}"#,
+                     URI, Json::String(SOURCE.to_string()));
+  let document = format!(r#"{{"textDocument": {{"uri": "{}"}}}}"#, URI);
+  let replies = run(&[
+    request(1, "initialize", r#"{"capabilities": {}}"#),
+    notification("initialized", "{}"),
+    notification("textDocument/didOpen", &open),
+    request(2, "textDocument/definition", &at_position(2, 1)),
+    request(3, "textDocument/definition", &at_position(2, 10)),
+    request(4, "textDocument/hover", &at_position(2, 10)),
+    request(5, "textDocument/documentSymbol", &document),
+    request(6, "textDocument/semanticTokens/full", &document),
+    request(7, "shutdown", "null"),
+    notification("exit", "null")
+  ]);
+
+  let capabilities = result(&replies, 1).find("capabilities").unwrap();
+  assert_eq!(capabilities.find("definitionProvider"), Some(&Json::Boolean(true)));
+
+  let diagnostics = replies.iter()
+    .find(|reply| reply.find("method").and_then(|m| m.as_string()) == Some("textDocument/publishDiagnostics"))
+    .and_then(|reply| reply.find_path(&["params", "diagnostics"]))
+    .and_then(|diagnostics| diagnostics.as_array())
+    .unwrap();
+  assert_eq!(diagnostics.len(), 1);
+  assert_eq!(range(diagnostics[0].find("range").unwrap()), (3, 4, 3, 5));
+
+  // the call of square and the use of %
+  assert_eq!(range(result(&replies, 2).find("range").unwrap()), (0, 4, 0, 10));
+  assert_eq!(range(result(&replies, 3).find("range").unwrap()), (1, 10, 1, 11));
+
+  let hover = result(&replies, 4).find_path(&["contents", "value"]).unwrap().as_string().unwrap();
+  assert!(hover.contains("def binary% 30 (a, b)"));
+  assert!(hover.contains("precedence 30"));
+
+  let symbols = result(&replies, 5).as_array().unwrap();
+  let names = symbols.iter().map(|symbol| symbol.find("name").unwrap().as_string().unwrap()).collect::<Vec<_>>();
+  assert_eq!(names, vec!["square", "binary%"]);
+
+  let data = result(&replies, 6).find("data").unwrap().as_array().unwrap();
+  let first = data[..15].iter().map(|n| n.as_u64().unwrap()).collect::<Vec<_>>();
+  // def, square, x
+  assert_eq!(first, vec![0, 0, 3, 0, 0, 0, 4, 6, 1, 0, 0, 7, 1, 2, 0]);
+}
+
+#[test]
+fn rejects_unknown_requests() {
+  let replies = run(&[request(1, "textDocument/frobnicate", "{}")]);
+  assert_eq!(replies[0].find_path(&["error", "code"]), Some(&Json::I64(-32601)));
+}
@@ -0,0 +1,29 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::lexer::tokenize;
+use kaleidoscope::parser::*;
+
+fn function(name: &str, args: &[&str], expression: Expression) -> ASTNode {
+  let prototype = Prototype { name: name.to_string(), args: args.iter().map(|arg| arg.to_string()).collect(), ftype: Normal };
+  FunctionNode(Function { prototype: prototype, expression: expression })
+}
+
+#[test]
+fn resumes_after_the_token_of_an_error() {
+  // the bad extern consumed its `;`, the second `def` starts the next item
+  let (ast, rest, errors) = parse(tokenize("extern foo(x; 1 + 2; def def g(y) y;").as_slice(), &[],
+                                  &mut default_parser_settings());
+  assert_eq!(errors, vec!["expected ')' in prototype", "expected function name in prototype"]);
+  assert_eq!(ast, vec![
+    function("", &[], BinaryExpr("+".to_string(), Box::new(LiteralExpr(1.0)), Box::new(LiteralExpr(2.0)))),
+    function("g", &["y"], VariableExpr("y".to_string()))
+  ]);
+  assert!(rest.is_empty());
+}
+
+#[test]
+fn gives_the_index_of_the_token_of_an_error() {
+  let (_, _, errors) = parse_with_positions(tokenize("def 1(x) x; extern foo(x;").as_slice(), &mut default_parser_settings());
+  assert_eq!(errors, vec![("expected function name in prototype".to_string(), 1),
+                          ("expected ')' in prototype".to_string(), 11)]);
+}
@@ -0,0 +1,41 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::passes::PassOptions;
+
+fn names(passes: &[String]) -> Vec<&str> {
+  passes.iter().map(|pass| pass.as_str()).collect()
+}
+
+#[test]
+fn runs_passes_before_the_first_module_pass_on_functions() {
+  let options = PassOptions::with_pass_list("instcombine, licm,inline,gvn,,globaldce").unwrap();
+  assert_eq!(names(options.function_passes()), vec!["instcombine", "licm"]);
+  assert_eq!(names(options.module_passes()), vec!["inline", "gvn", "globaldce"]);
+
+  let options = PassOptions::with_pass_list("gvn,simplifycfg").unwrap();
+  assert_eq!(names(options.function_passes()), vec!["gvn", "simplifycfg"]);
+  assert!(options.module_passes().is_empty());
+  assert!(PassOptions::with_pass_list("").unwrap().is_empty());
+}
+
+#[test]
+fn opt_levels_are_pass_lists() {
+  assert!(PassOptions::with_opt_level(0).unwrap().is_empty());
+  let o1 = "basicaa,instcombine,reassociate,gvn,simplifycfg,tailcallelim";
+  assert_eq!(PassOptions::with_opt_level(1), PassOptions::with_pass_list(o1));
+
+  let o2 = PassOptions::with_opt_level(2).unwrap();
+  assert_eq!(names(o2.function_passes()), o1.split(',').collect::<Vec<_>>());
+  assert_eq!(names(o2.module_passes()), vec!["inline", "instcombine", "gvn", "simplifycfg", "loop-rotate", "licm", "globaldce"]);
+
+  let o3 = PassOptions::with_opt_level(3).unwrap();
+  assert_eq!(o3.function_passes(), o2.function_passes());
+  assert!(o3.module_passes().iter().any(|pass| pass == "loop-vectorize"));
+  assert_eq!(o3.module_passes().last().map(|pass| pass.as_str()), Some("globaldce"));
+}
+
+#[test]
+fn rejects_unknown_passes_and_levels() {
+  assert_eq!(PassOptions::with_pass_list("gvn,mem2reg"), Err("unknown pass 'mem2reg'".to_string()));
+  assert_eq!(PassOptions::with_opt_level(4), Err("invalid optimization level 4".to_string()));
+}
@@ -0,0 +1,23 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::driver::{run_stage, IR};
+use kaleidoscope::lexer::tokenize;
+use kaleidoscope::parser::*;
+use kaleidoscope::prelude::STANDARD;
+
+#[test]
+fn standard_prelude_compiles() {
+  let (ast, rest, errors) = parse(tokenize(STANDARD).as_slice(), &[], &mut default_parser_settings());
+  assert!(errors.is_empty());
+  assert!(rest.is_empty());
+  assert_eq!(ast.len(), 7);
+  assert!(!run_stage(IR, STANDARD).unwrap().contains("Error"));
+}
+
+#[test]
+fn standard_prelude_defines_operators() {
+  let mut settings = default_parser_settings();
+  parse(tokenize(STANDARD).as_slice(), &[], &mut settings);
+  assert_eq!(settings.precedence("|"), Some(5));
+  assert_eq!(settings.precedence(":"), Some(1));
+}
@@ -0,0 +1,115 @@
+// Round-trip tests for the pretty-printer: printing an AST and parsing the output again
+// has to give back the same AST.
+
+extern crate kaleidoscope;
+
+use kaleidoscope::lexer::tokenize;
+use kaleidoscope::parser::*;
+use kaleidoscope::printer::pretty;
+
+// xorshift; deterministic so that a failing case can be reproduced from its seed
+struct Rng(u64);
+
+impl Rng {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+
+  fn below(&mut self, n: u64) -> u64 {
+    self.next() % n
+  }
+
+  fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+    items[self.below(items.len() as u64) as usize]
+  }
+}
+
+const VARIABLES: [&'static str; 4] = ["x", "y", "value", "n2"];
+const FUNCTIONS: [&'static str; 2] = ["f", "g"];
+const BINARY_OPS: [&'static str; 6] = ["+", "-", "*", "<", "|", "="];
+const UNARY_OPS: [&'static str; 2] = ["!", "-"];
+
+fn settings() -> ParserSettings {
+  let mut settings = default_parser_settings();
+  parse(tokenize("def binary| 5 (a b) a;").as_slice(), &[], &mut settings);
+  settings
+}
+
+fn gen_expr(rng: &mut Rng, depth: u32) -> Expression {
+  let choice = if depth == 0 { rng.below(2) } else { rng.below(7) };
+  match choice {
+    0 => LiteralExpr(rng.below(1000) as f64 / 8.0),
+    1 => VariableExpr(rng.pick(&VARIABLES).to_string()),
+    2 | 3 => BinaryExpr(rng.pick(&BINARY_OPS).to_string(),
+                        Box::new(gen_expr(rng, depth - 1)),
+                        Box::new(gen_expr(rng, depth - 1))),
+    4 => UnaryExpr(rng.pick(&UNARY_OPS).to_string(), Box::new(gen_expr(rng, depth - 1))),
+    5 => ConditionalExpr{cond_expr: Box::new(gen_expr(rng, depth - 1)),
+                         then_expr: Box::new(gen_expr(rng, depth - 1)),
+                         else_expr: Box::new(gen_expr(rng, depth - 1))},
+    _ => if rng.below(2) == 0 {
+      let args = (0..rng.below(3)).map(|_| gen_expr(rng, depth - 1)).collect();
+      CallExpr(rng.pick(&FUNCTIONS).to_string(), args)
+    } else {
+      LoopExpr{var_name: rng.pick(&VARIABLES).to_string(),
+               start_expr: Box::new(gen_expr(rng, depth - 1)),
+               end_expr: Box::new(gen_expr(rng, depth - 1)),
+               step_expr: Box::new(gen_expr(rng, depth - 1)),
+               body_expr: Box::new(gen_expr(rng, depth - 1))}
+    }
+  }
+}
+
+fn gen_prototype(rng: &mut Rng) -> Prototype {
+  match rng.below(3) {
+    0 => Prototype{name: "unary!".to_string(), ftype: UnaryOp("!".to_string()), args: vec!["v".to_string()]},
+    1 => Prototype{name: "binary|".to_string(), ftype: BinaryOp("|".to_string(), 5),
+                   args: vec!["a".to_string(), "b".to_string()]},
+    _ => {
+      let args = (0..rng.below(3)).map(|i| VARIABLES[i as usize].to_string()).collect();
+      Prototype{name: rng.pick(&FUNCTIONS).to_string(), ftype: Normal, args: args}
+    }
+  }
+}
+
+fn gen_node(rng: &mut Rng) -> ASTNode {
+  match rng.below(3) {
+    0 => ExternNode(gen_prototype(rng)),
+    1 => FunctionNode(Function{prototype: gen_prototype(rng), expression: gen_expr(rng, 4)}),
+    _ => FunctionNode(Function{prototype: Prototype{name: "".to_string(), ftype: Normal, args: vec![]},
+                               expression: gen_expr(rng, 4)})
+  }
+}
+
+fn round_trip(node: &ASTNode) -> Vec<ASTNode> {
+  let settings = settings();
+  let source = format!("{}", pretty(node, &settings));
+  let (ast, rest, errors) = parse(tokenize(&source).as_slice(), &[], &mut settings.clone());
+  assert!(errors.is_empty(), "errors {:?} when parsing {}", errors, source);
+  assert!(rest.is_empty(), "incomplete input {}", source);
+  ast
+}
+
+#[test]
+fn round_trip_random_asts() {
+  for seed in 1..1001 {
+    let mut rng = Rng((seed as u64).wrapping_mul(0x9E3779B97F4A7C15));
+    let node = gen_node(&mut rng);
+    assert_eq!(round_trip(&node), vec![node.clone()]);
+  }
+}
+
+#[test]
+fn minimal_parentheses() {
+  let settings = settings();
+  let source = "(1 + x) * (y - (2 - 3)) < f(if x then 1 else 2, -(x | y)) | !g();";
+  let (ast, _, _) = parse(tokenize(source).as_slice(), &[], &mut settings.clone());
+  assert_eq!(format!("{}", pretty(&ast[0], &settings)), source);
+
+  let source = "def binary| 5 (a, b) a - b - (a - b);";
+  let (ast, _, _) = parse(tokenize(source).as_slice(), &[], &mut settings.clone());
+  assert_eq!(format!("{}", pretty(&ast[0], &settings)), source);
+}
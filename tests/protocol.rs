@@ -0,0 +1,80 @@
+extern crate kaleidoscope;
+extern crate rustc_serialize;
+
+use rustc_serialize::json::Json;
+
+use kaleidoscope::driver::{Options, IR};
+use kaleidoscope::protocol::Server;
+
+fn request(server: &mut Server, line: &str) -> Json {
+  Json::from_str(&server.handle_line(line)).unwrap()
+}
+
+fn at<'a>(json: &'a Json, path: &[&str]) -> &'a Json {
+  json.find_path(path).unwrap()
+}
+
+#[test]
+fn evaluates_definitions() {
+  let mut server = Server::new(IR, &Options::default());
+  let response = request(&mut server, r#"{"id": 7, "method": "eval", "code": "def f(x) x + 1; f(2);", "ir": true}"#);
+  assert_eq!(at(&response, &["id"]), &Json::U64(7));
+  assert_eq!(at(&response, &["status"]).as_string(), Some("ok"));
+
+  let results = at(&response, &["results"]).as_array().unwrap();
+  assert_eq!(results.len(), 2);
+  assert_eq!(at(&results[0], &["kind"]).as_string(), Some("definition"));
+  assert_eq!(at(&results[0], &["name"]).as_string(), Some("f"));
+  assert!(at(&results[0], &["ir"]).as_string().unwrap().contains("define double @f"));
+  assert_eq!(at(&results[1], &["kind"]).as_string(), Some("expression"));
+  assert!(at(&response, &["diagnostics"]).as_array().unwrap().is_empty());
+}
+
+#[test]
+fn reports_diagnostics_with_spans() {
+  let mut server = Server::new(IR, &Options::default());
+  let response = request(&mut server, r#"{"method": "eval", "code": "def f(x) x;\ndef 1(x) x;\ng(1);"}"#);
+  let diagnostics = at(&response, &["diagnostics"]).as_array().unwrap();
+  assert_eq!(diagnostics.len(), 2);
+
+  assert_eq!(at(&diagnostics[0], &["phase"]).as_string(), Some("parse"));
+  assert_eq!(at(&diagnostics[0], &["span", "start", "line"]), &Json::U64(2));
+  assert_eq!(at(&diagnostics[0], &["span", "start", "column"]), &Json::U64(5));
+  assert_eq!(at(&diagnostics[0], &["span", "end", "column"]), &Json::U64(6));
+
+  // the unknown function g, found before compiling
+  assert_eq!(at(&diagnostics[1], &["phase"]).as_string(), Some("semantic"));
+  assert_eq!(at(&diagnostics[1], &["message"]).as_string(), Some("unknown function g"));
+  assert_eq!(at(&diagnostics[1], &["span", "start", "line"]), &Json::U64(3));
+  assert_eq!(at(&diagnostics[1], &["span", "start", "column"]), &Json::U64(1));
+  assert_eq!(at(&diagnostics[1], &["span", "end", "column"]), &Json::U64(2));
+}
+
+#[test]
+fn completes_and_inspects() {
+  let mut server = Server::new(IR, &Options::default());
+  request(&mut server, r#"{"method": "eval", "code": "def square(x) x * x;"}"#);
+
+  let response = request(&mut server, r#"{"method": "complete", "code": "1 + sq", "cursor": 6}"#);
+  let matches = at(&response, &["matches"]).as_array().unwrap();
+  assert_eq!(matches.iter().map(|word| word.as_string().unwrap()).collect::<Vec<_>>(), vec!["sqrt", "square"]);
+  assert_eq!(at(&response, &["start"]), &Json::U64(4));
+
+  let response = request(&mut server, r#"{"method": "inspect", "name": "square"}"#);
+  assert_eq!(at(&response, &["found"]), &Json::Boolean(true));
+  assert_eq!(at(&response, &["signature"]).as_string(), Some("def square(x)"));
+
+  request(&mut server, r#"{"method": "reset"}"#);
+  let response = request(&mut server, r#"{"method": "inspect", "name": "square"}"#);
+  assert_eq!(at(&response, &["found"]), &Json::Boolean(false));
+}
+
+#[test]
+fn rejects_bad_requests() {
+  let mut server = Server::new(IR, &Options::default());
+  let response = request(&mut server, r#"{"id": "a", "method": "frobnicate"}"#);
+  assert_eq!(at(&response, &["id"]).as_string(), Some("a"));
+  assert_eq!(at(&response, &["status"]).as_string(), Some("error"));
+  let response = request(&mut server, "not json");
+  assert_eq!(at(&response, &["status"]).as_string(), Some("error"));
+}
@@ -0,0 +1,33 @@
+extern crate kaleidoscope;
+
+use std::env;
+use std::fs;
+
+use kaleidoscope::driver::{Evaluation, Exec, Options, Returned, Session};
+
+fn evaluate(session: &mut Session, source: &str) -> Vec<Evaluation> {
+  let tokens = session.tokenize(source);
+  let (ast, _, errors) = session.parse(tokens.as_slice());
+  assert!(errors.is_empty());
+  session.evaluate(ast.as_slice(), Some(source))
+}
+
+#[test]
+fn skips_definitions_the_session_already_has() {
+  let path = env::temp_dir().join("kaleidoscope-restore-test.ks");
+  let path = path.to_str().unwrap();
+
+  let mut session = Session::new(Exec, &Options::default());
+  evaluate(&mut session, "def f(x) x + 1; def g(x) f(x) * 2;");
+  assert_eq!(session.save(path), Ok(2));
+
+  assert_eq!(session.restore(path), Ok(0));
+  assert_eq!(evaluate(&mut session, "g(1);"), vec![Returned(4.0)]);
+
+  let mut other = Session::new(Exec, &Options::default());
+  evaluate(&mut other, "def f(x) x + 1;");
+  assert_eq!(other.restore(path), Ok(1));
+  assert_eq!(evaluate(&mut other, "g(2);"), vec![Returned(6.0)]);
+
+  fs::remove_file(path).unwrap();
+}
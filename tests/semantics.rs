@@ -0,0 +1,51 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::lexer::*;
+use kaleidoscope::parser::*;
+use kaleidoscope::semantics::*;
+
+fn messages(source: &str, symbols: &SymbolTable) -> Vec<String> {
+  // `!` has a precedence, but its function is never known to the checks
+  let mut settings = default_parser_settings();
+  parse(tokenize("def binary! 10 (a b) a;").as_slice(), &[], &mut settings);
+  let (ast, _, errors) = parse(tokenize(source).as_slice(), &[], &mut settings);
+  assert!(errors.is_empty());
+  check(ast.as_slice(), symbols).iter().map(|error| error.message.clone()).collect()
+}
+
+#[test]
+fn finds_all_errors() {
+  assert_eq!(messages("def f(x) y + g(x); 1 ! 2; sin(1, 2);", &SymbolTable::new()),
+             vec!["unknown variable y", "unknown function g", "unknown binary operator !", "sin takes 1 arguments, not 2"]);
+}
+
+#[test]
+fn knows_earlier_definitions() {
+  let mut settings = default_parser_settings();
+  let (prelude, _, _) = parse(tokenize("def twice(x) x * 2; extern putchard(c);").as_slice(), &[], &mut settings);
+  let mut symbols = SymbolTable::new();
+  for node in prelude.iter() {
+    symbols.define_node(node);
+  }
+  assert_eq!(symbols.arity("twice"), Some(1));
+
+  assert!(messages("def f(n) if n < 1 then 0 else f(twice(n) - 1); for i = 1, i < 3 in putchard(i);", &symbols).is_empty());
+  assert_eq!(messages("def twice(a b) a; twice(1, 2, 3);", &symbols), vec!["twice was declared with 1 arguments, not 2",
+                                                                          "twice takes 1 arguments, not 3"]);
+  // the loop variable is only known in the loop
+  assert_eq!(messages("for i = i, i < 3 in i; i;", &symbols), vec!["unknown variable i", "unknown variable i"]);
+}
+
+#[test]
+fn locates_errors() {
+  let source = "def f(x) x;\nf(x) + f(1, 2);";
+  let (tokens, spans): (Vec<_>, Vec<_>) = tokenize_with_spans(source).into_iter().unzip();
+  let (nodes, _, _) = parse_with_positions(tokens.as_slice(), &mut default_parser_settings());
+  let errors = check_located(nodes.as_slice(), tokens.as_slice(), spans.as_slice(), &SymbolTable::new());
+
+  assert_eq!(errors.len(), 2);
+  assert_eq!(errors[0].node, 1);
+  assert_eq!(format!("{}", errors[0]), "2:3: unknown variable x");
+  // the second call of f, not the first one
+  assert_eq!(format!("{}", errors[1]), "2:8: f takes 1 arguments, not 2");
+}
@@ -0,0 +1,54 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::lexer::tokenize;
+use kaleidoscope::parser::*;
+use kaleidoscope::serialization::{serialize, deserialize, JSON, SExpr};
+
+const SOURCE: &'static str = "extern printd(x);
+def binary| 5 (a b) if a then 1 else b;
+def f(n) for i = 0, i < n, 2 in printd(!i);
+f(1.5) + 2;";
+
+fn parse_source() -> Vec<ASTNode> {
+  let (ast, _, errors) = parse(tokenize(SOURCE).as_slice(), &[], &mut default_parser_settings());
+  assert!(errors.is_empty());
+  ast
+}
+
+#[test]
+fn sexpr_output() {
+  assert_eq!(serialize(parse_source().as_slice(), SExpr),
+             "(extern (function printd (x)))
+(def (binary \"|\" 5 (a b)) (if (var a) (lit 1) (var b)))
+(def (function f (n)) (for i (lit 0) (binary \"<\" (var i) (var n)) (lit 2) (call printd (unary \"!\" (var i)))))
+(expr (binary \"+\" (call f (lit 1.5)) (lit 2)))
+");
+}
+
+#[test]
+fn round_trip() {
+  let ast = parse_source();
+  assert_eq!(deserialize(&serialize(ast.as_slice(), JSON), JSON), Ok(ast.clone()));
+  assert_eq!(deserialize(&serialize(ast.as_slice(), SExpr), SExpr), Ok(ast));
+}
+
+#[test]
+fn malformed_input() {
+  assert!(deserialize("(def (function f (x)) (var x)", SExpr).is_err());
+  assert!(deserialize("(expr (lit one))", SExpr).is_err());
+  assert!(deserialize("[{\"kind\": \"extern\"}]", JSON).is_err());
+}
+
+#[test]
+fn rejects_prototypes_the_parser_rejects() {
+  let prototype = |name: &str, precedence: &str| format!(
+    "[{{\"kind\": \"extern\", \"prototype\": {{\"kind\": \"prototype\", \"name\": \"{}\", \"args\": [\"a\", \"b\"], \
+     \"type\": {{\"kind\": \"binary\", \"operator\": \"|\", \"precedence\": {}}}}}}}]", name, precedence);
+  let (ast, _, _) = parse(tokenize("extern binary| 5 (a b);").as_slice(), &[], &mut default_parser_settings());
+  assert_eq!(deserialize(&prototype("binary|", "5"), JSON), Ok(ast));
+  assert!(deserialize(&prototype("f", "5"), JSON).is_err());
+  assert!(deserialize(&prototype("binary|", "-1"), JSON).is_err());
+  assert!(deserialize("(extern (binary \"|\" -1 (a b)))", SExpr).is_err());
+  assert!(deserialize("(def (unary \"!\" (a b)) (var a))", SExpr).is_err());
+  assert!(deserialize("(extern (binary \"|\" 5 (a)))", SExpr).is_err());
+}
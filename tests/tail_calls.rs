@@ -0,0 +1,42 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::driver::{run_stage, IR};
+use kaleidoscope::lexer::tokenize;
+use kaleidoscope::parser::*;
+use kaleidoscope::tail_calls::non_tail_recursive_calls;
+
+fn reports(source: &str) -> Vec<String> {
+  let (ast, _, errors) = parse(tokenize(source).as_slice(), &[], &mut default_parser_settings());
+  assert!(errors.is_empty());
+  match ast[0] {
+    FunctionNode(ref function) => non_tail_recursive_calls(function),
+    _ => panic!("expected a definition")
+  }
+}
+
+#[test]
+fn tail_recursion() {
+  assert!(reports("def fact(n acc) if n < 2 then acc else fact(n - 1, acc * n);").is_empty());
+  assert!(reports("def count(n) if n < 1 then 0 else if 1 then count(n - 1) else 1;").is_empty());
+}
+
+#[test]
+fn non_tail_recursion() {
+  assert_eq!(reports("def fib(n) if n < 3 then 1 else fib(n - 1) + fib(n - 2);"),
+             vec!["recursive call `fib(n - 1)` in fib(n) is not in tail position",
+                  "recursive call `fib(n - 2)` in fib(n) is not in tail position"]);
+  assert_eq!(reports("def f(n) f(f(n));"),
+             vec!["recursive call `f(n)` in f(n) is not in tail position"]);
+}
+
+#[test]
+fn builder_marks_only_tail_calls() {
+  let ir = run_stage(IR, "def fact(n acc) if n < 2 then acc else fact(n - 1, acc * n);\n\
+                          def fib(n) if n < 3 then 1 else fib(n - 1) + fib(n - 2);").unwrap();
+  let calls = |name: &str| ir.lines().filter(|line| line.contains(&format!("call double @{}(", name))).map(|line| line.to_string())
+    .collect::<Vec<_>>();
+  assert_eq!(calls("fact").len(), 1);
+  assert!(calls("fact").iter().all(|line| line.contains("tail call")));
+  assert_eq!(calls("fib").len(), 2);
+  assert!(calls("fib").iter().all(|line| !line.contains("tail call")));
+}
@@ -0,0 +1,15 @@
+extern crate kaleidoscope;
+
+use kaleidoscope::timing::Stats;
+
+#[test]
+fn merges_phases() {
+  let mut stats = Stats { parse_ms: 1.0, instructions_before: 10, instructions_after: 4, ..Stats::default() };
+  stats.merge(&Stats { parse_ms: 0.5, execute_ms: 2.0, modules: 3, compiled_modules: 2, ..Stats::default() });
+  stats.merge(&Stats { codegen_ms: 0.25, ..Stats::default() });
+
+  assert_eq!(stats.parse_ms, 1.5);
+  assert_eq!(stats.total_ms(), 3.75);
+  assert_eq!((stats.modules, stats.compiled_modules), (3, 2));
+  assert!(stats.report().contains("instructions: 10 before optimization, 4 after"));
+}